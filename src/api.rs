@@ -0,0 +1,527 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use axum::extract::{Path, State};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use wireguard_uapi::{Backend, Device, InterfaceName, KeyPair};
+
+use crate::clock::Clock;
+use crate::conf::{export, render, NodeOpt};
+use crate::model::validate;
+use crate::model::Node;
+use crate::standard::DEFAULT_PEER_PERSISTENT_KEEPALIVE;
+
+#[derive(Clone)]
+pub(crate) struct ApiState {
+    store: Arc<Mutex<Box<dyn NodeOpt>>>,
+    token: Arc<String>,
+    // time the expired peers are left out of the configurations at
+    clock: Arc<dyn Clock>,
+    backend: Backend,
+}
+
+impl ApiState {
+    // an empty token would authorize any request with an empty bearer token
+    pub fn new(
+        store: Box<dyn NodeOpt>,
+        token: String,
+        clock: Arc<dyn Clock>,
+        backend: Backend,
+    ) -> anyhow::Result<Self> {
+        if token.trim().is_empty() {
+            anyhow::bail!("the API token must not be empty")
+        }
+        Ok(Self {
+            store: Arc::new(Mutex::new(store)),
+            token: Arc::new(token),
+            clock,
+            backend,
+        })
+    }
+}
+
+pub(crate) struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn not_found(e: anyhow::Error) -> Self {
+        Self(StatusCode::NOT_FOUND, e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(ErrorBody { error: self.1 })).into_response()
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Deserialize)]
+pub(crate) struct CreateNode {
+    name: String,
+    relay: Option<String>,
+    address: Option<Vec<IpNet>>,
+    #[serde(default)]
+    allowed_ips: Vec<IpNet>,
+    endpoint_allowed_ips: Option<Vec<IpNet>>,
+    persistent_keepalive: Option<u16>,
+    mtu: Option<u16>,
+    // subnets reachable behind the node
+    routes: Option<Vec<IpNet>>,
+}
+
+#[derive(Serialize)]
+struct PeerStatus {
+    name: Option<String>,
+    public_key: String,
+    endpoint: Option<SocketAddr>,
+    // seconds since the unix epoch
+    latest_handshake: Option<u64>,
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct InterfaceStatus {
+    interface: String,
+    up: bool,
+    listen_port: Option<u16>,
+    peers: Vec<PeerStatus>,
+}
+
+pub(crate) fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/nodes", get(list_nodes).post(create_node))
+        .route("/nodes/:name", get(get_node).delete(delete_node))
+        .route("/nodes/:name/config", get(node_config))
+        .route("/nodes/:name/qrcode.png", get(node_qrcode))
+        .route("/status", get(status))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state)
+}
+
+pub(crate) async fn serve(listen: SocketAddr, state: ApiState) -> anyhow::Result<()> {
+    log::info!("management api listening on http://{}", listen);
+    axum::Server::bind(&listen)
+        .serve(router(state).into_make_service())
+        .await?;
+    Ok(())
+}
+
+// every request needs `Authorization: Bearer <token>`
+async fn authorize<B>(
+    State(state): State<ApiState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match token {
+        Some(token) if constant_time_eq(token.as_bytes(), state.token.as_bytes()) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// private keys never leave the api, except inside a rendered configuration
+fn without_private_key(mut node: Node) -> Node {
+    node.with_private_key(None);
+    node.previous_private_key = None;
+    node
+}
+
+async fn list_nodes(State(state): State<ApiState>) -> ApiResult<Json<Vec<Node>>> {
+    let node_list = state.store.lock().await.list().await?;
+    Ok(Json(
+        node_list.into_iter().map(without_private_key).collect(),
+    ))
+}
+
+async fn get_node(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> ApiResult<Json<Node>> {
+    let node = state
+        .store
+        .lock()
+        .await
+        .get_by_name(&name)
+        .await
+        .map_err(ApiError::not_found)?;
+    Ok(Json(without_private_key(node)))
+}
+
+async fn create_node(
+    State(state): State<ApiState>,
+    Json(create): Json<CreateNode>,
+) -> ApiResult<(StatusCode, Json<Node>)> {
+    // held until the node is stored, so the checks below can not go stale
+    let mut store = state.store.lock().await;
+    let node_list = store.list().await?;
+    let relay = match create.relay {
+        Some(relay) => match node_list.iter().find(|n| n.relay && n.name().eq(&relay)) {
+            Some(relay) => relay.name().to_string(),
+            None => {
+                return Err(ApiError(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("there is no peer relay node named '{}'", relay),
+                ))
+            }
+        },
+        None => match node_list.iter().find(|n| n.relay) {
+            Some(relay) => relay.name().to_string(),
+            None => {
+                return Err(ApiError(
+                    StatusCode::PRECONDITION_FAILED,
+                    "please add peer relay node first".to_string(),
+                ))
+            }
+        },
+    };
+    if node_list.iter().any(|n| n.name().eq(&create.name)) {
+        return Err(ApiError(
+            StatusCode::CONFLICT,
+            format!("Duplicate node {} name", create.name),
+        ));
+    }
+
+    let network = store.network().await?;
+    let address = match create.address {
+        Some(address) => address,
+        None => network.allocate(&node_list)?,
+    };
+    let endpoint_allowed_ips = match create.endpoint_allowed_ips {
+        Some(endpoint_allowed_ips) => endpoint_allowed_ips,
+        None => network.prefixes(),
+    };
+    let persistent_keepalive = match create.persistent_keepalive {
+        Some(persistent_keepalive) => persistent_keepalive,
+        None => DEFAULT_PEER_PERSISTENT_KEEPALIVE
+            .parse()
+            .map_err(anyhow::Error::from)?,
+    };
+    let key_pair = KeyPair::generate();
+    let mut node = Node::default();
+    node.with_relay(false)
+        .with_name(Some(create.name))
+        .with_parent(Some(relay))
+        .with_address(Some(address))
+        .with_allowed_ips(Some(create.allowed_ips))
+        .with_endpoint_allowed_ips(Some(endpoint_allowed_ips))
+        .with_persistent_keepalive(Some(persistent_keepalive))
+        .with_mtu(create.mtu)
+        .with_routes(create.routes)
+        .with_public_key(Some(key_pair.public.to_base64()))
+        .with_private_key(Some(key_pair.private.to_base64()));
+    let mut new_list = node_list.clone();
+    new_list.push(node.clone());
+    if let Err(e) = validate::check(&network, &new_list, node.name()) {
+        return Err(ApiError(StatusCode::CONFLICT, e.to_string()));
+    }
+    store.push(node.clone()).await?;
+    Ok((StatusCode::CREATED, Json(without_private_key(node))))
+}
+
+async fn delete_node(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> ApiResult<StatusCode> {
+    state
+        .store
+        .lock()
+        .await
+        .remove_by_name(&name)
+        .await
+        .map_err(ApiError::not_found)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn rendered_config(state: &ApiState, name: &str) -> ApiResult<String> {
+    let mut store = state.store.lock().await;
+    let network = store.network().await?;
+    let node_list = store.list().await?;
+    if !node_list.iter().any(|n| n.name().eq(name)) {
+        return Err(ApiError::not_found(anyhow::anyhow!(
+            "node does not exist: {}",
+            name
+        )));
+    }
+    Ok(render::node_config_at(
+        &network,
+        name,
+        &node_list,
+        state.clock.now(),
+    )?)
+}
+
+async fn node_config(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> ApiResult<Response> {
+    let config = rendered_config(&state, &name).await?;
+    let disposition = format!("attachment; filename=\"{}.conf\"", name);
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        config,
+    )
+        .into_response())
+}
+
+async fn node_qrcode(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> ApiResult<Response> {
+    let config = rendered_config(&state, &name).await?;
+    let png = export::qr_png(&config)?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+async fn status(State(state): State<ApiState>) -> ApiResult<Json<InterfaceStatus>> {
+    let (network, node_list) = {
+        let mut store = state.store.lock().await;
+        (store.network().await?, store.list().await?)
+    };
+    let mut status = InterfaceStatus {
+        interface: network.interface.clone(),
+        up: false,
+        listen_port: None,
+        peers: vec![],
+    };
+    let interface = match network.interface.parse::<InterfaceName>() {
+        Ok(interface) => interface,
+        Err(_) => return Ok(Json(status)),
+    };
+    let backend = state.backend;
+    let device = tokio::task::spawn_blocking(move || Device::get(&interface, backend))
+        .await
+        .map_err(anyhow::Error::from)?;
+    if let Ok(device) = device {
+        status.up = true;
+        status.listen_port = device.listen_port;
+        status.peers = device
+            .peers
+            .into_iter()
+            .map(|peer| {
+                let public_key = peer.config.public_key.to_base64();
+                PeerStatus {
+                    name: node_list
+                        .iter()
+                        .find(|n| n.public_key.as_deref() == Some(public_key.as_str()))
+                        .map(|n| n.name().to_string()),
+                    public_key,
+                    endpoint: peer.config.endpoint,
+                    latest_handshake: peer
+                        .stats
+                        .last_handshake_time
+                        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs())
+                        .filter(|secs| *secs > 0),
+                    rx_bytes: peer.stats.rx_bytes,
+                    tx_bytes: peer.stats.tx_bytes,
+                }
+            })
+            .collect();
+    }
+    Ok(Json(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::conf::sqlite::SqliteStore;
+    use crate::model::test_util::{self, home};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "secret";
+
+    async fn store() -> SqliteStore {
+        let db = sea_orm::Database::connect("sqlite::memory:").await.unwrap();
+        crate::db::initialize_table(&db).await.unwrap();
+
+        let key_pair = KeyPair::generate();
+        let mut relay = test_util::node("wg0", true, "10.66.66.1/24");
+        relay
+            .with_listen_port(Some(51820))
+            .with_endpoint(Some("vpn.example.com:51820".parse().unwrap()))
+            .with_public_key(Some(key_pair.public.to_base64()))
+            .with_private_key(Some(key_pair.private.to_base64()));
+        let mut store = SqliteStore::new(db, "home");
+        let mut network = home();
+        network.dns = Some(vec!["10.66.66.1".parse().unwrap()]);
+        store.push_network(network).await.unwrap();
+        store.push(relay).await.unwrap();
+        store
+    }
+
+    fn router_of(store: SqliteStore) -> Router {
+        let clock = Arc::new(FixedClock(0));
+        router(
+            ApiState::new(
+                Box::new(store),
+                TOKEN.to_string(),
+                clock,
+                Backend::Userspace,
+            )
+            .unwrap(),
+        )
+    }
+
+    async fn app() -> Router {
+        router_of(store().await)
+    }
+
+    fn request(method: &str, uri: &str, body: Option<&str>) -> Request<Body> {
+        let builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN));
+        match body {
+            Some(body) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        }
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Vec<u8>) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_requires_bearer_token() {
+        let app = app().await;
+        let unauthorized = Request::builder()
+            .uri("/nodes")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&app, unauthorized).await.0, StatusCode::UNAUTHORIZED);
+
+        let wrong = Request::builder()
+            .uri("/nodes")
+            .header(header::AUTHORIZATION, "Bearer wrong")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&app, wrong).await.0, StatusCode::UNAUTHORIZED);
+
+        let db = sea_orm::Database::connect("sqlite::memory:").await.unwrap();
+        for token in ["", "  "] {
+            let store = Box::new(SqliteStore::new(db.clone(), "home"));
+            let clock = Arc::new(FixedClock(0));
+            assert!(ApiState::new(store, token.to_string(), clock, Backend::Userspace).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_node_crud_and_config() {
+        let app = app().await;
+        let create = r#"{"name": "laptop"}"#;
+
+        let (status, _) = send(&app, request("POST", "/nodes", Some(create))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send(&app, request("POST", "/nodes", Some(create))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, body) = send(&app, request("GET", "/nodes", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let node_list: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(node_list.len(), 2);
+        assert!(node_list.iter().all(|n| n["private_key"].is_null()));
+
+        let (status, body) = send(&app, request("GET", "/nodes/laptop/config", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let config = String::from_utf8(body).unwrap();
+        // allocated from the network
+        assert!(config.contains("Address = 10.66.66.2/24"));
+        assert!(config.contains("DNS = 10.66.66.1"));
+        assert!(config.contains("AllowedIPs = 10.66.66.0/24"));
+        assert!(config.contains("Endpoint = vpn.example.com:51820"));
+
+        let (status, body) = send(&app, request("GET", "/nodes/laptop/qrcode.png", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.starts_with(b"\x89PNG"));
+
+        let (status, _) = send(&app, request("DELETE", "/nodes/laptop", None)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, request("DELETE", "/nodes/laptop", None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, request("GET", "/nodes/laptop/config", None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_rotated_keys_are_hidden() {
+        let mut store = store().await;
+        let mut relay = store.get_by_name("wg0").await.unwrap();
+        let old_private_key = relay.private_key.clone().unwrap();
+        let key_pair = KeyPair::generate();
+        relay.rotate_key(
+            key_pair.public.to_base64(),
+            key_pair.private.to_base64(),
+            i64::MAX,
+        );
+        store.set_keys(&relay).await.unwrap();
+        let app = router_of(store);
+
+        for uri in ["/nodes", "/nodes/wg0"] {
+            let (status, body) = send(&app, request("GET", uri, None)).await;
+            assert_eq!(status, StatusCode::OK);
+            let json = String::from_utf8(body).unwrap();
+            assert!(!json.contains(&old_private_key));
+            assert!(!json.contains(&key_pair.private.to_base64()));
+            let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let relay = if value.is_array() { &value[0] } else { &value };
+            assert!(relay["previous_public_key"].is_string());
+            assert!(relay["private_key"].is_null());
+            assert!(relay["previous_private_key"].is_null());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_of_missing_interface() {
+        let app = app().await;
+        let (status, body) = send(&app, request("GET", "/status", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["interface"], "wg0");
+        assert_eq!(status["up"], false);
+    }
+}
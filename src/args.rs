@@ -1,7 +1,18 @@
+use crate::conf::export::Format;
+use crate::conf::listing::{Output, Role, SortKey};
+use crate::conf::Store;
+use crate::model::acl::Rule;
+use crate::model::audit::Action;
+use crate::model::endpoint::Endpoint;
+use crate::model::hook::Preset;
+use crate::model::topology::Topology;
 use crate::parser;
+#[cfg(feature = "api")]
+use crate::standard::DEFAULT_API_LISTEN;
 use crate::standard::{
-    DEFAULT_INTERFACE_ADDRESS, DEFAULT_INTERFACE_LISTEN_PORT, DEFAULT_MTU,
-    DEFAULT_PEER_ENDPOINT_ALLOWED_IPS, DEFAULT_PEER_PERSISTENT_KEEPALIVE,
+    DEFAULT_HOOK_TIMEOUT, DEFAULT_INTERFACE_ADDRESS, DEFAULT_INTERFACE_LISTEN_PORT,
+    DEFAULT_KEY_GRACE_PERIOD, DEFAULT_MTU, DEFAULT_NETWORK, DEFAULT_PEER_PERSISTENT_KEEPALIVE,
+    DEFAULT_RERESOLVE_INTERVAL, DEFAULT_SYNC_INTERVAL,
 };
 use clap::{Args, Subcommand};
 use ipnet::IpNet;
@@ -16,9 +27,21 @@ pub(crate) struct Opt {
     pub debug: bool,
 
     /// Configuration directory
-    #[arg(long, short, default_value = "/etc/wireguard/wgsdc")]
+    #[arg(global = true, long, short, default_value = "/etc/wireguard/wgsdc")]
     pub dir: PathBuf,
 
+    /// Network the subcommand works on
+    #[arg(global = true, long, short = 'N', default_value = DEFAULT_NETWORK)]
+    pub network: String,
+
+    /// Node storage backend
+    #[arg(global = true, long, value_enum, default_value_t = Store::Sqlite)]
+    pub store: Store,
+
+    /// File holding the key of an encrypted store
+    #[arg(global = true, long, env = "WGSDC_KEY_FILE")]
+    pub key_file: Option<PathBuf>,
+
     /// Subcommands
     #[command(subcommand)]
     pub commands: Option<SubCommands>,
@@ -30,27 +53,121 @@ pub(crate) enum SubCommands {
     #[command(arg_required_else_help = true)]
     New(NewPeerRelayNetwork),
 
+    /// Add another WireGuard Peer Relay to the network
+    #[command(arg_required_else_help = true)]
+    AddRelay(AddRelay),
+
     /// Add WireGuard Peer
     #[command(arg_required_else_help = true)]
     AddPeer(AddPeer),
 
+    /// Change the settings of an existing peer in place
+    #[command(arg_required_else_help = true)]
+    EditPeer(EditPeer),
+
     /// Revoke WireGuard existing peer
-    RevokePeer,
+    RevokePeer(RevokePeer),
 
     /// Print WireGuard configuration
     PrintPeer,
 
-    Up,
+    /// List the nodes of the network
+    List(List),
 
-    Down,
+    /// Check the network configuration for conflicts
+    Validate,
+
+    /// Show the audit trail of the node changes
+    Log(Log),
+
+    /// Export a peer's configuration to a file
+    Export(Export),
+
+    /// Rotate the key pair of a node, or roll the last rotation back
+    #[command(arg_required_else_help = true)]
+    RotateKey(RotateKey),
+
+    /// Apply a declarative YAML spec of a network and its nodes
+    #[command(arg_required_else_help = true)]
+    Apply(Apply),
+
+    /// Back up all networks and nodes to a JSON or YAML archive
+    #[command(arg_required_else_help = true)]
+    Backup(Backup),
+
+    /// Replace all networks and nodes with those of an archive
+    #[command(arg_required_else_help = true)]
+    Restore(Restore),
+
+    /// Unlock the encrypted store, prints the session of the later commands
+    Unlock,
+
+    /// Encrypt all private keys of the store with a new passphrase or key file
+    RekeyStore(RekeyStore),
+
+    /// Load the nftables forwarding and NAT ruleset of the relay interface
+    Firewall(Firewall),
+
+    /// Manage the access control rules the relay enforces between its peers
+    Acl(Acl),
+
+    /// Bring the relay interface up, running its PreUp and PostUp hooks
+    Up(UpDown),
+
+    /// Bring the relay interface down, running its PreDown and PostDown hooks
+    Down(UpDown),
 
     Status,
+
+    /// Keep re-resolving the domain endpoints of stale peers
+    Reresolve(Reresolve),
+
+    /// Run the relay interface and keep it synchronized with the database
+    Daemon(Daemon),
+
+    /// Serve the HTTP management API
+    #[cfg(feature = "api")]
+    Serve(Serve),
 }
 
 #[allow(unused_qualifications)]
 #[derive(Args)]
 pub(crate) struct NewPeerRelayNetwork {
-    /// Interface's name
+    #[command(flatten)]
+    pub relay: Relay,
+
+    /// Interface's WireGuard address
+    // issue https://github.com/clap-rs/clap/issues/4481#issuecomment-1314475143
+    #[arg(long, short, default_value = DEFAULT_INTERFACE_ADDRESS, value_parser = parser::parser_interface_address)]
+    pub address: std::vec::Vec<IpNet>,
+
+    /// Add a random unique local IPv6 range to the network, dual-stack with the IPv4 address
+    #[arg(long)]
+    pub ula: bool,
+
+    /// DNS servers of the network's peers
+    #[arg(long, value_parser = parser::parser_dns)]
+    pub dns: Option<std::vec::Vec<std::net::IpAddr>>,
+
+    /// How the network's peers reach each other
+    #[arg(long, value_enum, default_value_t = Topology::Hub)]
+    pub topology: Topology,
+}
+
+#[allow(unused_qualifications)]
+#[derive(Args)]
+pub(crate) struct AddRelay {
+    #[command(flatten)]
+    pub relay: Relay,
+
+    /// Relay's WireGuard address, allocated from the network when omitted
+    #[arg(long, short, value_parser = parser::parser_interface_address)]
+    pub address: Option<std::vec::Vec<IpNet>>,
+}
+
+#[derive(Args)]
+pub(crate) struct Relay {
+    /// Relay's name, also the interface's name of a new network
     #[arg(long, short)]
     pub name: String,
 
@@ -58,11 +175,6 @@ pub(crate) struct NewPeerRelayNetwork {
     #[arg(long, value_name = "HOST", value_parser = parser::parser_host)]
     pub endpoint: String,
 
-    /// Interface's WireGuard address
-    // issue https://github.com/clap-rs/clap/issues/4481#issuecomment-1314475143
-    #[arg(long, short, default_value = DEFAULT_INTERFACE_ADDRESS, value_parser = parser::parser_address_in_range)]
-    pub address: std::vec::Vec<IpNet>,
-
     /// Interface's WireGuard listen port
     #[arg(long, default_value = DEFAULT_INTERFACE_LISTEN_PORT, value_parser = parser::parser_port_in_range)]
     pub listen_port: u16,
@@ -71,7 +183,7 @@ pub(crate) struct NewPeerRelayNetwork {
     #[arg(long, default_value = DEFAULT_MTU, value_parser = parser::parser_mtu)]
     pub mtu: u16,
 
-    /// Interface's WireGuard PostUp command
+    /// Interface's WireGuard PostUp command, {interface}, {address}, {subnet} and {listen_port} are replaced in the hooks
     #[arg(long)]
     pub post_up: Option<String>,
 
@@ -86,6 +198,14 @@ pub(crate) struct NewPeerRelayNetwork {
     /// Interface's WireGuard PreDown command
     #[arg(long)]
     pub pre_down: Option<String>,
+
+    /// Hook preset appended to PostUp/PostDown, may be repeated
+    #[arg(long = "preset", value_name = "PRESET")]
+    pub presets: Vec<Preset>,
+
+    /// Tag of the node, may be repeated
+    #[arg(long = "tag", value_name = "TAG", value_parser = parser::parser_tag)]
+    pub tags: Vec<String>,
 }
 
 #[allow(unused_qualifications)]
@@ -95,9 +215,17 @@ pub(crate) struct AddPeer {
     #[arg(long, short)]
     pub name: String,
 
-    /// Peer's WireGuard address
-    #[arg(long, short, value_parser = parser::parser_address_in_range)]
-    pub address: std::vec::Vec<IpNet>,
+    /// Peer's public endpoint, other peers of a mesh network connect to it directly
+    #[arg(long, value_name = "HOST:PORT")]
+    pub endpoint: Option<Endpoint>,
+
+    /// Peer's primary relay, the network's first relay when omitted
+    #[arg(long)]
+    pub relay: Option<String>,
+
+    /// Peer's WireGuard address, allocated from the network when omitted
+    #[arg(long, short, value_parser = parser::parser_interface_address)]
+    pub address: Option<std::vec::Vec<IpNet>>,
 
     /// Peer's AllowedIPs
     #[arg(long, value_parser = parser::parser_address_in_range)]
@@ -111,11 +239,94 @@ pub(crate) struct AddPeer {
     #[arg(long, default_value = DEFAULT_PEER_PERSISTENT_KEEPALIVE)]
     pub persistent_keepalive: u16,
 
+    /// Subnets behind the peer, routed to it by every other node
+    #[arg(long = "route", value_name = "ROUTES", value_parser = parser::parser_address_in_range)]
+    pub routes: Option<std::vec::Vec<IpNet>>,
+
+    /// Forward traffic between the tunnel and the routes in PostUp/PostDown
+    #[arg(long)]
+    pub forward: bool,
+
+    /// Forward and masquerade traffic leaving through this LAN interface
+    #[arg(long, value_name = "LAN_INTERFACE")]
+    pub nat: Option<String>,
+
+    /// Peer's endpoint allowed ips, the network's address range when omitted
+    #[arg(long, value_name = "ALLOWED_IPS", value_parser = parser::parser_address_in_range)]
+    pub endpoint_allowed_ips: Option<std::vec::Vec<IpNet>>,
+
+    /// Peer's WireGuard PostUp command, {interface}, {address}, {subnet} and {listen_port} are replaced in the hooks
+    #[arg(long)]
+    pub post_up: Option<String>,
+
+    /// Peer's WireGuard PostDown command
+    #[arg(long)]
+    pub post_down: Option<String>,
+
+    /// Peer's WireGuard PreUp command
+    #[arg(long)]
+    pub pre_up: Option<String>,
+
+    /// Peer's WireGuard PreDown command
+    #[arg(long)]
+    pub pre_down: Option<String>,
+
+    /// Hook preset appended to PostUp/PostDown, may be repeated
+    #[arg(long = "preset", value_name = "PRESET")]
+    pub presets: Vec<Preset>,
+
+    /// Tag of the node, may be repeated
+    #[arg(long = "tag", value_name = "TAG", value_parser = parser::parser_tag)]
+    pub tags: Vec<String>,
+
+    /// Revoke the peer after the duration, e.g. 12h, 7d or 2w
+    #[arg(long, value_parser = parser::parser_duration)]
+    pub expires_in: Option<std::time::Duration>,
+
+    /// Revoke the peer at the time, e.g. 2024-01-31 or 2024-01-31 18:00:00
+    #[arg(long, conflicts_with = "expires_in", value_parser = parser::parser_date_time)]
+    pub expires_at: Option<i64>,
+}
+
+#[allow(unused_qualifications)]
+#[derive(Args)]
+pub(crate) struct EditPeer {
+    /// Peer's name
+    pub name: String,
+
+    /// Peer's new name
+    #[arg(long, value_name = "NAME")]
+    pub rename: Option<String>,
+
+    /// Peer's public endpoint
+    #[arg(long, value_name = "HOST:PORT")]
+    pub endpoint: Option<Endpoint>,
+
+    /// Peer's WireGuard address
+    #[arg(long, short, value_parser = parser::parser_interface_address)]
+    pub address: Option<std::vec::Vec<IpNet>>,
+
+    /// Peer's AllowedIPs
+    #[arg(long, value_parser = parser::parser_address_in_range)]
+    pub allowed_ips: Option<std::vec::Vec<IpNet>>,
+
+    /// Peer's MTU
+    #[arg(long, value_parser = parser::parser_mtu)]
+    pub mtu: Option<u16>,
+
+    /// Peer's persistent keepalive
+    #[arg(long)]
+    pub persistent_keepalive: Option<u16>,
+
+    /// Subnets behind the peer, routed to it by every other node
+    #[arg(long = "route", value_name = "ROUTES", value_parser = parser::parser_address_in_range)]
+    pub routes: Option<std::vec::Vec<IpNet>>,
+
     /// Peer's endpoint allowed ips
-    #[arg(long, value_name = "ALLOWED_IPS", default_value = DEFAULT_PEER_ENDPOINT_ALLOWED_IPS, value_parser = parser::parser_address_in_range)]
-    pub endpoint_allowed_ips: std::vec::Vec<IpNet>,
+    #[arg(long, value_name = "ALLOWED_IPS", value_parser = parser::parser_address_in_range)]
+    pub endpoint_allowed_ips: Option<std::vec::Vec<IpNet>>,
 
-    /// Peer's WireGuard PostUp command
+    /// Peer's WireGuard PostUp command, {interface}, {address}, {subnet} and {listen_port} are replaced in the hooks
     #[arg(long)]
     pub post_up: Option<String>,
 
@@ -130,4 +341,307 @@ pub(crate) struct AddPeer {
     /// Peer's WireGuard PreDown command
     #[arg(long)]
     pub pre_down: Option<String>,
+
+    /// Tag replacing the tags of the peer, may be repeated
+    #[arg(long = "tag", value_name = "TAG", value_parser = parser::parser_tag)]
+    pub tags: Vec<String>,
+
+    #[command(flatten)]
+    pub clear: ClearPeer,
+
+    /// Edit the settings in prompts pre-filled with the current values, `-` clears a setting
+    #[arg(long, short)]
+    pub interactive: bool,
+
+    /// Also update the peer on the running relay interface
+    #[arg(long)]
+    pub live: bool,
+}
+
+// settings edit-peer unsets, each conflicts with the flag that sets it
+#[derive(Args, Clone, Copy)]
+pub(crate) struct ClearPeer {
+    /// Clear the peer's public endpoint
+    #[arg(long, conflicts_with = "endpoint")]
+    pub clear_endpoint: bool,
+
+    /// Clear the peer's AllowedIPs
+    #[arg(long, conflicts_with = "allowed_ips")]
+    pub clear_allowed_ips: bool,
+
+    /// Clear the peer's MTU
+    #[arg(long, conflicts_with = "mtu")]
+    pub clear_mtu: bool,
+
+    /// Clear the peer's persistent keepalive
+    #[arg(long, conflicts_with = "persistent_keepalive")]
+    pub clear_persistent_keepalive: bool,
+
+    /// Clear the subnets behind the peer
+    #[arg(long, conflicts_with = "routes")]
+    pub clear_routes: bool,
+
+    /// Clear the peer's endpoint allowed ips
+    #[arg(long, conflicts_with = "endpoint_allowed_ips")]
+    pub clear_endpoint_allowed_ips: bool,
+
+    /// Clear the peer's WireGuard PostUp command
+    #[arg(long, conflicts_with = "post_up")]
+    pub clear_post_up: bool,
+
+    /// Clear the peer's WireGuard PostDown command
+    #[arg(long, conflicts_with = "post_down")]
+    pub clear_post_down: bool,
+
+    /// Clear the peer's WireGuard PreUp command
+    #[arg(long, conflicts_with = "pre_up")]
+    pub clear_pre_up: bool,
+
+    /// Clear the peer's WireGuard PreDown command
+    #[arg(long, conflicts_with = "pre_down")]
+    pub clear_pre_down: bool,
+
+    /// Clear the tags of the peer
+    #[arg(long, conflicts_with = "tags")]
+    pub clear_tags: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct List {
+    /// Only the expired peers
+    #[arg(long)]
+    pub expired: bool,
+
+    /// Only the nodes of the tag
+    #[arg(long, value_parser = parser::parser_tag)]
+    pub tag: Option<String>,
+
+    /// Only the nodes of the role
+    #[arg(long, value_enum)]
+    pub role: Option<Role>,
+
+    /// Column the nodes are sorted by, the order they were added in when omitted
+    #[arg(long, value_enum)]
+    pub sort: Option<SortKey>,
+
+    /// Output format
+    #[arg(long, short, value_enum, default_value_t = Output::Table)]
+    pub output: Output,
+}
+
+#[derive(Args)]
+pub(crate) struct RevokePeer {
+    /// Revoke every peer of the tag at once, the peer is picked interactively when omitted
+    #[arg(long, value_parser = parser::parser_tag)]
+    pub tag: Option<String>,
+
+    /// Revoke without asking for confirmation
+    #[arg(long, short, requires = "tag")]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct Log {
+    /// Only the changes of the node
+    #[arg(long)]
+    pub node: Option<String>,
+
+    /// Only the changes of the action
+    #[arg(long, value_enum)]
+    pub action: Option<Action>,
+
+    /// Only the changes made by the user
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Only the changes since the time, e.g. 2024-01-31 or 2024-01-31 18:00:00
+    #[arg(long, value_parser = parser::parser_date_time)]
+    pub since: Option<i64>,
+
+    /// Only the changes until the time, e.g. 2024-01-31 or 2024-01-31 18:00:00
+    #[arg(long, value_parser = parser::parser_date_time)]
+    pub until: Option<i64>,
+
+    /// Only the latest changes
+    #[arg(long, short = 'n')]
+    pub limit: Option<usize>,
+
+    /// Print the node before and after every change
+    #[arg(long, short)]
+    pub verbose: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct Export {
+    /// Peer's name, all peers of the network are bundled by the zip format when omitted
+    pub name: Option<String>,
+
+    /// Bundle the peers of the tag by the zip format
+    #[arg(long, conflicts_with = "name", value_parser = parser::parser_tag)]
+    pub tag: Option<String>,
+
+    /// Exported file format
+    #[arg(long, short, value_enum, default_value_t = Format::Conf)]
+    pub format: Format,
+
+    /// Exported file, named after the peer or the network in the current directory when omitted
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub(crate) struct RotateKey {
+    /// Node's name
+    pub name: String,
+
+    /// Hours the previous key pair can be rolled back to
+    #[arg(long, default_value = DEFAULT_KEY_GRACE_PERIOD)]
+    pub grace: u64,
+
+    /// Restore the previous key pair of the node
+    #[arg(long, conflicts_with = "grace")]
+    pub rollback: bool,
+
+    /// Directory the changed configurations are written to, printed when omitted
+    #[arg(long, short)]
+    pub output_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub(crate) struct Apply {
+    /// Network spec file
+    #[arg(long, short)]
+    pub file: PathBuf,
+
+    /// Print the plan without applying it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Apply the plan without confirmation
+    #[arg(long, short)]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct Backup {
+    /// Archive file, JSON when named *.json and YAML otherwise
+    pub file: PathBuf,
+
+    /// Encrypt the private keys with a passphrase
+    #[arg(long)]
+    pub encrypt: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct Restore {
+    /// Archive file
+    pub file: PathBuf,
+
+    /// Print the changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Apply the changes without confirmation
+    #[arg(long, short)]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct RekeyStore {
+    /// Key file of the new key, a random key is written to it if it does not exist
+    #[arg(long)]
+    pub new_key_file: Option<PathBuf>,
+
+    /// Decrypt the private keys and store them in plaintext
+    #[arg(long, conflicts_with = "new_key_file")]
+    pub plaintext: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct Reresolve {
+    /// Seconds between two re-resolution rounds
+    #[arg(long, default_value = DEFAULT_RERESOLVE_INTERVAL, value_parser = clap::value_parser!(u64).range(1..))]
+    pub interval: u64,
+}
+
+#[derive(Args)]
+pub(crate) struct Daemon {
+    /// Relay node the host runs, the network's first relay when omitted
+    #[arg(long)]
+    pub relay: Option<String>,
+
+    /// Seconds between two database synchronizations
+    #[arg(long, default_value = DEFAULT_SYNC_INTERVAL, value_parser = clap::value_parser!(u64).range(1..))]
+    pub interval: u64,
+
+    /// Seconds between two re-resolutions of stale peer endpoints
+    #[arg(long, default_value = DEFAULT_RERESOLVE_INTERVAL, value_parser = clap::value_parser!(u64).range(1..))]
+    pub reresolve_interval: u64,
+
+    /// Seconds a hook of the relay may run before it is killed
+    #[arg(long, default_value = DEFAULT_HOOK_TIMEOUT, value_parser = clap::value_parser!(u64).range(1..))]
+    pub hook_timeout: u64,
+}
+
+#[derive(Args)]
+pub(crate) struct Firewall {
+    /// Interface the network's traffic leaves through, any other interface when omitted
+    #[arg(long, value_name = "INTERFACE")]
+    pub egress: Option<String>,
+
+    /// Drop the traffic between the peers of the relay
+    #[arg(long)]
+    pub isolate: bool,
+
+    /// Print the ruleset without loading it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Remove the ruleset
+    #[arg(long, conflicts_with_all = ["egress", "isolate"])]
+    pub remove: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct Acl {
+    #[command(subcommand)]
+    pub command: AclCommand,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum AclCommand {
+    /// List the groups and the numbered rules
+    List,
+
+    /// Allow traffic, e.g. "group:dev -> node:db tcp/5432"
+    Allow { rule: Rule },
+
+    /// Remove the rule of the number printed by list
+    Remove { number: usize },
+
+    /// Define the nodes of a group, a group without nodes is removed
+    Group { name: String, nodes: Vec<String> },
+}
+
+#[derive(Args)]
+pub(crate) struct UpDown {
+    /// Relay node the host runs, the network's first relay when omitted
+    #[arg(long)]
+    pub relay: Option<String>,
+
+    /// Seconds a hook may run before it is killed
+    #[arg(long, default_value = DEFAULT_HOOK_TIMEOUT, value_parser = clap::value_parser!(u64).range(1..))]
+    pub hook_timeout: u64,
+}
+
+#[cfg(feature = "api")]
+#[derive(Args)]
+pub(crate) struct Serve {
+    /// Address the management API listens on
+    #[arg(long, default_value = DEFAULT_API_LISTEN)]
+    pub listen: std::net::SocketAddr,
+
+    /// Bearer token required by every request
+    #[arg(long, env = "WGSDC_API_TOKEN", hide_env_values = true)]
+    pub token: String,
 }
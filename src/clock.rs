@@ -0,0 +1,42 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send + Sync {
+    // current unix timestamp
+    fn now(&self) -> i64;
+
+    // current time, for the peer handshake times of the device
+    fn system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.now().max(0) as u64)
+    }
+}
+
+// system clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64)
+    }
+}
+
+// clock standing still at a unix timestamp
+#[cfg(test)]
+pub struct FixedClock(pub i64);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.0
+    }
+}
+
+// local date and time of a unix timestamp
+pub fn format(timestamp: i64) -> String {
+    use chrono::TimeZone;
+    match chrono::Local.timestamp_opt(timestamp, 0) {
+        chrono::LocalResult::Single(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        _ => timestamp.to_string(),
+    }
+}
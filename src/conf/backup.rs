@@ -0,0 +1,488 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::conf::cipher::{self, Cipher, Secret};
+use crate::model::audit::{Action, AuditEvent};
+use crate::model::network::Network;
+use crate::model::{validate, Node};
+
+// version of the archive layout, archives of another version are rejected
+pub const SCHEMA_VERSION: u32 = 1;
+
+// a network and its nodes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkState {
+    pub network: Network,
+    #[serde(default)]
+    pub node_list: Vec<Node>,
+}
+
+// salt and verifier of the passphrase the private keys of an archive are encrypted with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encryption {
+    pub salt: String,
+    pub verifier: String,
+}
+
+// backup of all networks of a store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    // unix timestamp of the backup
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    pub network_list: Vec<NetworkState>,
+}
+
+impl Archive {
+    // archive of the networks, the private keys are encrypted with the secret if any
+    pub fn new(
+        network_list: Vec<NetworkState>,
+        created_at: i64,
+        secret: Option<&Secret>,
+    ) -> anyhow::Result<Self> {
+        let mut archive = Self {
+            version: SCHEMA_VERSION,
+            created_at,
+            encryption: None,
+            network_list,
+        };
+        if let Some(secret) = secret {
+            let salt = Cipher::salt();
+            let cipher = Cipher::new(secret, &salt)?;
+            archive.map_private_keys(|key| cipher.encrypt(key))?;
+            archive.encryption = Some(Encryption {
+                salt: STANDARD.encode(salt),
+                verifier: cipher.verifier()?,
+            });
+        }
+        Ok(archive)
+    }
+
+    // read a JSON or YAML archive, checking its version before anything else
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        // JSON is read as YAML as well
+        let value: serde_yaml::Value = serde_yaml::from_str(content).context("invalid archive")?;
+        let version = value
+            .get("version")
+            .and_then(serde_yaml::Value::as_u64)
+            .context("the archive has no schema version")?;
+        if version != u64::from(SCHEMA_VERSION) {
+            anyhow::bail!(
+                "the archive has schema version {}, this version of wgsdc reads version {}",
+                version,
+                SCHEMA_VERSION
+            )
+        }
+        Ok(serde_yaml::from_value(value).context("invalid archive")?)
+    }
+
+    // JSON archive when the file is named *.json, YAML otherwise
+    pub fn to_content(&self, path: &Path) -> anyhow::Result<String> {
+        Ok(match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::to_string_pretty(self)?,
+            _ => serde_yaml::to_string(self)?,
+        })
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    // the networks with the private keys decrypted by the secret
+    pub fn into_network_list(
+        mut self,
+        secret: Option<&Secret>,
+    ) -> anyhow::Result<Vec<NetworkState>> {
+        if let Some(encryption) = self.encryption.take() {
+            let secret = secret.context("the archive is encrypted, a passphrase is required")?;
+            let cipher = Cipher::new(secret, &STANDARD.decode(&encryption.salt)?)?;
+            cipher.verify(&encryption.verifier)?;
+            self.map_private_keys(|key| cipher.decrypt(key))?;
+        } else {
+            let mut private_keys = self
+                .network_list
+                .iter()
+                .flat_map(|s| &s.node_list)
+                .flat_map(|n| n.private_key.iter().chain(&n.previous_private_key));
+            if private_keys.any(|key| cipher::is_encrypted(key)) {
+                anyhow::bail!("the archive holds encrypted private keys but no passphrase salt")
+            }
+        }
+        Ok(self.network_list)
+    }
+
+    fn map_private_keys(
+        &mut self,
+        f: impl Fn(&str) -> anyhow::Result<String>,
+    ) -> anyhow::Result<()> {
+        for node in self.network_list.iter_mut().flat_map(|s| &mut s.node_list) {
+            for private_key in [&mut node.private_key, &mut node.previous_private_key]
+                .into_iter()
+                .flatten()
+            {
+                *private_key = f(private_key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// a restored archive must be consistent on its own
+pub fn validate(network_list: &[NetworkState]) -> anyhow::Result<()> {
+    let mut errors = Vec::new();
+    for (i, state) in network_list.iter().enumerate() {
+        let name = &state.network.name;
+        for other in &network_list[i + 1..] {
+            if other.network.name.eq(name) {
+                errors.push(format!("network {} is archived twice", name));
+            }
+            if other.network.interface.eq(&state.network.interface) {
+                errors.push(format!(
+                    "interface {} is used by networks {} and {}",
+                    state.network.interface, name, other.network.name
+                ));
+            }
+        }
+        for (j, node) in state.node_list.iter().enumerate() {
+            if node.name.is_none() {
+                errors.push(format!("network {} has a node without a name", name));
+                continue;
+            }
+            if node.public_key.is_none() || node.private_key.is_none() {
+                errors.push(format!(
+                    "node {} of network {} has no key pair",
+                    node.name(),
+                    name
+                ));
+            }
+            if state.node_list[j + 1..]
+                .iter()
+                .any(|n| n.name.as_deref() == Some(node.name()))
+            {
+                errors.push(format!(
+                    "node {} of network {} is archived twice",
+                    node.name(),
+                    name
+                ));
+            }
+            // the relay comes first, as it was added first
+            if let Some(parent) = &node.parent {
+                if !state.node_list[..j]
+                    .iter()
+                    .any(|n| n.relay && n.name.as_deref() == Some(parent.as_str()))
+                {
+                    errors.push(format!(
+                        "node {} of network {} has no relay named {} before it",
+                        node.name(),
+                        name,
+                        parent
+                    ));
+                }
+            }
+        }
+        if state.node_list.iter().any(|n| n.name.is_none()) {
+            continue;
+        }
+        errors.extend(
+            validate::validate(&state.network, &state.node_list)
+                .into_iter()
+                .map(|c| format!("network {}: {}", name, c)),
+        );
+    }
+    if !errors.is_empty() {
+        anyhow::bail!(errors.join("\n"))
+    }
+    Ok(())
+}
+
+// change of the store made by a restore
+#[derive(Debug, Clone)]
+pub enum Change {
+    Network {
+        action: Action,
+        name: String,
+    },
+    Node {
+        action: Action,
+        network: String,
+        before: Option<Box<Node>>,
+        after: Option<Box<Node>>,
+    },
+}
+
+impl Change {
+    // audit event of a node change
+    pub fn audit_event(&self) -> Option<AuditEvent> {
+        match self {
+            Change::Network { .. } => None,
+            Change::Node {
+                action,
+                network,
+                before,
+                after,
+            } => {
+                let node = before.as_deref().or(after.as_deref())?;
+                let mut event =
+                    AuditEvent::new(*action, node.name(), before.as_deref(), after.as_deref());
+                event.network = network.clone();
+                Some(event)
+            }
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sign = |action: &Action| match action {
+            Action::Create => "+",
+            Action::Delete => "-",
+            Action::Update | Action::Rotate => "~",
+        };
+        match self {
+            Change::Network { action, name } => write!(f, "{} network {}", sign(action), name),
+            Change::Node {
+                action,
+                network,
+                before,
+                after,
+            } => {
+                let node = before
+                    .as_deref()
+                    .or(after.as_deref())
+                    .expect("node change without node");
+                write!(f, "{} node {}/{}", sign(action), network, node.name())?;
+                if let (Some(before), Some(after)) = (before, after) {
+                    write!(f, " ({})", changed_fields(before, after).join(", "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// fields of the node that differ, the fields only one of the nodes serializes included
+fn changed_fields(before: &Node, after: &Node) -> Vec<String> {
+    let before = serde_json::to_value(before).unwrap_or_default();
+    let after = serde_json::to_value(after).unwrap_or_default();
+    match (before.as_object(), after.as_object()) {
+        (Some(before), Some(after)) => before
+            .keys()
+            .chain(after.keys())
+            .collect::<BTreeSet<&String>>()
+            .into_iter()
+            .filter(|field| before.get(*field) != after.get(*field))
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// changes that turn the current networks into the restored ones
+pub fn diff(current: &[NetworkState], restored: &[NetworkState]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let empty = Vec::new();
+    for state in restored {
+        let name = &state.network.name;
+        let existing = current.iter().find(|s| s.network.name.eq(name));
+        match existing {
+            None => changes.push(Change::Network {
+                action: Action::Create,
+                name: name.clone(),
+            }),
+            Some(existing) if existing.network != state.network => changes.push(Change::Network {
+                action: Action::Update,
+                name: name.clone(),
+            }),
+            Some(_) => {}
+        }
+        let node_list = existing.map_or(&empty, |s| &s.node_list);
+        for node in &state.node_list {
+            match node_list.iter().find(|n| n.name().eq(node.name())) {
+                None => changes.push(Change::Node {
+                    action: Action::Create,
+                    network: name.clone(),
+                    before: None,
+                    after: Some(Box::new(node.clone())),
+                }),
+                Some(before) if !changed_fields(before, node).is_empty() => {
+                    changes.push(Change::Node {
+                        action: Action::Update,
+                        network: name.clone(),
+                        before: Some(Box::new(before.clone())),
+                        after: Some(Box::new(node.clone())),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for node in node_list
+            .iter()
+            .filter(|n| !state.node_list.iter().any(|r| r.name().eq(n.name())))
+        {
+            changes.push(Change::Node {
+                action: Action::Delete,
+                network: name.clone(),
+                before: Some(Box::new(node.clone())),
+                after: None,
+            });
+        }
+    }
+    for state in current
+        .iter()
+        .filter(|s| !restored.iter().any(|r| r.network.name.eq(&s.network.name)))
+    {
+        for node in &state.node_list {
+            changes.push(Change::Node {
+                action: Action::Delete,
+                network: state.network.name.clone(),
+                before: Some(Box::new(node.clone())),
+                after: None,
+            });
+        }
+        changes.push(Change::Network {
+            action: Action::Delete,
+            name: state.network.name.clone(),
+        });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::{self, leaf, node};
+
+    fn home() -> NetworkState {
+        NetworkState {
+            network: test_util::home(),
+            node_list: vec![
+                node("wg0", true, "10.66.66.1/24"),
+                leaf("laptop", "wg0", "10.66.66.2/24"),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let secret = Secret::Passphrase("correct horse".to_string());
+        for path in ["backup.yaml", "backup.json"] {
+            let archive = Archive::new(vec![home()], 100, Some(&secret)).unwrap();
+            let content = archive.to_content(Path::new(path)).unwrap();
+            assert!(!content.contains("laptop-private"));
+
+            let archive = Archive::parse(&content).unwrap();
+            assert!(archive.is_encrypted());
+            let wrong = Secret::Passphrase("battery staple".to_string());
+            assert!(archive.clone().into_network_list(Some(&wrong)).is_err());
+            assert!(archive.clone().into_network_list(None).is_err());
+            let network_list = archive.into_network_list(Some(&secret)).unwrap();
+            assert_eq!(network_list[0].network, home().network);
+            assert_eq!(
+                network_list[0].node_list[1].private_key.as_deref(),
+                Some("laptop-private")
+            );
+        }
+
+        let content = Archive::new(vec![home()], 100, None)
+            .unwrap()
+            .to_content(Path::new("backup.yaml"))
+            .unwrap();
+        let network_list = Archive::parse(&content)
+            .unwrap()
+            .into_network_list(None)
+            .unwrap();
+        assert_eq!(network_list[0].node_list.len(), 2);
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let content = Archive::new(vec![home()], 100, None)
+            .unwrap()
+            .to_content(Path::new("backup.json"))
+            .unwrap()
+            .replace("\"version\": 1", "\"version\": 2");
+        let err = Archive::parse(&content).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the archive has schema version 2, this version of wgsdc reads version 1"
+        );
+        assert!(Archive::parse("network_list: []").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[home()]).is_ok());
+
+        let mut state = home();
+        state.node_list.push(leaf("phone", "wg0", "10.66.66.2/24"));
+        state.node_list[1].with_parent(Some("wg1".to_string()));
+        let err = validate(&[state, home()]).unwrap_err().to_string();
+        assert_eq!(
+            err.lines().collect::<Vec<&str>>(),
+            [
+                "network home is archived twice",
+                "interface wg0 is used by networks home and home",
+                "node laptop of network home has no relay named wg1 before it",
+                "network home: address 10.66.66.2 of laptop is also used by phone",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        assert!(diff(&[home()], &[home()]).is_empty());
+
+        let mut restored = home();
+        restored.node_list[1].with_mtu(Some(1280));
+        restored
+            .node_list
+            .push(leaf("phone", "wg0", "10.66.66.3/24"));
+        let mut office = home();
+        office.network.name = "office".to_string();
+        office.network.interface = "wg1".to_string();
+        let changes = diff(&[home(), office], &[restored]);
+        assert_eq!(
+            changes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>(),
+            [
+                "~ node home/laptop (mtu)",
+                "+ node home/phone",
+                "- node office/wg0",
+                "- node office/laptop",
+                "- network office",
+            ]
+        );
+        let events = changes
+            .iter()
+            .filter_map(Change::audit_event)
+            .map(|e| (e.network, e.action, e.node))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events[0],
+            ("home".to_string(), Action::Update, "laptop".to_string())
+        );
+        assert_eq!(events.len(), 4);
+
+        // a field dropped from the node is a change as well
+        let mut current = home();
+        current.node_list[1].with_tags(Some(vec!["ops".to_string()]));
+        let changes = diff(&[current], &[home()]);
+        assert_eq!(
+            changes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>(),
+            ["~ node home/laptop (tags)"]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand_core::RngCore;
+
+use crate::standard::{PASSPHRASE_ENV, SESSION_ENV};
+
+// prefix of an encrypted value, the values without it are plaintext
+const PREFIX: &str = "enc:v1:";
+// decrypted by the key the store was encrypted with
+const VERIFIER: &str = "wgsdc";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+pub const SALT_LEN: usize = 16;
+
+// secret the store key is taken from
+#[derive(Debug, Clone)]
+pub enum Secret {
+    // derived from the passphrase with Argon2id and the salt of the store
+    Passphrase(String),
+    // file holding the base64 encoded key
+    KeyFile(PathBuf),
+    // base64 encoded key printed by `wgsdc unlock`
+    Session(String),
+}
+
+impl Secret {
+    // the session, the key file or the passphrase of the environment, in that order
+    pub fn from_env(key_file: Option<PathBuf>) -> Option<Secret> {
+        if let Ok(session) = std::env::var(SESSION_ENV) {
+            return Some(Secret::Session(session));
+        }
+        if let Some(key_file) = key_file {
+            return Some(Secret::KeyFile(key_file));
+        }
+        std::env::var(PASSPHRASE_ENV).ok().map(Secret::Passphrase)
+    }
+}
+
+// envelope encryption of the secrets stored in the database
+#[derive(Clone)]
+pub struct Cipher {
+    key: [u8; KEY_LEN],
+}
+
+impl Cipher {
+    // key of the secret, the salt is only used by a passphrase
+    pub fn new(secret: &Secret, salt: &[u8]) -> anyhow::Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        match secret {
+            Secret::Passphrase(passphrase) => argon2::Argon2::default()
+                .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                .map_err(|e| anyhow!("failed to derive the store key: {}", e))?,
+            Secret::KeyFile(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read key file {}", path.display()))?;
+                key = decode_key(content.trim())
+                    .with_context(|| format!("invalid key file {}", path.display()))?;
+            }
+            Secret::Session(session) => {
+                key = decode_key(session.trim()).context("invalid session key")?;
+            }
+        }
+        Ok(Self { key })
+    }
+
+    // random salt of a new store key
+    pub fn salt() -> [u8; SALT_LEN] {
+        let mut salt = [0u8; SALT_LEN];
+        rand_core::OsRng.fill_bytes(&mut salt);
+        salt
+    }
+
+    // random key, base64 encoded for a key file
+    pub fn generate_key() -> String {
+        let mut key = [0u8; KEY_LEN];
+        rand_core::OsRng.fill_bytes(&mut key);
+        STANDARD.encode(key)
+    }
+
+    // base64 encoded key, supplied as the session of later commands
+    pub fn session(&self) -> String {
+        STANDARD.encode(self.key)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> anyhow::Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand_core::OsRng.fill_bytes(&mut nonce);
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| anyhow!("failed to encrypt"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!("{}{}", PREFIX, STANDARD.encode(sealed)))
+    }
+
+    // plaintext values are returned as they are
+    pub fn decrypt(&self, value: &str) -> anyhow::Result<String> {
+        let sealed = match value.strip_prefix(PREFIX) {
+            Some(sealed) => STANDARD.decode(sealed)?,
+            None => return Ok(value.to_string()),
+        };
+        if sealed.len() < NONCE_LEN {
+            anyhow::bail!("invalid encrypted value")
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("wrong passphrase or key file"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    // proof of the key, stored with the salt
+    pub fn verifier(&self) -> anyhow::Result<String> {
+        self.encrypt(VERIFIER)
+    }
+
+    pub fn verify(&self, verifier: &str) -> anyhow::Result<()> {
+        match self.decrypt(verifier) {
+            Ok(value) if value.eq(VERIFIER) => Ok(()),
+            _ => anyhow::bail!("wrong passphrase or key file"),
+        }
+    }
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+fn decode_key(s: &str) -> anyhow::Result<[u8; KEY_LEN]> {
+    STANDARD
+        .decode(s)?
+        .try_into()
+        .map_err(|_| anyhow!("the key is not {} bytes", KEY_LEN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cipher() {
+        let salt = Cipher::salt();
+        let cipher = Cipher::new(&Secret::Passphrase("correct horse".to_string()), &salt).unwrap();
+        let sealed = cipher.encrypt("private-key").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert_ne!(sealed, cipher.encrypt("private-key").unwrap());
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), "private-key");
+        assert_eq!(cipher.decrypt("plaintext").unwrap(), "plaintext");
+
+        // the same passphrase and salt derive the same key
+        let same = Cipher::new(&Secret::Passphrase("correct horse".to_string()), &salt).unwrap();
+        assert_eq!(same.decrypt(&sealed).unwrap(), "private-key");
+        let session = Cipher::new(&Secret::Session(cipher.session()), &[]).unwrap();
+        assert_eq!(session.decrypt(&sealed).unwrap(), "private-key");
+
+        let wrong = Cipher::new(&Secret::Passphrase("battery staple".to_string()), &salt).unwrap();
+        assert!(wrong.decrypt(&sealed).is_err());
+        assert!(wrong.verify(&cipher.verifier().unwrap()).is_err());
+        assert!(same.verify(&cipher.verifier().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.key");
+        std::fs::write(&path, format!("{}\n", Cipher::generate_key())).unwrap();
+        let cipher = Cipher::new(&Secret::KeyFile(path.clone()), &[]).unwrap();
+        let sealed = cipher.encrypt("private-key").unwrap();
+        let again = Cipher::new(&Secret::KeyFile(path.clone()), &[]).unwrap();
+        assert_eq!(again.decrypt(&sealed).unwrap(), "private-key");
+
+        std::fs::write(&path, "short").unwrap();
+        assert!(Cipher::new(&Secret::KeyFile(path), &[]).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use std::io::Write;
+
+use qrcode::render::svg;
+use qrcode::QrCode;
+use serde::Serialize;
+
+use crate::conf::render;
+use crate::model::network::Network;
+use crate::model::Node;
+
+// exported file formats of a peer configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    // wg-quick configuration file
+    Conf,
+    // QR code PNG image, scanned by the mobile clients
+    QrPng,
+    // QR code SVG image
+    QrSvg,
+    // configuration wrapped in a JSON document
+    Json,
+    // configuration and QR code images of all peers of the network
+    Zip,
+}
+
+impl Format {
+    // file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Conf => "conf",
+            Format::QrPng => "png",
+            Format::QrSvg => "svg",
+            Format::Json => "json",
+            Format::Zip => "zip",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PeerExport<'a> {
+    network: &'a str,
+    name: &'a str,
+    config: &'a str,
+}
+
+// configuration as a QR code PNG image
+pub fn qr_png(config: &str) -> anyhow::Result<Vec<u8>> {
+    let code = QrCode::new(config.as_bytes())?;
+    let image = code.render::<image::Luma<u8>>().build();
+    let mut png = Vec::new();
+    image::DynamicImage::ImageLuma8(image).write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+// configuration as a QR code SVG image
+pub fn qr_svg(config: &str) -> anyhow::Result<String> {
+    let code = QrCode::new(config.as_bytes())?;
+    Ok(code.render::<svg::Color>().build())
+}
+
+// configuration as a JSON document
+pub fn json(network: &Network, node_name: &str, config: &str) -> anyhow::Result<String> {
+    let export = PeerExport {
+        network: &network.name,
+        name: node_name,
+        config,
+    };
+    Ok(serde_json::to_string_pretty(&export)?)
+}
+
+// zip archive with the configuration and QR code images of the peers, the named peer
+// or the peers of the tag, all peers of the network when neither is given
+pub fn zip(
+    network: &Network,
+    node_name: Option<&str>,
+    tag: Option<&str>,
+    node_list: &[Node],
+    now: i64,
+) -> anyhow::Result<Vec<u8>> {
+    if let Some(node_name) = node_name {
+        if !node_list.iter().any(|n| !n.relay && n.name().eq(node_name)) {
+            anyhow::bail!("there is no peer named {}", node_name)
+        }
+    }
+    if let Some(tag) = tag {
+        if !node_list.iter().any(|n| !n.relay && n.has_tag(tag)) {
+            anyhow::bail!("there is no peer tagged {}", tag)
+        }
+    }
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(0o600);
+    for node in node_list
+        .iter()
+        .filter(|n| !n.relay && (node_name.is_none() || node_name == Some(n.name())))
+        .filter(|n| tag.iter().all(|tag| n.has_tag(tag)))
+    {
+        let config = render::node_config_at(network, node.name(), node_list, now)?;
+        archive.start_file(format!("{}.conf", node.name()), options)?;
+        archive.write_all(config.as_bytes())?;
+        archive.start_file(format!("{}.png", node.name()), options)?;
+        archive.write_all(&qr_png(&config)?)?;
+        archive.start_file(format!("{}.svg", node.name()), options)?;
+        archive.write_all(qr_svg(&config)?.as_bytes())?;
+    }
+    Ok(archive.finish()?.into_inner())
+}
+
+// exported file content of the peer in the format
+pub fn export(
+    format: Format,
+    network: &Network,
+    node_name: Option<&str>,
+    tag: Option<&str>,
+    node_list: &[Node],
+    now: i64,
+) -> anyhow::Result<Vec<u8>> {
+    if format == Format::Zip {
+        return zip(network, node_name, tag, node_list, now);
+    }
+    if tag.is_some() {
+        anyhow::bail!("the peers of a tag are exported by the zip format")
+    }
+    let node_name = match node_name {
+        Some(node_name) => node_name,
+        None => anyhow::bail!(
+            "a peer name is required to export a {} file",
+            format.extension()
+        ),
+    };
+    let config = render::node_config_at(network, node_name, node_list, now)?;
+    Ok(match format {
+        Format::Conf => config.into_bytes(),
+        Format::QrPng => qr_png(&config)?,
+        Format::QrSvg => qr_svg(&config)?.into_bytes(),
+        Format::Json => json(network, node_name, &config)?.into_bytes(),
+        Format::Zip => unreachable!(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::{self, home, leaf};
+    use std::io::Read;
+
+    fn node_list() -> Vec<Node> {
+        let mut relay = test_util::node("wg0", true, "10.66.66.1/24");
+        relay.with_endpoint(Some("relay.example.com:51820".parse().unwrap()));
+        vec![
+            relay,
+            leaf("laptop", "wg0", "10.66.66.2/24"),
+            leaf("phone", "wg0", "10.66.66.3/24"),
+        ]
+    }
+
+    // decode the single QR code of a greyscale image
+    fn decode(width: usize, height: usize, luma: impl FnMut(usize, usize) -> u8) -> String {
+        let mut image = rqrr::PreparedImage::prepare_from_greyscale(width, height, luma);
+        let grids = image.detect_grids();
+        assert_eq!(grids.len(), 1);
+        grids[0].decode().unwrap().1
+    }
+
+    fn decode_png(png: &[u8]) -> String {
+        let image = image::load_from_memory(png).unwrap().to_luma8();
+        decode(image.width() as usize, image.height() as usize, |x, y| {
+            image.get_pixel(x as u32, y as u32).0[0]
+        })
+    }
+
+    // rasterise the SVG on a white background
+    fn decode_svg(svg: &[u8]) -> String {
+        let tree = resvg::usvg::Tree::from_data(svg, &resvg::usvg::Options::default()).unwrap();
+        let size = tree.size().to_int_size();
+        let mut pixmap = resvg::tiny_skia::Pixmap::new(size.width(), size.height()).unwrap();
+        pixmap.fill(resvg::tiny_skia::Color::WHITE);
+        resvg::render(
+            &tree,
+            resvg::tiny_skia::Transform::default(),
+            &mut pixmap.as_mut(),
+        );
+        decode(size.width() as usize, size.height() as usize, |x, y| {
+            pixmap.pixel(x as u32, y as u32).unwrap().green()
+        })
+    }
+
+    #[test]
+    fn test_qr_roundtrip() {
+        let config = render::node_config_at(&home(), "laptop", &node_list(), 0).unwrap();
+        let png = export(
+            Format::QrPng,
+            &home(),
+            Some("laptop"),
+            None,
+            &node_list(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(decode_png(&png), config);
+
+        let svg = export(
+            Format::QrSvg,
+            &home(),
+            Some("laptop"),
+            None,
+            &node_list(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(decode_svg(&svg), config);
+    }
+
+    #[test]
+    fn test_export() {
+        let config = render::node_config_at(&home(), "laptop", &node_list(), 0).unwrap();
+        let conf = export(Format::Conf, &home(), Some("laptop"), None, &node_list(), 0).unwrap();
+        assert_eq!(conf, config.as_bytes());
+
+        let json = export(Format::Json, &home(), Some("laptop"), None, &node_list(), 0).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["network"], "home");
+        assert_eq!(json["name"], "laptop");
+        assert_eq!(json["config"], config.as_str());
+
+        assert!(export(Format::Conf, &home(), None, None, &node_list(), 0).is_err());
+        assert!(export(Format::Conf, &home(), Some("tablet"), None, &node_list(), 0).is_err());
+    }
+
+    #[test]
+    fn test_zip() {
+        let bundle = export(Format::Zip, &home(), None, None, &node_list(), 0).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bundle)).unwrap();
+        let mut names = archive.file_names().collect::<Vec<&str>>();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "laptop.conf",
+                "laptop.png",
+                "laptop.svg",
+                "phone.conf",
+                "phone.png",
+                "phone.svg"
+            ]
+        );
+
+        let mut config = String::new();
+        archive
+            .by_name("phone.conf")
+            .unwrap()
+            .read_to_string(&mut config)
+            .unwrap();
+        assert_eq!(
+            config,
+            render::node_config_at(&home(), "phone", &node_list(), 0).unwrap()
+        );
+        let mut png = Vec::new();
+        archive
+            .by_name("phone.png")
+            .unwrap()
+            .read_to_end(&mut png)
+            .unwrap();
+        assert_eq!(decode_png(&png), config);
+
+        // only the named peer
+        let bundle = export(Format::Zip, &home(), Some("laptop"), None, &node_list(), 0).unwrap();
+        let archive = zip::ZipArchive::new(std::io::Cursor::new(bundle)).unwrap();
+        assert_eq!(archive.len(), 3);
+        // an unknown peer or a relay is not exported
+        for name in ["tablet", "wg0"] {
+            let err = export(Format::Zip, &home(), Some(name), None, &node_list(), 0).unwrap_err();
+            assert_eq!(err.to_string(), format!("there is no peer named {}", name));
+        }
+
+        // only the peers of the tag
+        let mut node_list = node_list();
+        node_list[2].with_tags(Some(vec!["ops".to_string()]));
+        let bundle = export(Format::Zip, &home(), None, Some("ops"), &node_list, 0).unwrap();
+        let archive = zip::ZipArchive::new(std::io::Cursor::new(bundle)).unwrap();
+        let mut names = archive.file_names().collect::<Vec<&str>>();
+        names.sort_unstable();
+        assert_eq!(names, ["phone.conf", "phone.png", "phone.svg"]);
+        assert!(export(Format::Zip, &home(), None, Some("dev"), &node_list, 0).is_err());
+        assert!(export(Format::Conf, &home(), None, Some("ops"), &node_list, 0).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use ipnet::IpNet;
+use serde::Serialize;
+
+use crate::clock;
+use crate::model::audit::{Action, AuditEvent};
+use crate::model::Node;
+
+// output formats of the node list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Output {
+    // aligned columns for the terminal
+    Table,
+    Json,
+    Csv,
+    Yaml,
+}
+
+// role of a node in the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Relay,
+    Leaf,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Relay => "relay",
+            Role::Leaf => "leaf",
+        }
+    }
+}
+
+// columns the node list is sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortKey {
+    Name,
+    Role,
+    Address,
+    CreatedAt,
+    // the latest handshake first
+    Handshake,
+}
+
+// a node of the list, timestamps are unix timestamps
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Row {
+    pub name: String,
+    pub role: Role,
+    pub address: Vec<IpNet>,
+    pub endpoint: Option<String>,
+    // time of the node's latest creation in the audit trail
+    pub created_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub tags: Vec<String>,
+    // latest handshake of the peer with the running interface
+    pub last_handshake: Option<i64>,
+}
+
+// rows of the nodes, `handshakes` holds the latest handshake by public key
+pub fn rows(
+    node_list: &[Node],
+    audit_list: &[AuditEvent],
+    handshakes: &HashMap<String, i64>,
+) -> Vec<Row> {
+    node_list
+        .iter()
+        .map(|node| Row {
+            name: node.name().to_string(),
+            role: if node.relay { Role::Relay } else { Role::Leaf },
+            address: node.address.clone().unwrap_or_default(),
+            endpoint: node.endpoint.as_ref().map(ToString::to_string),
+            created_at: audit_list
+                .iter()
+                .rev()
+                .find(|e| e.action == Action::Create && e.node.eq(node.name()))
+                .map(|e| e.created_at),
+            expires_at: node.expires_at,
+            tags: node.tags.clone().unwrap_or_default(),
+            last_handshake: node
+                .public_key
+                .as_ref()
+                .and_then(|key| handshakes.get(key))
+                .copied(),
+        })
+        .collect()
+}
+
+// stable sort by the key, rows without a value come last
+pub fn sort(rows: &mut [Row], key: SortKey) {
+    match key {
+        SortKey::Name => rows.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortKey::Role => rows.sort_by_key(|r| r.role),
+        SortKey::Address => rows.sort_by_key(|r| (r.address.is_empty(), r.address.clone())),
+        SortKey::CreatedAt => rows.sort_by_key(|r| (r.created_at.is_none(), r.created_at)),
+        SortKey::Handshake => {
+            rows.sort_by_key(|r| (r.last_handshake.is_none(), r.last_handshake.map(|t| -t)))
+        }
+    }
+}
+
+const HEADER: [&str; 8] = [
+    "name",
+    "role",
+    "address",
+    "endpoint",
+    "created-at",
+    "expires-at",
+    "tags",
+    "last-handshake",
+];
+
+// fields of the row in the order of the header
+fn fields(row: &Row, time: impl Fn(i64) -> String) -> [String; 8] {
+    let address = row
+        .address
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>();
+    let time = |t: Option<i64>| t.map(&time).unwrap_or_default();
+    [
+        row.name.clone(),
+        row.role.as_str().to_string(),
+        address.join(","),
+        row.endpoint.clone().unwrap_or_default(),
+        time(row.created_at),
+        time(row.expires_at),
+        row.tags.join(","),
+        time(row.last_handshake),
+    ]
+}
+
+// a field quoted when it holds a separator, a quote or a line break (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn render(rows: &[Row], output: Output) -> anyhow::Result<String> {
+    Ok(match output {
+        Output::Json => serde_json::to_string_pretty(rows)? + "\n",
+        Output::Yaml => serde_yaml::to_string(rows)?,
+        Output::Csv => std::iter::once(HEADER.map(ToString::to_string))
+            .chain(rows.iter().map(|r| fields(r, |t| t.to_string())))
+            .map(|fields| {
+                fields
+                    .iter()
+                    .map(|f| csv_field(f))
+                    .collect::<Vec<String>>()
+                    .join(",")
+                    + "\n"
+            })
+            .collect(),
+        Output::Table => {
+            let lines = std::iter::once(HEADER.map(str::to_uppercase))
+                .chain(rows.iter().map(|r| fields(r, clock::format)))
+                .collect::<Vec<[String; 8]>>();
+            let mut widths = [0; 8];
+            for fields in &lines {
+                for (width, field) in widths.iter_mut().zip(fields) {
+                    *width = (*width).max(field.chars().count());
+                }
+            }
+            lines
+                .iter()
+                .map(|fields| {
+                    let line = fields
+                        .iter()
+                        .zip(widths)
+                        .map(|(field, width)| format!("{:<width$}", field, width = width))
+                        .collect::<Vec<String>>()
+                        .join("  ");
+                    line.trim_end().to_string() + "\n"
+                })
+                .collect()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::node;
+
+    fn node_list() -> Vec<Node> {
+        let mut relay = node("wg0", true, "10.66.66.1/24");
+        relay.with_endpoint(Some("relay.example.com:51820".parse().unwrap()));
+        let mut laptop = node("laptop", false, "10.66.66.3/24");
+        laptop.with_tags(Some(vec!["dev".to_string(), "ops".to_string()]));
+        vec![relay, laptop, node("phone", false, "10.66.66.2/24")]
+    }
+
+    fn audit_list() -> Vec<AuditEvent> {
+        let event = |action, node: &str, created_at| {
+            let mut event = AuditEvent::new(action, node, None, None);
+            event.created_at = created_at;
+            event
+        };
+        vec![
+            event(Action::Create, "wg0", 100),
+            event(Action::Create, "laptop", 200),
+            event(Action::Update, "laptop", 300),
+            event(Action::Delete, "laptop", 400),
+            event(Action::Create, "laptop", 500),
+        ]
+    }
+
+    fn rows() -> Vec<Row> {
+        let handshakes = HashMap::from([
+            ("laptop-public".to_string(), 1_000),
+            ("phone-public".to_string(), 2_000),
+        ]);
+        super::rows(&node_list(), &audit_list(), &handshakes)
+    }
+
+    #[test]
+    fn test_rows() {
+        let rows = rows();
+        assert_eq!(rows[0].role, Role::Relay);
+        assert_eq!(rows[0].endpoint.as_deref(), Some("relay.example.com:51820"));
+        assert_eq!(rows[0].last_handshake, None);
+        // a node added again was created by its latest creation
+        assert_eq!(rows[1].created_at, Some(500));
+        assert_eq!(rows[1].last_handshake, Some(1_000));
+        assert_eq!(rows[2].created_at, None);
+
+        let names = |rows: &[Row]| rows.iter().map(|r| r.name.clone()).collect::<Vec<String>>();
+        let mut sorted = rows.clone();
+        sort(&mut sorted, SortKey::Name);
+        assert_eq!(names(&sorted), ["laptop", "phone", "wg0"]);
+        sort(&mut sorted, SortKey::Address);
+        assert_eq!(names(&sorted), ["wg0", "phone", "laptop"]);
+        sort(&mut sorted, SortKey::Handshake);
+        assert_eq!(names(&sorted), ["phone", "laptop", "wg0"]);
+        sort(&mut sorted, SortKey::CreatedAt);
+        assert_eq!(names(&sorted), ["wg0", "laptop", "phone"]);
+        sort(&mut sorted, SortKey::Role);
+        assert_eq!(names(&sorted), ["wg0", "laptop", "phone"]);
+    }
+
+    #[test]
+    fn test_render() {
+        let rows = rows();
+        let csv = render(&rows, Output::Csv).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<&str>>(),
+            [
+                "name,role,address,endpoint,created-at,expires-at,tags,last-handshake",
+                "wg0,relay,10.66.66.1/24,relay.example.com:51820,100,,,",
+                "laptop,leaf,10.66.66.3/24,,500,,\"dev,ops\",1000",
+                "phone,leaf,10.66.66.2/24,,,,,2000",
+            ]
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&rows, Output::Json).unwrap()).unwrap();
+        assert_eq!(json[1]["name"], "laptop");
+        assert_eq!(json[1]["role"], "leaf");
+        assert_eq!(json[1]["tags"], serde_json::json!(["dev", "ops"]));
+        assert_eq!(json[1]["last_handshake"], 1_000);
+        let yaml = render(&rows, Output::Yaml).unwrap();
+        assert!(yaml.starts_with("- name: wg0\n  role: relay\n"));
+
+        let table = render(&rows, Output::Table).unwrap();
+        let lines = table.lines().collect::<Vec<&str>>();
+        assert!(lines[0].starts_with("NAME    ROLE   ADDRESS        ENDPOINT"));
+        assert!(lines[2].starts_with("laptop  leaf   10.66.66.3/24"));
+        assert!(lines[2].contains(&clock::format(500)));
+        assert!(lines.iter().all(|line| line.eq(&line.trim_end())));
+    }
+}
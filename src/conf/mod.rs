@@ -1,24 +1,57 @@
 extern crate ipnet;
 
-use anyhow::{anyhow, Context};
+use anyhow::Context;
 use async_trait::async_trait;
 
-use crate::conf::models::WireGuard;
-use crate::model::endpoint::{Interface, Peer};
+use crate::clock::{Clock, SystemClock};
+use crate::conf::backup::{Change, NetworkState};
+use crate::conf::cipher::Secret;
+use crate::conf::models::{WireGuard, WireGuardFile};
+use crate::conf::sqlite::SqliteStore;
+use crate::model::audit::{Action, AuditEvent};
+use crate::model::network::Network;
 use crate::model::Node;
-use std::ops::{DerefMut, Not};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, MutexGuard};
 
+pub mod backup;
+pub mod cipher;
+pub mod export;
+pub mod listing;
 pub mod models;
+pub mod render;
+pub mod spec;
+pub mod sqlite;
 
+const YAML_FILE: &str = "wgsdc.yaml";
+
+// node storage, scoped to the network it was opened with; every change of a node
+// is recorded in the audit trail along with it
 #[async_trait]
-pub trait NodeOpt: Sized {
+pub trait NodeOpt: Send {
+    // the network of the node list
+    async fn network(&mut self) -> anyhow::Result<Network>;
+    // create a network, an existing network with the same name is replaced
+    async fn push_network(&mut self, network: Network) -> anyhow::Result<()>;
+    // get all networks of the storage
+    async fn list_networks(&mut self) -> anyhow::Result<Vec<Network>>;
+    // all networks of the storage and their nodes
+    async fn state(&mut self) -> anyhow::Result<Vec<NetworkState>>;
+    // replace all networks and nodes of the storage at once
+    async fn restore(&mut self, network_list: Vec<NetworkState>) -> anyhow::Result<()>;
+    // bring a network to the state at once, only the nodes that differ are written and
+    // the other networks are left alone
+    async fn apply(&mut self, state: NetworkState) -> anyhow::Result<()>;
     // get node by name
     async fn get_by_name(&mut self, node_name: &str) -> anyhow::Result<Node>;
-    // push node to list
+    // push node to list, a node with the same name is updated
     async fn push(&mut self, node: Node) -> anyhow::Result<()>;
+    // write the current and previous key pairs of the node, push keeps the existing keys
+    async fn set_keys(&mut self, node: &Node) -> anyhow::Result<()>;
+    // audit trail of the node changes of the network, oldest first
+    async fn audit_list(&mut self) -> anyhow::Result<Vec<AuditEvent>>;
     // get from node list(by relay)
     async fn list_by_relay(&mut self, relay: bool) -> anyhow::Result<Vec<Node>>;
     // get from node list
@@ -27,6 +60,16 @@ pub trait NodeOpt: Sized {
     async fn remove_all(&mut self) -> anyhow::Result<()>;
     // remove node from list
     async fn remove_by_name(&mut self, node_name: &str) -> anyhow::Result<()>;
+    // replace the settings of the node and rename it at once, the keys are kept; the leaves
+    // of a relay and the ACL follow a new name
+    async fn edit(
+        &mut self,
+        node_name: &str,
+        node: Node,
+        new_name: Option<&str>,
+    ) -> anyhow::Result<()>;
+    // remove the named nodes at once, nothing is removed unless every node exists
+    async fn remove_by_names(&mut self, node_names: &[String]) -> anyhow::Result<()>;
     // remove node from list
     async fn remove(&mut self, index: usize) -> anyhow::Result<()>;
     // clear
@@ -40,212 +83,880 @@ pub trait AsyncTryFrom<T>: Sized {
     async fn try_from(_: T) -> Result<Self, Self::Error>;
 }
 
+// storage backend of the node list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Store {
+    // sqlite database
+    Sqlite,
+    // yaml file
+    Yaml,
+}
+
+// open the node storage of a network in the configuration directory, it is created if it does not exist;
+// the secret unlocks an encrypted sqlite store, the clock stamps the audit events
+pub async fn open(
+    store: Store,
+    config_dir: &Path,
+    network: &str,
+    secret: Option<&Secret>,
+    clock: Arc<dyn Clock>,
+) -> anyhow::Result<Box<dyn NodeOpt>> {
+    Ok(match store {
+        Store::Sqlite => {
+            let mut store = SqliteStore::open(config_dir, network).await?;
+            store.unlock(secret).await?;
+            store.with_clock(clock);
+            Box::new(store)
+        }
+        Store::Yaml => {
+            let mut store = Configuration::load(config_dir.join(YAML_FILE), network).await?;
+            store.with_clock(clock);
+            Box::new(store)
+        }
+    })
+}
+
+// yaml file storage, the file is read before and written back after every change
 pub struct Configuration {
     path: PathBuf,
-    wireguard: Arc<Mutex<WireGuard>>,
+    network: String,
+    wireguard: Arc<Mutex<WireGuardFile>>,
+    // time of the recorded audit events
+    clock: Arc<dyn Clock>,
 }
 
 impl Configuration {
-    async fn init(config_path: Option<PathBuf>) -> anyhow::Result<()> {
-        if let Some(config_path) = config_path {
-            if !config_path.exists() {
-                return Err(anyhow!("config path:'{}' not exists!", config_path.display()));
-            }
-            if config_path.is_file() {
-                return Err(anyhow!("The configuration path must be a directory!"));
+    // load the configuration file, the file does not have to exist
+    pub async fn load(path: PathBuf, network: &str) -> anyhow::Result<Self> {
+        let configuration = Self {
+            path,
+            network: network.to_string(),
+            wireguard: Arc::new(Mutex::new(WireGuardFile::default())),
+            clock: Arc::new(SystemClock),
+        };
+        drop(configuration.reload().await?);
+        Ok(configuration)
+    }
+
+    pub fn with_clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    // pick up the changes of other processes
+    async fn reload(&self) -> anyhow::Result<MutexGuard<'_, WireGuardFile>> {
+        let mut wireguard = self.wireguard.lock().await;
+        if self.path.is_file() {
+            let content = tokio::fs::read_to_string(&self.path).await?;
+            *wireguard = serde_yaml::from_str(&content)
+                .with_context(|| format!("invalid configuration file: {}", self.path.display()))?;
+        }
+        Ok(wireguard)
+    }
+
+    // run on the node list of the network
+    async fn with_network<T>(
+        &self,
+        f: impl FnOnce(&mut WireGuard) -> anyhow::Result<T> + Send,
+    ) -> anyhow::Result<T> {
+        let mut wireguard = self.reload().await?;
+        let network = wireguard
+            .network_list
+            .iter_mut()
+            .find(|w| w.network.name.eq(&self.network))
+            .with_context(|| format!("network does not exist: {}", self.network))?;
+        f(network)
+    }
+
+    // run a change on the node list of the network, the events it returns are
+    // appended to the audit trail and saved with it
+    async fn change(
+        &self,
+        f: impl FnOnce(&mut WireGuard) -> anyhow::Result<Vec<AuditEvent>> + Send,
+    ) -> anyhow::Result<()> {
+        {
+            let mut wireguard = self.reload().await?;
+            let network = wireguard
+                .network_list
+                .iter_mut()
+                .find(|w| w.network.name.eq(&self.network))
+                .with_context(|| format!("network does not exist: {}", self.network))?;
+            let events = f(network)?;
+            wireguard
+                .audit_list
+                .extend(events.into_iter().map(|mut event| {
+                    if event.network.is_empty() {
+                        event.network = self.network.clone();
+                    }
+                    event.created_at = self.clock.now();
+                    event
+                }));
+        }
+        self.save().await
+    }
+
+    // write the networks back, through a temporary file so it is never truncated
+    async fn save(&self) -> anyhow::Result<()> {
+        let content = serde_yaml::to_string(&*self.wireguard.lock().await)?;
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp = self.path.with_extension("yaml.tmp");
+        // the file holds the private keys, only readable by its owner
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options
+            .open(&temp)
+            .await?
+            .write_all(content.as_bytes())
+            .await?;
+        tokio::fs::rename(&temp, &self.path)
+            .await
+            .with_context(|| format!("failed to save {}", self.path.display()))
+    }
+}
+
+#[async_trait]
+impl NodeOpt for Configuration {
+    async fn network(&mut self) -> anyhow::Result<Network> {
+        self.with_network(|w| Ok(w.network.clone())).await
+    }
+
+    async fn push_network(&mut self, network: Network) -> anyhow::Result<()> {
+        {
+            let mut wireguard = self.reload().await?;
+            match wireguard
+                .network_list
+                .iter_mut()
+                .find(|w| w.network.name.eq(&network.name))
+            {
+                Some(w) => w.network = network,
+                None => wireguard.network_list.push(WireGuard::new(network)),
             }
-            crate::db::initialize_database(config_path.join("db")).await?;
         }
-        crate::sudo()?;
-        let home_dir = dirs::home_dir();
-
-        if let Some(home_dir) = home_dir {
-            if home_dir.is_dir() {
-                let config_dir = home_dir.join(".wgsdc");
-                if !config_dir.exists() {
-                    tokio::fs::create_dir_all(&config_dir).await?;
-                    crate::db::initialize_database(config_dir.join("db")).await?
-                }
+        self.save().await
+    }
+
+    async fn list_networks(&mut self) -> anyhow::Result<Vec<Network>> {
+        let wireguard = self.reload().await?;
+        Ok(wireguard
+            .network_list
+            .iter()
+            .map(|w| w.network.clone())
+            .collect())
+    }
+
+    async fn state(&mut self) -> anyhow::Result<Vec<NetworkState>> {
+        let wireguard = self.reload().await?;
+        Ok(wireguard
+            .network_list
+            .iter()
+            .map(WireGuard::state)
+            .collect())
+    }
+
+    async fn restore(&mut self, network_list: Vec<NetworkState>) -> anyhow::Result<()> {
+        {
+            let mut wireguard = self.reload().await?;
+            let current = wireguard
+                .network_list
+                .iter()
+                .map(WireGuard::state)
+                .collect::<Vec<NetworkState>>();
+            let events = backup::diff(&current, &network_list)
+                .iter()
+                .filter_map(Change::audit_event)
+                .collect::<Vec<AuditEvent>>();
+            wireguard.network_list = network_list.into_iter().map(WireGuard::from).collect();
+            let now = self.clock.now();
+            wireguard
+                .audit_list
+                .extend(events.into_iter().map(|mut event| {
+                    event.created_at = now;
+                    event
+                }));
+        }
+        self.save().await
+    }
+
+    async fn apply(&mut self, state: NetworkState) -> anyhow::Result<()> {
+        {
+            let mut wireguard = self.reload().await?;
+            let position = wireguard
+                .network_list
+                .iter()
+                .position(|w| w.network.name.eq(&state.network.name));
+            let current = position
+                .map(|i| wireguard.network_list[i].state())
+                .into_iter()
+                .collect::<Vec<NetworkState>>();
+            let events = backup::diff(&current, std::slice::from_ref(&state))
+                .iter()
+                .filter_map(Change::audit_event)
+                .collect::<Vec<AuditEvent>>();
+            let network = WireGuard::from(state);
+            match position {
+                Some(i) => wireguard.network_list[i] = network,
+                None => wireguard.network_list.push(network),
             }
+            let now = self.clock.now();
+            wireguard
+                .audit_list
+                .extend(events.into_iter().map(|mut event| {
+                    event.created_at = now;
+                    event
+                }));
         }
-        Ok(())
+        self.save().await
     }
 
-    // Non-relay node configuration
-    // pub async fn get_peer_config(&mut self, name: &str) -> anyhow::Result<String> {
-    //     // node
-    //     let node = self.get_by_name(name).await?;
-
-    //     // is relay node
-    //     if node.relay {
-    //         return Err(anyhow::anyhow!("This function does not support"));
-    //     }
-
-    //     // node relay list
-    //     let mut node_relay_list = self.list_by_relay(node.relay).await?;
-
-    //     // convert
-    //     for v in &mut node_relay_list {
-    //         // v.allowed_ips = node.endpoint_allowed_ips.clone();
-    //         v.persistent_keepalive = node.persistent_keepalive;
-    //     }
+    async fn get_by_name(&mut self, node_name: &str) -> anyhow::Result<Node> {
+        self.with_network(|w| w.get_by_name(node_name)).await
+    }
 
-    //     let mut lines = String::new();
-    //     // node name
-    //     lines.push_str(&format!("# {}\n", node.name()));
+    async fn push(&mut self, node: Node) -> anyhow::Result<()> {
+        self.change(|w| {
+            let name = node.name().to_string();
+            let before = w.get_by_name(&name).ok();
+            w.push(node)?;
+            let after = w.get_by_name(&name)?;
+            let action = match before {
+                Some(_) => Action::Update,
+                None => Action::Create,
+            };
+            Ok(vec![AuditEvent::new(
+                action,
+                &name,
+                before.as_ref(),
+                Some(&after),
+            )])
+        })
+        .await
+    }
 
-    //     let interface = Interface::from(node);
+    async fn set_keys(&mut self, node: &Node) -> anyhow::Result<()> {
+        self.change(|w| {
+            let before = w.get_by_name(node.name())?;
+            w.set_keys(node)?;
+            Ok(vec![AuditEvent::new(
+                Action::Rotate,
+                node.name(),
+                Some(&before),
+                Some(node),
+            )])
+        })
+        .await
+    }
 
-    //     // Interface section begins
-    //     lines.push_str("[Interface]\n");
+    async fn audit_list(&mut self) -> anyhow::Result<Vec<AuditEvent>> {
+        let wireguard = self.reload().await?;
+        Ok(wireguard
+            .audit_list
+            .iter()
+            .filter(|e| e.network.eq(&self.network))
+            .cloned()
+            .collect())
+    }
 
-    //     // Interface Private key
-    //     lines.push_str(&format!("PrivateKey = {}\n", interface.private_key()?));
+    async fn list_by_relay(&mut self, relay: bool) -> anyhow::Result<Vec<Node>> {
+        self.with_network(|w| w.list_by_relay(relay)).await
+    }
+
+    async fn list(&mut self) -> anyhow::Result<Vec<Node>> {
+        self.with_network(|w| w.list()).await
+    }
 
-    //     // Interface address
-    //     lines.push_str(&format!("Address = {}\n", interface.address()?));
+    async fn remove_all(&mut self) -> anyhow::Result<()> {
+        self.change(|w| {
+            let node_list = w.list()?;
+            w.remove_all()?;
+            Ok(deleted(&node_list))
+        })
+        .await
+    }
 
-    //     // Interface listen port, if any
-    //     if let Some(listen_port) = interface.listen_port() {
-    //         lines.push_str(&format!("ListenPort = {}\n", listen_port));
-    //     }
+    async fn remove_by_name(&mut self, node_name: &str) -> anyhow::Result<()> {
+        self.change(|w| {
+            let node = w.get_by_name(node_name);
+            w.remove_by_name(node_name)?;
+            Ok(deleted(&node.into_iter().collect::<Vec<Node>>()))
+        })
+        .await
+    }
 
-    //     // Interface MTU, if any
-    //     if let Some(mtu) = interface.mtu() {
-    //         lines.push_str(&format!("MTU = {}\n", mtu));
-    //     }
+    async fn edit(
+        &mut self,
+        node_name: &str,
+        node: Node,
+        new_name: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.change(|w| {
+            let before = w.get_by_name(node_name)?;
+            let name = new_name.unwrap_or(node_name);
+            w.edit(node_name, node, new_name)?;
+            let after = w.get_by_name(name)?;
+            Ok(vec![AuditEvent::new(
+                Action::Update,
+                name,
+                Some(&before),
+                Some(&after),
+            )])
+        })
+        .await
+    }
 
-    //     // Interface PreUp, if any
-    //     if let Some(pre_up) = interface.pre_up() {
-    //         lines.push_str(&format!("PreUp = {}\n", pre_up));
-    //     }
-
-    //     // Interface PostUp, if any
-    //     if let Some(post_up) = interface.post_up() {
-    //         lines.push_str(&format!("PostUp = {}\n", post_up));
-    //     }
-
-    //     // Interface PreDown, if any
-    //     if let Some(pre_down) = interface.pre_down() {
-    //         lines.push_str(&format!("PreDown = {}\n", pre_down));
-    //     }
-
-    //     // Interface PostDown, if any
-    //     if let Some(post_down) = interface.post_down() {
-    //         lines.push_str(&format!("PostDown = {}\n", post_down));
-    //     }
+    async fn remove_by_names(&mut self, node_names: &[String]) -> anyhow::Result<()> {
+        self.change(|w| {
+            let mut node_list = Vec::new();
+            for node_name in node_names {
+                node_list.push(w.get_by_name(node_name)?);
+                w.remove_by_name(node_name)?;
+            }
+            Ok(deleted(&node_list))
+        })
+        .await
+    }
 
-    //     // ------------------------------Peer----------------------------------
-    //     for node in node_relay_list {
-    //         // Peer name
-    //         lines.push_str(&format!("# {}\n", node.name()));
+    async fn remove(&mut self, index: usize) -> anyhow::Result<()> {
+        self.change(|w| {
+            let node = w.list()?.into_iter().nth(index);
+            w.remove(index)?;
+            Ok(deleted(&node.into_iter().collect::<Vec<Node>>()))
+        })
+        .await
+    }
 
-    //         let peer = Peer::from(node);
+    async fn clear(&mut self) -> anyhow::Result<()> {
+        self.change(|w| {
+            let node_list = w.list()?;
+            w.clear()?;
+            Ok(deleted(&node_list))
+        })
+        .await
+    }
+}
 
-    //         // Peer section begins
-    //         lines.push_str("[Peer]\n");
-
-    //         // Peer Public key
-    //         lines.push_str(&format!("PublicKey = {}\n", peer.public_key()?));
-
-    //         // Peer Allowed IPs
-    //         lines.push_str(&format!("AllowedIPs = {}\n", peer.allowed_ips()?));
-
-    //         // Peer Persistent Keepalive, if any
-    //         if let Some(keepalive) = peer.persistent_keepalive() {
-    //             lines.push_str(&format!("PersistentKeepalive = {}\n", keepalive));
-    //         }
-
-    //         // Peer Endpoint, if any
-    //         if let Some(endpoint) = peer.endpoint() {
-    //             lines.push_str(&format!("Endpoint = {}\n", endpoint));
-    //         }
-    //     }
-
-    //     Ok(lines)
-    // }
-
-    // Relay node configuration
-    // pub async fn get_relay_peer_config(&mut self, node_name: &str) -> anyhow::Result<String> {
-    //     // get node
-    //     let config_relay_node = self.get_by_name(node_name).await?;
-
-    //     // convert to peer
-    //     let node_list = self
-    //         .list()
-    //         .await?
-    //         .iter()
-    //         .filter(|n| n.name().ne(node_name))
-    //         .map(|v| v.clone())
-    //         .collect::<Vec<Node>>();
-
-    //     let mut lines = String::new();
-    //     // node name
-    //     lines.push_str(&format!("# {}\n", config_relay_node.name()));
-
-    //     let interface = Interface::from(config_relay_node);
-
-    //     // Interface section begins
-    //     lines.push_str("[Interface]\n");
-
-    //     // Interface Private key
-    //     lines.push_str(&format!("PrivateKey = {}\n", interface.private_key()?));
-
-    //     // Interface address
-    //     lines.push_str(&format!("Address = {}\n", interface.address()?));
-
-    //     // Interface listen port, if any
-    //     if let Some(listen_port) = interface.listen_port() {
-    //         lines.push_str(&format!("ListenPort = {}\n", listen_port));
-    //     }
-
-    //     // MTU, if any
-    //     if let Some(mtu) = interface.mtu() {
-    //         lines.push_str(&format!("MTU = {}\n", mtu));
-    //     }
-
-    //     // PreUp, if any
-    //     if let Some(pre_up) = interface.pre_up() {
-    //         lines.push_str(&format!("PreUp = {}\n", pre_up));
-    //     }
-
-    //     // PostUp, if any
-    //     if let Some(post_up) = interface.post_up() {
-    //         lines.push_str(&format!("PostUp = {}\n", post_up));
-    //     }
-
-    //     // PreDown, if any
-    //     if let Some(pre_down) = interface.pre_down() {
-    //         lines.push_str(&format!("PreDown = {}\n", pre_down));
-    //     }
-
-    //     // PostDown, if any
-    //     if let Some(post_down) = interface.post_down() {
-    //         lines.push_str(&format!("PostDown = {}\n", post_down));
-    //     }
-
-    //     // ------------------------------Peer----------------------------------
-    //     for node in node_list {
-    //         // node name
-    //         lines.push_str(&format!("# {}\n", node.name()));
-
-    //         let peer = Peer::from(node);
-
-    //         // Peer section begins
-    //         lines.push_str("[Peer]\n");
-
-    //         // Peer Public key
-    //         lines.push_str(&format!("PublicKey = {}\n", peer.public_key()?));
-
-    //         // Peer Allowed IPs
-    //         lines.push_str(&format!("AllowedIPs = {}\n", peer.allowed_ips()?));
+// delete events of the removed nodes
+fn deleted(node_list: &[Node]) -> Vec<AuditEvent> {
+    node_list
+        .iter()
+        .map(|node| AuditEvent::new(Action::Delete, node.name(), Some(node), None))
+        .collect()
+}
 
-    //         // Peer Persistent Keepalive
-    //         if let Some(keepalive) = peer.persistent_keepalive() {
-    //             lines.push_str(&format!("PersistentKeepalive = {}\n", keepalive));
-    //         }
-    //     }
-    //     return Ok(lines);
-    // }
-
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::model::audit::Action;
+    use crate::model::test_util::{self, network};
+    use crate::model::topology::Topology;
+
+    // time of the audit events recorded by the stores under test
+    const NOW: i64 = 1_700_000_000;
+
+    // node with a keepalive that a push without one keeps
+    fn node(name: &str, relay: bool, address: &str) -> Node {
+        let mut node = test_util::node(name, relay, address);
+        node.with_persistent_keepalive(Some(25));
+        node
+    }
+
+    fn names(node_list: &[Node]) -> Vec<&str> {
+        node_list.iter().map(|n| n.name()).collect()
+    }
+
+    async fn relay_and_laptop(store: &mut dyn NodeOpt, prefix: &str) {
+        store
+            .push(node("wg0", true, &format!("{}.1/24", prefix)))
+            .await
+            .unwrap();
+        store
+            .push(node("laptop", false, &format!("{}.2/24", prefix)))
+            .await
+            .unwrap();
+    }
+
+    // behaviour every storage backend has to share, `store` and `office` are
+    // two storages of the same backend scoped to different networks
+    async fn conformance(store: &mut dyn NodeOpt, office: &mut dyn NodeOpt) {
+        // the network comes first
+        assert!(store.network().await.is_err());
+        assert!(store.list().await.is_err());
+        assert!(store
+            .push(node("wg0", true, "10.66.66.1/24"))
+            .await
+            .is_err());
+
+        let mut home = network("home", "10.66.66.0/24", "wg0");
+        store.push_network(home.clone()).await.unwrap();
+        assert_eq!(store.network().await.unwrap(), home);
+        home.dns = Some(vec!["1.1.1.1".parse().unwrap()]);
+        home.listen_port = Some(51820);
+        home.topology = Topology::Mesh;
+        home.cidr_v6 = Some("fd00:66:66::/64".parse().unwrap());
+        store.push_network(home.clone()).await.unwrap();
+        assert_eq!(store.network().await.unwrap(), home);
+        assert!(store.list().await.unwrap().is_empty());
+
+        // the relay comes first
+        let err = store.push(node("laptop", false, "10.66.66.2/24")).await;
+        assert!(err.is_err());
+
+        store
+            .push(node("wg0", true, "10.66.66.1/24"))
+            .await
+            .unwrap();
+        store
+            .push(node("laptop", false, "10.66.66.2/24"))
+            .await
+            .unwrap();
+        store
+            .push(node("phone", false, "10.66.66.3/24"))
+            .await
+            .unwrap();
+        assert_eq!(
+            names(&store.list().await.unwrap()),
+            ["wg0", "laptop", "phone"]
+        );
+        assert_eq!(names(&store.list_by_relay(true).await.unwrap()), ["wg0"]);
+        assert_eq!(
+            names(&store.list_by_relay(false).await.unwrap()),
+            ["laptop", "phone"]
+        );
+
+        let laptop = store.get_by_name("laptop").await.unwrap();
+        assert_eq!(laptop.address, Some(vec!["10.66.66.2/24".parse().unwrap()]));
+        assert_eq!(laptop.public_key.as_deref(), Some("laptop-public"));
+        assert_eq!(laptop.persistent_keepalive, Some(25));
+        assert!(store.get_by_name("tablet").await.is_err());
+
+        // pushing an existing node updates it, the keys are kept
+        let mut change = node("laptop", false, "10.66.66.4/24");
+        change
+            .with_public_key(Some("other-public".to_string()))
+            .with_persistent_keepalive(None)
+            .with_mtu(Some(1280))
+            .with_expires_at(Some(1_700_000_000))
+            .with_tags(Some(vec!["dev".to_string(), "ops".to_string()]));
+        store.push(change).await.unwrap();
+        let laptop = store.get_by_name("laptop").await.unwrap();
+        assert_eq!(laptop.address, Some(vec!["10.66.66.4/24".parse().unwrap()]));
+        assert_eq!(laptop.public_key.as_deref(), Some("laptop-public"));
+        assert_eq!(laptop.persistent_keepalive, Some(25));
+        assert_eq!(laptop.mtu, Some(1280));
+        assert_eq!(laptop.expires_at, Some(1_700_000_000));
+        assert_eq!(
+            laptop.tags,
+            Some(vec!["dev".to_string(), "ops".to_string()])
+        );
+        assert_eq!(store.list().await.unwrap().len(), 3);
+
+        // the keys only change by a rotation
+        let mut rotated = laptop.clone();
+        rotated.rotate_key("new-public".to_string(), "new-private".to_string(), 100);
+        store.set_keys(&rotated).await.unwrap();
+        let laptop = store.get_by_name("laptop").await.unwrap();
+        assert_eq!(laptop.public_key.as_deref(), Some("new-public"));
+        assert_eq!(laptop.private_key.as_deref(), Some("new-private"));
+        assert_eq!(laptop.previous_public_key.as_deref(), Some("laptop-public"));
+        assert_eq!(laptop.previous_key_expires_at, Some(100));
+        assert_eq!(laptop.mtu, Some(1280));
+        let rotation = store.audit_list().await.unwrap().pop().unwrap();
+        assert_eq!(rotation.network, "home");
+        assert_eq!(rotation.action, Action::Rotate);
+        let after: Node = serde_json::from_str(rotation.after.as_deref().unwrap()).unwrap();
+        assert_eq!(after.public_key.as_deref(), Some("new-public"));
+        assert!(!rotation.after.unwrap().contains("new-private"));
+
+        // a conflicting node is not written
+        let err = store
+            .push(node("tablet", false, "10.66.66.3/24"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "address 10.66.66.3 of phone is also used by tablet"
+        );
+        assert!(store.get_by_name("tablet").await.is_err());
+
+        // the same name can not change its role
+        assert!(store
+            .push(node("laptop", true, "10.66.66.2/24"))
+            .await
+            .is_err());
+
+        store.remove_by_name("laptop").await.unwrap();
+        assert!(store.remove_by_name("laptop").await.is_err());
+        assert_eq!(names(&store.list().await.unwrap()), ["wg0", "phone"]);
+        // a bulk removal of a missing node removes nothing
+        assert!(store
+            .remove_by_names(&["phone".to_string(), "laptop".to_string()])
+            .await
+            .is_err());
+        assert_eq!(names(&store.list().await.unwrap()), ["wg0", "phone"]);
+
+        // an edit replaces the settings and the name at once, the keys and the ACL follow
+        let mut acl = home.clone();
+        acl.acl
+            .rules
+            .push("node:phone -> node:wg0".parse().unwrap());
+        store.push_network(acl).await.unwrap();
+        let events = store.audit_list().await.unwrap().len();
+        let mut tablet = store.get_by_name("phone").await.unwrap();
+        tablet
+            .with_persistent_keepalive(None)
+            .with_mtu(Some(1280))
+            .with_private_key(None);
+        store
+            .edit("phone", tablet.clone(), Some("tablet"))
+            .await
+            .unwrap();
+        assert!(store
+            .edit("tablet", tablet.clone(), Some("wg0"))
+            .await
+            .is_err());
+        assert!(store.edit("phone", tablet, Some("laptop")).await.is_err());
+        assert!(store
+            .edit("tablet", node("tablet", true, "10.66.66.3/24"), None)
+            .await
+            .is_err());
+        assert_eq!(names(&store.list().await.unwrap()), ["wg0", "tablet"]);
+        let tablet = store.get_by_name("tablet").await.unwrap();
+        assert_eq!(tablet.public_key.as_deref(), Some("phone-public"));
+        assert_eq!(tablet.private_key.as_deref(), Some("phone-private"));
+        assert_eq!(
+            (tablet.persistent_keepalive, tablet.mtu),
+            (None, Some(1280))
+        );
+        assert_eq!(
+            store.network().await.unwrap().acl.rules[0].to_string(),
+            "node:tablet -> node:wg0"
+        );
+        let audit_list = store.audit_list().await.unwrap();
+        assert_eq!(audit_list.len(), events + 1);
+        assert_eq!(audit_list[events].node, "tablet");
+        store.push_network(home.clone()).await.unwrap();
+
+        store.remove(1).await.unwrap();
+        assert!(store.remove(1).await.is_err());
+        assert_eq!(names(&store.list().await.unwrap()), ["wg0"]);
+
+        store.remove_all().await.unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+
+        store
+            .push(node("wg0", true, "10.66.66.1/24"))
+            .await
+            .unwrap();
+        store.clear().await.unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+
+        // every change is recorded, the failed ones are not
+        let audit_list = store.audit_list().await.unwrap();
+        assert_eq!(
+            audit_list
+                .iter()
+                .map(|e| (e.action.as_str(), e.node.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("create", "wg0"),
+                ("create", "laptop"),
+                ("create", "phone"),
+                ("update", "laptop"),
+                ("rotate", "laptop"),
+                ("delete", "laptop"),
+                ("update", "tablet"),
+                ("delete", "tablet"),
+                ("delete", "wg0"),
+                ("create", "wg0"),
+                ("delete", "wg0"),
+            ]
+        );
+        assert!(audit_list.iter().all(|e| e.created_at == NOW));
+        let update = &audit_list[3];
+        let before: Node = serde_json::from_str(update.before.as_deref().unwrap()).unwrap();
+        let after: Node = serde_json::from_str(update.after.as_deref().unwrap()).unwrap();
+        assert_eq!((before.mtu, after.mtu), (None, Some(1280)));
+        assert!(audit_list[5].after.is_none());
+
+        // node names are unique within a network only
+        office
+            .push_network(network("office", "10.77.77.0/24", "wg1"))
+            .await
+            .unwrap();
+        relay_and_laptop(store, "10.66.66").await;
+        relay_and_laptop(office, "10.77.77").await;
+        assert_eq!(office.network().await.unwrap().interface, "wg1");
+        assert_eq!(office.audit_list().await.unwrap().len(), 2);
+        assert_eq!(
+            store.get_by_name("laptop").await.unwrap().address,
+            Some(vec!["10.66.66.2/24".parse().unwrap()])
+        );
+        assert_eq!(
+            office.get_by_name("laptop").await.unwrap().address,
+            Some(vec!["10.77.77.2/24".parse().unwrap()])
+        );
+        let networks = store.list_networks().await.unwrap();
+        assert_eq!(
+            networks.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(),
+            ["home", "office"]
+        );
+
+        // address allocation is per network
+        let office_network = office.network().await.unwrap();
+        assert_eq!(
+            office_network
+                .allocate(&office.list().await.unwrap())
+                .unwrap(),
+            ["10.77.77.3/24".parse().unwrap()]
+        );
+
+        // leaves are assigned to a relay of their network
+        let mut tablet = node("tablet", false, "10.77.77.4/24");
+        tablet.with_parent(Some("eu".to_string()));
+        assert!(office.push(tablet.clone()).await.is_err());
+        let mut eu = node("eu", true, "10.77.77.3/24");
+        eu.with_parent(Some("wg0".to_string()));
+        assert!(office.push(eu.clone()).await.is_err());
+        eu.with_parent(None);
+        office.push(eu).await.unwrap();
+        office.push(tablet).await.unwrap();
+        let node_list = office.list().await.unwrap();
+        assert_eq!(node_list[3].name(), "tablet");
+        assert_eq!(node_list[3].parent.as_deref(), Some("eu"));
+        let tablet = office.get_by_name("tablet").await.unwrap();
+        assert_eq!(tablet.parent.as_deref(), Some("eu"));
+        assert!(office.get_by_name("laptop").await.unwrap().parent.is_none());
+
+        // an apply writes the nodes of its network that differ only
+        let mut state = store.state().await.unwrap().remove(0);
+        state.node_list[1].with_mtu(Some(1380));
+        state.node_list.push(node("phone", false, "10.66.66.3/24"));
+        let before = store.audit_list().await.unwrap().len();
+        store.apply(state.clone()).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(store.state().await.unwrap().remove(0)).unwrap(),
+            serde_json::to_value(&state).unwrap()
+        );
+        state.node_list.remove(1);
+        store.apply(state.clone()).await.unwrap();
+        assert_eq!(names(&store.list().await.unwrap()), ["wg0", "phone"]);
+        let events = store.audit_list().await.unwrap();
+        assert_eq!(
+            events[before..]
+                .iter()
+                .map(|e| (e.action, e.node.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (Action::Update, "laptop"),
+                (Action::Create, "phone"),
+                (Action::Delete, "laptop"),
+            ]
+        );
+        assert_eq!(
+            names(&office.list().await.unwrap()),
+            ["wg0", "laptop", "eu", "tablet"]
+        );
+        store.remove_by_name("phone").await.unwrap();
+        store
+            .push(node("laptop", false, "10.66.66.2/24"))
+            .await
+            .unwrap();
+
+        office.remove_all().await.unwrap();
+        assert!(office.list().await.unwrap().is_empty());
+        assert_eq!(names(&store.list().await.unwrap()), ["wg0", "laptop"]);
+
+        // a restore replaces every network and records the node changes
+        let state = store.state().await.unwrap();
+        assert_eq!(state.len(), 2);
+        assert_eq!(state[0].network.name, "home");
+        assert_eq!(
+            state[0].node_list[1].private_key.as_deref(),
+            Some("laptop-private")
+        );
+        let mut restored = state.clone();
+        restored[0].node_list[1].with_mtu(Some(1420));
+        restored[0]
+            .node_list
+            .push(node("tablet", false, "10.66.66.5/24"));
+        restored.pop();
+        let before = store.audit_list().await.unwrap().len();
+        store.restore(restored.clone()).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(store.state().await.unwrap()).unwrap(),
+            serde_json::to_value(&restored).unwrap()
+        );
+        assert_eq!(office.list_networks().await.unwrap().len(), 1);
+        let events = store.audit_list().await.unwrap();
+        assert_eq!(
+            events[before..]
+                .iter()
+                .map(|e| (e.action, e.node.as_str()))
+                .collect::<Vec<_>>(),
+            [(Action::Update, "laptop"), (Action::Create, "tablet")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let db = sea_orm::Database::connect("sqlite::memory:").await.unwrap();
+        crate::db::initialize_table(&db).await.unwrap();
+        conformance(
+            SqliteStore::new(db.clone(), "home").with_clock(Arc::new(FixedClock(NOW))),
+            SqliteStore::new(db, "office").with_clock(Arc::new(FixedClock(NOW))),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_yaml_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(YAML_FILE);
+        conformance(
+            Configuration::load(path.clone(), "home")
+                .await
+                .unwrap()
+                .with_clock(Arc::new(FixedClock(NOW))),
+            Configuration::load(path.clone(), "office")
+                .await
+                .unwrap()
+                .with_clock(Arc::new(FixedClock(NOW))),
+        )
+        .await;
+
+        // changes are persisted to the file
+        let mut store = Configuration::load(path, "home").await.unwrap();
+        assert_eq!(
+            names(&store.list().await.unwrap()),
+            ["wg0", "laptop", "tablet"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_store() {
+        let dir = tempfile::tempdir().unwrap();
+        for store in [Store::Sqlite, Store::Yaml] {
+            let clock = Arc::new(FixedClock(NOW));
+            let mut store = open(store, &dir.path().join("conf"), "home", None, clock)
+                .await
+                .unwrap();
+            store
+                .push_network(network("home", "10.66.66.0/24", "wg0"))
+                .await
+                .unwrap();
+            store
+                .push(node("wg0", true, "10.66.66.1/24"))
+                .await
+                .unwrap();
+            // the events are stamped by the clock the store is opened with
+            let audit_list = store.audit_list().await.unwrap();
+            assert_eq!(audit_list.last().unwrap().created_at, NOW);
+        }
+        assert!(dir.path().join("conf").join(YAML_FILE).is_file());
+        assert!(dir.path().join("conf").join("db").is_file());
+        #[cfg(unix)]
+        for file in [YAML_FILE, "db"] {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = std::fs::metadata(dir.path().join("conf").join(file)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_store() {
+        use crate::conf::cipher::{self, Secret};
+        use crate::db::model::prelude::NodeRelay;
+        use sea_orm::EntityTrait;
+
+        let dir = tempfile::tempdir().unwrap();
+        let passphrase = Secret::Passphrase("correct horse".to_string());
+        let mut store = SqliteStore::open(dir.path(), "home").await.unwrap();
+        store
+            .push_network(network("home", "10.66.66.0/24", "wg0"))
+            .await
+            .unwrap();
+        relay_and_laptop(&mut store, "10.66.66").await;
+        assert_eq!(store.rekey(Some(&passphrase)).await.unwrap(), 2);
+
+        // written encrypted, read decrypted
+        store
+            .push(node("phone", false, "10.66.66.3/24"))
+            .await
+            .unwrap();
+        let db = crate::db::connect(dir.path()).await.unwrap();
+        let rows = NodeRelay::find().all(&db).await.unwrap();
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|r| cipher::is_encrypted(&r.private_key)));
+        assert_eq!(
+            store
+                .get_by_name("phone")
+                .await
+                .unwrap()
+                .private_key
+                .as_deref(),
+            Some("phone-private")
+        );
+
+        // a locked store can not be opened without the passphrase
+        assert!(open(
+            Store::Sqlite,
+            dir.path(),
+            "home",
+            None,
+            Arc::new(FixedClock(NOW))
+        )
+        .await
+        .is_err());
+        let wrong = Secret::Passphrase("battery staple".to_string());
+        assert!(open(
+            Store::Sqlite,
+            dir.path(),
+            "home",
+            Some(&wrong),
+            Arc::new(FixedClock(NOW))
+        )
+        .await
+        .is_err());
+        let mut unlocked = open(
+            Store::Sqlite,
+            dir.path(),
+            "home",
+            Some(&passphrase),
+            Arc::new(FixedClock(NOW)),
+        )
+        .await
+        .unwrap();
+        let laptop = unlocked.get_by_name("laptop").await.unwrap();
+        assert_eq!(laptop.private_key.as_deref(), Some("laptop-private"));
+
+        // the session of the unlocked store
+        let session = Secret::Session(store.cipher().unwrap().session());
+        assert!(open(
+            Store::Sqlite,
+            dir.path(),
+            "home",
+            Some(&session),
+            Arc::new(FixedClock(NOW))
+        )
+        .await
+        .is_ok());
+
+        // back to plaintext
+        assert_eq!(store.rekey(None).await.unwrap(), 3);
+        let rows = NodeRelay::find().all(&db).await.unwrap();
+        assert!(rows.iter().all(|r| !cipher::is_encrypted(&r.private_key)));
+        assert!(open(
+            Store::Sqlite,
+            dir.path(),
+            "home",
+            None,
+            Arc::new(FixedClock(NOW))
+        )
+        .await
+        .is_ok());
+    }
+}
@@ -1,20 +1,53 @@
 use std::ops::Not;
 
-use crate::conf::NodeOpt;
+use anyhow::Context;
 
+use crate::conf::backup::NetworkState;
+use crate::model::audit::AuditEvent;
+use crate::model::network::Network;
+use crate::model::validate;
 use crate::model::Node;
 use serde::{Deserialize, Serialize};
 
+// content of the yaml configuration file
 #[derive(Debug, Default, Serialize, Deserialize)]
-pub(super) struct WireGuard {
+pub(super) struct WireGuardFile {
+    #[serde(default)]
+    pub(super) network_list: Vec<WireGuard>,
+    // audit trail of all networks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) audit_list: Vec<AuditEvent>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct WireGuard {
+    pub(super) network: Network,
     node_list: Option<Vec<Node>>,
 }
 
 impl WireGuard {
+    pub(super) fn new(network: Network) -> Self {
+        Self {
+            network,
+            node_list: None,
+        }
+    }
+
+    pub(super) fn state(&self) -> NetworkState {
+        NetworkState {
+            network: self.network.clone(),
+            node_list: self.node_list.clone().unwrap_or_default(),
+        }
+    }
+
     // replace if not present
-    fn map_set(change: &mut Node, node: Node) {
+    pub(crate) fn map_set(change: &mut Node, node: Node) {
         // node name
         change.with_name(node.name);
+        // node primary relay(peer)
+        if node.parent.is_some() {
+            change.with_parent(node.parent);
+        }
         // node endpoint(peer)
         if node.endpoint.is_some() {
             change.with_endpoint(node.endpoint);
@@ -35,6 +68,10 @@ impl WireGuard {
         if node.allowed_ips.is_some() {
             change.with_allowed_ips(node.allowed_ips);
         }
+        // node advertised routes
+        if node.routes.is_some() {
+            change.with_routes(node.routes);
+        }
         // node endpoint allowed ips(peer)
         if node.endpoint_allowed_ips.is_some() {
             change.with_endpoint_allowed_ips(node.endpoint_allowed_ips);
@@ -71,12 +108,17 @@ impl WireGuard {
         if node.pre_down.is_some() {
             change.with_pre_down(node.pre_down);
         }
+        // node expiry
+        if node.expires_at.is_some() {
+            change.with_expires_at(node.expires_at);
+        }
+        // node tags
+        if node.tags.is_some() {
+            change.with_tags(node.tags);
+        }
     }
-}
 
-#[async_trait::async_trait]
-impl NodeOpt for WireGuard {
-    async fn get_by_name(&mut self, node_name: &str) -> anyhow::Result<Node> {
+    pub(super) fn get_by_name(&mut self, node_name: &str) -> anyhow::Result<Node> {
         let node_list = self.node_list.get_or_insert_with(Vec::new);
         if let Some(index) = node_list.iter().position(|n| n.name().eq(node_name)) {
             let node = node_list.get(index).expect("array out of bounds");
@@ -88,7 +130,7 @@ impl NodeOpt for WireGuard {
         )))
     }
 
-    async fn push(&mut self, node: Node) -> anyhow::Result<()> {
+    pub(super) fn push(&mut self, node: Node) -> anyhow::Result<()> {
         let node_list = self.node_list.get_or_insert_with(Vec::new);
 
         if node.relay.not() {
@@ -98,31 +140,103 @@ impl NodeOpt for WireGuard {
             }
         }
 
-        // duplicate name
-        let repeat_name_count = node_list
-            .iter()
-            .filter(|n| n.name().eq(node.name()))
-            .count();
-        if repeat_name_count > 1 {
-            return Err(anyhow::anyhow!(format!(
-                "Duplicate node {} name",
-                node.name()
-            )));
+        // primary relay
+        if let Some(ref parent) = node.parent {
+            if node.relay {
+                return Err(anyhow::anyhow!(format!(
+                    "peer relay node {} can not have a primary relay",
+                    node.name()
+                )));
+            }
+            if node_list
+                .iter()
+                .any(|n| n.relay && n.name().eq(parent))
+                .not()
+            {
+                return Err(anyhow::anyhow!(format!(
+                    "there is no peer relay node named '{}'",
+                    parent
+                )));
+            }
         }
 
-        if let Some(index) = node_list
+        // the node list after the change, written only without conflicts
+        let name = node.name().to_string();
+        let mut change_list = node_list.clone();
+        if let Some(index) = change_list.iter().position(|n| n.name().eq(node.name())) {
+            // duplicate name
+            if change_list[index].relay.ne(&node.relay) {
+                return Err(anyhow::anyhow!(format!(
+                    "Duplicate node {} name",
+                    node.name()
+                )));
+            }
+            Self::map_set(&mut change_list[index], node);
+        } else {
+            change_list.push(node);
+        }
+        validate::check(&self.network, &change_list, &name)?;
+        *node_list = change_list;
+        Ok(())
+    }
+
+    pub(super) fn set_keys(&mut self, node: &Node) -> anyhow::Result<()> {
+        let change = self
+            .node_list
+            .get_or_insert_with(Vec::new)
+            .iter_mut()
+            .find(|n| n.name().eq(node.name()))
+            .with_context(|| format!("node does not exist: {}", node.name()))?;
+        change
+            .with_public_key(node.public_key.clone())
+            .with_private_key(node.private_key.clone())
+            .with_previous_key(
+                node.previous_public_key.clone(),
+                node.previous_private_key.clone(),
+                node.previous_key_expires_at,
+            );
+        Ok(())
+    }
+
+    // replace the settings of the node, renamed when a new name is given; the keys are
+    // kept, the leaves of a relay and the ACL follow the new name
+    pub(super) fn edit(
+        &mut self,
+        node_name: &str,
+        node: Node,
+        new_name: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let node_list = self.node_list.get_or_insert_with(Vec::new);
+        let index = node_list
             .iter()
-            // node eq
-            .position(|n| n.name().eq(node.name()) && n.relay.eq(&node.relay))
+            .position(|n| n.name().eq(node_name))
+            .with_context(|| format!("node does not exist: {}", node_name))?;
+        let name = new_name.unwrap_or(node_name);
+        if new_name.is_some() && node_list.iter().any(|n| n.name().eq(name)) {
+            anyhow::bail!("node {} already exists", name)
+        }
+        if node_list[index].relay.ne(&node.relay) {
+            anyhow::bail!("node {} can not change its role", node_name)
+        }
+
+        // the node list after the change, written only without conflicts
+        let mut change_list = node_list.clone();
+        change_list[index] = edited(&change_list[index], node, name);
+        for leaf in change_list
+            .iter_mut()
+            .filter(|n| n.parent.as_deref() == Some(node_name))
         {
-            Self::map_set(&mut node_list[index], node);
-        } else {
-            node_list.push(node);
+            leaf.with_parent(Some(name.to_string()));
+        }
+        validate::check(&self.network, &change_list, name)?;
+        *node_list = change_list;
+        if name.ne(node_name) {
+            self.network.acl.rename(node_name, name);
         }
         Ok(())
     }
 
-    async fn list_by_relay(&mut self, relay: bool) -> anyhow::Result<Vec<Node>> {
+    pub(super) fn list_by_relay(&mut self, relay: bool) -> anyhow::Result<Vec<Node>> {
         let vec = self
             .node_list
             .get_or_insert_with(Vec::new)
@@ -133,18 +247,18 @@ impl NodeOpt for WireGuard {
         Ok(vec)
     }
 
-    async fn list(&mut self) -> anyhow::Result<Vec<Node>> {
+    pub(super) fn list(&mut self) -> anyhow::Result<Vec<Node>> {
         Ok(self.node_list.get_or_insert_with(Vec::new).clone())
     }
 
-    async fn remove_all(&mut self) -> anyhow::Result<()> {
+    pub(super) fn remove_all(&mut self) -> anyhow::Result<()> {
         if let Some(node_list) = self.node_list.as_mut() {
             node_list.clear();
         }
         Ok(())
     }
 
-    async fn remove_by_name(&mut self, node_name: &str) -> anyhow::Result<()> {
+    pub(super) fn remove_by_name(&mut self, node_name: &str) -> anyhow::Result<()> {
         if let Some(node_list) = self.node_list.as_mut() {
             if let Some(index) = node_list.iter().position(|n| n.name().eq(node_name)) {
                 node_list.remove(index);
@@ -157,7 +271,7 @@ impl NodeOpt for WireGuard {
         )))
     }
 
-    async fn remove(&mut self, index: usize) -> anyhow::Result<()> {
+    pub(super) fn remove(&mut self, index: usize) -> anyhow::Result<()> {
         if let Some(node_list) = self.node_list.as_mut() {
             if index >= node_list.len() {
                 return Err(anyhow::anyhow!(format!(
@@ -170,8 +284,32 @@ impl NodeOpt for WireGuard {
         Ok(())
     }
 
-    async fn clear(&mut self) -> anyhow::Result<()> {
+    pub(super) fn clear(&mut self) -> anyhow::Result<()> {
         self.node_list = None;
         Ok(())
     }
 }
+
+// the node with the settings of the edit and the keys it already has
+pub(super) fn edited(before: &Node, node: Node, name: &str) -> Node {
+    let mut after = node;
+    after
+        .with_name(Some(name.to_string()))
+        .with_public_key(before.public_key.clone())
+        .with_private_key(before.private_key.clone())
+        .with_previous_key(
+            before.previous_public_key.clone(),
+            before.previous_private_key.clone(),
+            before.previous_key_expires_at,
+        );
+    after
+}
+
+impl From<NetworkState> for WireGuard {
+    fn from(state: NetworkState) -> Self {
+        Self {
+            network: state.network,
+            node_list: Some(state.node_list),
+        }
+    }
+}
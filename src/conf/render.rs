@@ -0,0 +1,418 @@
+use anyhow::Context;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::model::endpoint::{Interface, Peer};
+use crate::model::network::Network;
+use crate::model::Node;
+use crate::model::{hook, topology};
+
+// one line per hook, like the repeated keys wg-quick runs in order
+fn push_hooks(lines: &mut String, key: &str, hook: Option<&str>) {
+    for hook in hook.into_iter().flat_map(str::lines).map(str::trim) {
+        if !hook.is_empty() {
+            lines.push_str(&format!("{} = {}\n", key, hook));
+        }
+    }
+}
+
+// [Interface] section of a node
+fn push_interface(
+    lines: &mut String,
+    network: &Network,
+    mut node: Node,
+    dns: Option<&[IpAddr]>,
+) -> anyhow::Result<()> {
+    // node name
+    lines.push_str(&format!("# {}\n", node.name()));
+
+    // hook templates of the node
+    hook::render_hooks(network, &mut node)?;
+
+    let interface = Interface::from(node);
+
+    // Interface section begins
+    lines.push_str("[Interface]\n");
+
+    // Interface Private key
+    lines.push_str(&format!("PrivateKey = {}\n", interface.private_key()?));
+
+    // Interface address
+    lines.push_str(&format!("Address = {}\n", interface.address()?));
+
+    // Interface DNS, if any
+    if let Some(dns) = dns.filter(|dns| !dns.is_empty()) {
+        let dns = dns
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+        lines.push_str(&format!("DNS = {}\n", dns));
+    }
+
+    // Interface listen port, if any
+    if let Some(listen_port) = interface.listen_port() {
+        lines.push_str(&format!("ListenPort = {}\n", listen_port));
+    }
+
+    // Interface MTU, if any
+    if let Some(mtu) = interface.mtu() {
+        lines.push_str(&format!("MTU = {}\n", mtu));
+    }
+
+    // Interface PreUp, if any
+    push_hooks(lines, "PreUp", interface.pre_up());
+
+    // Interface PostUp, if any
+    push_hooks(lines, "PostUp", interface.post_up());
+
+    // Interface PreDown, if any
+    push_hooks(lines, "PreDown", interface.pre_down());
+
+    // Interface PostDown, if any
+    push_hooks(lines, "PostDown", interface.post_down());
+    Ok(())
+}
+
+// [Peer] section of a node
+fn push_peer(lines: &mut String, node: Node) -> anyhow::Result<()> {
+    // Peer name
+    lines.push_str(&format!("\n# {}\n", node.name()));
+
+    let peer = Peer::from(node);
+
+    // Peer section begins
+    lines.push_str("[Peer]\n");
+
+    // Peer Public key
+    lines.push_str(&format!("PublicKey = {}\n", peer.public_key()?));
+
+    // Peer Allowed IPs
+    lines.push_str(&format!("AllowedIPs = {}\n", peer.allowed_ips()?));
+
+    // Peer Persistent Keepalive, if any
+    if let Some(keepalive) = peer.persistent_keepalive() {
+        lines.push_str(&format!("PersistentKeepalive = {}\n", keepalive));
+    }
+
+    // Peer Endpoint, if any
+    if let Some(endpoint) = peer.endpoint() {
+        lines.push_str(&format!("Endpoint = {}\n", endpoint));
+    }
+    Ok(())
+}
+
+// Non-relay node configuration, the primary relay routes the whole network and,
+// depending on the topology, host routes of other peers go to them directly
+pub fn peer_config(network: &Network, node: &Node, node_list: &[Node]) -> anyhow::Result<String> {
+    // is relay node
+    if node.relay {
+        anyhow::bail!("This function does not support relay node {}", node.name());
+    }
+
+    // primary relay, routed through the node's endpoint allowed ips and the
+    // networks advertised by the nodes not peered directly
+    let direct = topology::direct_peers(network.topology, node, node_list)
+        .map(Node::name)
+        .collect::<Vec<&str>>();
+    let mut relay = topology::primary_relay(node, node_list)
+        .context("please add peer relay node first")?
+        .clone();
+    let allowed_ips = match network.acl.allowed_ips(node, node_list) {
+        // only what the rules let through the relay, and the relay itself
+        Some(narrowed) => {
+            let routed = node_list
+                .iter()
+                .filter(|n| direct.contains(&n.name()))
+                .flat_map(topology::node_routes)
+                .collect::<Vec<IpNet>>();
+            let mut allowed_ips = topology::node_routes(&relay).collect::<Vec<IpNet>>();
+            for net in narrowed {
+                if !routed.contains(&net) && !allowed_ips.contains(&net) {
+                    allowed_ips.push(net);
+                }
+            }
+            allowed_ips
+        }
+        None => {
+            let mut allowed_ips = node.endpoint_allowed_ips.clone().unwrap_or_default();
+            allowed_ips.extend(
+                node_list
+                    .iter()
+                    .filter(|n| n.name().ne(node.name()) && !direct.contains(&n.name()))
+                    .flat_map(|n| n.routes.iter().flatten()),
+            );
+            allowed_ips
+        }
+    };
+    relay
+        .with_allowed_ips(Some(allowed_ips))
+        .with_persistent_keepalive(node.persistent_keepalive);
+
+    let mut lines = String::new();
+    push_interface(&mut lines, network, node.clone(), network.dns.as_deref())?;
+
+    // ------------------------------Peer----------------------------------
+    push_peer(&mut lines, relay)?;
+    for peer in topology::direct_peers(network.topology, node, node_list) {
+        let mut peer = peer.clone();
+        peer.with_persistent_keepalive(node.persistent_keepalive);
+        push_peer(&mut lines, peer)?;
+    }
+    Ok(lines)
+}
+
+// Relay node configuration, its leaves and the other relays
+pub fn relay_config(network: &Network, relay: &Node, node_list: &[Node]) -> anyhow::Result<String> {
+    let mut lines = String::new();
+    push_interface(&mut lines, network, relay.clone(), None)?;
+
+    // ------------------------------Peer----------------------------------
+    for node in topology::relay_peers(relay, node_list) {
+        push_peer(&mut lines, node)?;
+    }
+    Ok(lines)
+}
+
+// configuration of the named node at the time, the expired peers are left out
+pub fn node_config_at(
+    network: &Network,
+    node_name: &str,
+    node_list: &[Node],
+    now: i64,
+) -> anyhow::Result<String> {
+    let node_list = topology::unexpired(node_list, node_name, now);
+    let node = node_list
+        .iter()
+        .find(|n| n.name().eq(node_name))
+        .with_context(|| format!("node does not exist: {}", node_name))?;
+    if node.relay {
+        relay_config(network, node, &node_list)
+    } else {
+        peer_config(network, node, &node_list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::{self, home, leaf};
+    use crate::model::topology::Topology;
+
+    // relay, or leaf of the relay, reachable at the endpoint if any
+    fn node(name: &str, relay: Option<&str>, address: &str, endpoint: Option<&str>) -> Node {
+        let mut node = match relay {
+            Some(relay) => leaf(name, relay, address),
+            None => test_util::node(name, true, address),
+        };
+        node.with_endpoint(endpoint.map(|e| e.parse().unwrap()));
+        node
+    }
+
+    fn node_list() -> Vec<Node> {
+        let mut node_list = vec![
+            node("us", None, "10.66.66.1/24", Some("us.example.com:51820")),
+            node("eu", None, "10.66.66.2/24", Some("eu.example.com:51820")),
+            node(
+                "server",
+                Some("us"),
+                "10.66.66.3/24",
+                Some("203.0.113.3:51820"),
+            ),
+            node("laptop", Some("us"), "10.66.66.4/24", None),
+            node("phone", Some("us"), "10.66.66.5/24", None),
+            node(
+                "nas",
+                Some("eu"),
+                "10.66.66.6/24",
+                Some("198.51.100.6:51820"),
+            ),
+        ];
+        // the nas serves its LAN
+        node_list[5].with_routes(Some(vec!["192.168.10.0/24".parse().unwrap()]));
+        node_list
+    }
+
+    // names of the [Peer] sections and their AllowedIPs
+    fn peers(config: &str) -> Vec<(String, String)> {
+        let lines = config.lines().collect::<Vec<&str>>();
+        lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.eq(&&"[Peer]"))
+            .map(|(i, _)| {
+                let name = lines[i - 1].trim_start_matches("# ").to_string();
+                let allowed_ips = lines[i + 1..]
+                    .iter()
+                    .find_map(|line| line.strip_prefix("AllowedIPs = "))
+                    .unwrap()
+                    .to_string();
+                (name, allowed_ips)
+            })
+            .collect()
+    }
+
+    fn peer_names(topology: Topology, node_name: &str) -> Vec<String> {
+        let mut network = home();
+        network.topology = topology;
+        let config = node_config_at(&network, node_name, &node_list(), 0).unwrap();
+        peers(&config).into_iter().map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn test_hub_config() {
+        assert_eq!(peer_names(Topology::Hub, "laptop"), ["us"]);
+        assert_eq!(peer_names(Topology::Hub, "server"), ["us"]);
+        assert_eq!(peer_names(Topology::Hub, "nas"), ["eu"]);
+
+        // the laptop reaches the LAN of the nas through its relay
+        let network = home();
+        let config = node_config_at(&network, "laptop", &node_list(), 0).unwrap();
+        assert_eq!(
+            peers(&config),
+            [(
+                "us".to_string(),
+                "10.66.66.0/24, 192.168.10.0/24".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_mesh_config() {
+        // every peer with an endpoint, the others through the relay
+        assert_eq!(
+            peer_names(Topology::Mesh, "laptop"),
+            ["us", "server", "nas"]
+        );
+        assert_eq!(
+            peer_names(Topology::Mesh, "server"),
+            ["us", "laptop", "phone", "nas"]
+        );
+        assert_eq!(
+            peer_names(Topology::Mesh, "nas"),
+            ["eu", "server", "laptop", "phone"]
+        );
+
+        let network = Network {
+            topology: Topology::Mesh,
+            ..home()
+        };
+        let config = node_config_at(&network, "laptop", &node_list(), 0).unwrap();
+        assert_eq!(
+            peers(&config),
+            [
+                ("us".to_string(), "10.66.66.0/24".to_string()),
+                ("server".to_string(), "10.66.66.3/32".to_string()),
+                (
+                    "nas".to_string(),
+                    "10.66.66.6/32, 192.168.10.0/24".to_string()
+                ),
+            ]
+        );
+        assert!(config.contains("Endpoint = 203.0.113.3:51820"));
+        assert!(config.contains("Endpoint = 198.51.100.6:51820"));
+    }
+
+    #[test]
+    fn test_acl_config() {
+        let mut network = home();
+        network.acl.rules = vec![
+            "node:laptop -> node:nas tcp/445".parse().unwrap(),
+            "node:laptop -> node:server".parse().unwrap(),
+        ];
+        // the relay and the nodes the rules allow, with the LAN behind the nas
+        let config = node_config_at(&network, "laptop", &node_list(), 0).unwrap();
+        assert_eq!(
+            peers(&config),
+            [(
+                "us".to_string(),
+                "10.66.66.1/32, 10.66.66.3/32, 10.66.66.6/32, 192.168.10.0/24".to_string()
+            )]
+        );
+        let config = node_config_at(&network, "phone", &node_list(), 0).unwrap();
+        assert_eq!(
+            peers(&config),
+            [("us".to_string(), "10.66.66.1/32".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_hybrid_config() {
+        // direct links stay among the peers of the same relay
+        assert_eq!(peer_names(Topology::Hybrid, "laptop"), ["us", "server"]);
+        assert_eq!(
+            peer_names(Topology::Hybrid, "server"),
+            ["us", "laptop", "phone"]
+        );
+        assert_eq!(peer_names(Topology::Hybrid, "nas"), ["eu"]);
+    }
+
+    #[test]
+    fn test_relay_config() {
+        let network = home();
+        let config = node_config_at(&network, "eu", &node_list(), 0).unwrap();
+        assert_eq!(
+            peers(&config),
+            [
+                (
+                    "nas".to_string(),
+                    "10.66.66.6/32, 192.168.10.0/24".to_string()
+                ),
+                (
+                    "us".to_string(),
+                    "10.66.66.1/32, 10.66.66.3/32, 10.66.66.4/32, 10.66.66.5/32".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expired_peers() {
+        let network = home();
+        let mut node_list = node_list();
+        node_list[3].with_expires_at(Some(100));
+        let relay_peers = |now| {
+            peers(&node_config_at(&network, "us", &node_list, now).unwrap())
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(relay_peers(99), ["server", "laptop", "phone", "eu"]);
+        assert_eq!(relay_peers(100), ["server", "phone", "eu"]);
+        // the expired peer's own configuration is still rendered
+        assert!(node_config_at(&network, "laptop", &node_list, 100).is_ok());
+    }
+
+    #[test]
+    fn test_dual_stack_config() {
+        let mut node_list = node_list();
+        for (i, node) in node_list.iter_mut().enumerate() {
+            let mut address = node.address.clone().unwrap();
+            address.push(format!("fd00:66:66::{}/64", i + 1).parse().unwrap());
+            node.with_address(Some(address))
+                .with_endpoint_allowed_ips(Some(vec![
+                    "10.66.66.0/24".parse().unwrap(),
+                    "fd00:66:66::/64".parse().unwrap(),
+                ]));
+        }
+        let network = home();
+        let config = node_config_at(&network, "laptop", &node_list, 0).unwrap();
+        assert!(config.contains("Address = 10.66.66.4/24, fd00:66:66::4/64\n"));
+        assert_eq!(
+            peers(&config),
+            [(
+                "us".to_string(),
+                "10.66.66.0/24, fd00:66:66::/64, 192.168.10.0/24".to_string()
+            )]
+        );
+
+        let config = node_config_at(&network, "us", &node_list, 0).unwrap();
+        assert_eq!(
+            peers(&config)[1],
+            (
+                "laptop".to_string(),
+                "10.66.66.4/32, fd00:66:66::4/128".to_string()
+            )
+        );
+    }
+}
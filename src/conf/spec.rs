@@ -0,0 +1,235 @@
+use anyhow::Context;
+
+use crate::conf::backup::{Change, NetworkState};
+use crate::model::audit::Action;
+
+// read a declarative spec of a network and its nodes, the keys are left to wgsdc
+pub fn parse(content: &str) -> anyhow::Result<NetworkState> {
+    let spec: NetworkState = serde_yaml::from_str(content).context("invalid network spec")?;
+    let mut errors = Vec::new();
+    for (i, node) in spec.node_list.iter().enumerate() {
+        let name = match &node.name {
+            Some(name) => name,
+            None => {
+                errors.push("a node has no name".to_string());
+                continue;
+            }
+        };
+        if spec.node_list[i + 1..]
+            .iter()
+            .any(|n| n.name.as_deref() == Some(name.as_str()))
+        {
+            errors.push(format!("node {} is defined twice", name));
+        }
+        if node.public_key.is_some()
+            || node.private_key.is_some()
+            || node.previous_public_key.is_some()
+            || node.previous_private_key.is_some()
+        {
+            errors.push(format!(
+                "node {} has a key, keys are generated by wgsdc",
+                name
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!(errors.join("\n"))
+    }
+    Ok(spec)
+}
+
+// all networks once the spec is applied, the spec replaces the network of the same name;
+// nodes that exist keep their keys, new nodes get a key pair from `key_pair`, and the
+// address, relay and endpoint allowed ips a spec leaves out are filled in like add-peer does
+pub fn desired(
+    current: &[NetworkState],
+    spec: NetworkState,
+    mut key_pair: impl FnMut() -> (String, String),
+) -> anyhow::Result<Vec<NetworkState>> {
+    let existing = current
+        .iter()
+        .find(|s| s.network.name.eq(&spec.network.name))
+        .map_or(&[][..], |s| s.node_list.as_slice());
+    let network = spec.network;
+    let first_relay = spec
+        .node_list
+        .iter()
+        .find(|n| n.relay)
+        .map(|n| n.name().to_string());
+    let mut node_list = spec.node_list;
+    for node in &mut node_list {
+        match existing.iter().find(|n| n.name().eq(node.name())) {
+            Some(before) => {
+                node.with_public_key(before.public_key.clone())
+                    .with_private_key(before.private_key.clone());
+                node.previous_public_key = before.previous_public_key.clone();
+                node.previous_private_key = before.previous_private_key.clone();
+                node.previous_key_expires_at = before.previous_key_expires_at;
+                if node.address.is_none() {
+                    node.with_address(before.address.clone());
+                }
+                if !node.relay && node.parent.is_none() {
+                    node.with_parent(before.parent.clone());
+                }
+                if !node.relay && node.endpoint_allowed_ips.is_none() {
+                    node.with_endpoint_allowed_ips(before.endpoint_allowed_ips.clone());
+                }
+            }
+            None => {
+                let (public_key, private_key) = key_pair();
+                node.with_public_key(Some(public_key))
+                    .with_private_key(Some(private_key));
+            }
+        }
+        if !node.relay && node.parent.is_none() {
+            node.with_parent(first_relay.clone());
+        }
+        if !node.relay && node.endpoint_allowed_ips.is_none() {
+            node.with_endpoint_allowed_ips(Some(network.prefixes()));
+        }
+    }
+    // addresses are allocated once every explicit address is known
+    for i in 0..node_list.len() {
+        if node_list[i].address.is_none() {
+            let address = network.allocate(&node_list)?;
+            node_list[i].with_address(Some(address));
+        }
+    }
+
+    let mut network_list = current.to_vec();
+    let state = NetworkState { network, node_list };
+    match network_list
+        .iter_mut()
+        .find(|s| s.network.name.eq(&state.network.name))
+    {
+        Some(existing) => *existing = state,
+        None => network_list.push(state),
+    }
+    Ok(network_list)
+}
+
+// last line of a plan, counting networks and nodes alike
+pub fn summary(changes: &[Change]) -> String {
+    let count = |action: Action| {
+        changes
+            .iter()
+            .filter(|c| match c {
+                Change::Network { action: a, .. } | Change::Node { action: a, .. } => *a == action,
+            })
+            .count()
+    };
+    format!(
+        "Plan: {} to add, {} to change, {} to destroy.",
+        count(Action::Create),
+        count(Action::Update),
+        count(Action::Delete)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::backup;
+    use crate::model::Node;
+
+    const SPEC: &str = r#"
+network:
+  name: home
+  cidr: 10.66.66.0/24
+  interface: wg0
+  listen_port: 51820
+node_list:
+  - name: wg0
+    relay: true
+    address: [10.66.66.1/24]
+  - name: laptop
+    mtu: 1280
+  - name: phone
+    relay: false
+    address: [10.66.66.2/24]
+"#;
+
+    fn keys() -> impl FnMut() -> (String, String) {
+        let mut i = 0;
+        move || {
+            i += 1;
+            (format!("public-{}", i), format!("private-{}", i))
+        }
+    }
+
+    fn get<'a>(state: &'a NetworkState, name: &str) -> &'a Node {
+        state.node_list.iter().find(|n| n.name().eq(name)).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let spec = parse(SPEC).unwrap();
+        assert_eq!(spec.network.name, "home");
+        assert_eq!(spec.node_list.len(), 3);
+
+        let content = format!("{}    private_key: secret\n  - name: phone\n", SPEC);
+        let err = parse(&content).unwrap_err().to_string();
+        assert_eq!(
+            err.lines().collect::<Vec<&str>>(),
+            [
+                "node phone is defined twice",
+                "node phone has a key, keys are generated by wgsdc",
+            ]
+        );
+        assert!(parse("node_list: []").is_err());
+    }
+
+    #[test]
+    fn test_desired() {
+        let network_list = desired(&[], parse(SPEC).unwrap(), keys()).unwrap();
+        backup::validate(&network_list).unwrap();
+        let home = &network_list[0];
+        let laptop = get(home, "laptop");
+        assert_eq!(laptop.public_key.as_deref(), Some("public-2"));
+        assert_eq!(laptop.parent.as_deref(), Some("wg0"));
+        assert_eq!(laptop.address, Some(vec!["10.66.66.3/24".parse().unwrap()]));
+        assert_eq!(
+            laptop.endpoint_allowed_ips,
+            Some(vec!["10.66.66.0/24".parse().unwrap()])
+        );
+
+        // applying the same spec again changes nothing
+        let again = desired(&network_list, parse(SPEC).unwrap(), keys()).unwrap();
+        assert!(backup::diff(&network_list, &again).is_empty());
+
+        // only new nodes get keys, removed nodes are gone
+        let content = SPEC
+            .replace("mtu: 1280", "mtu: 1420")
+            .replace("name: phone", "name: tablet");
+        let mut office = home.clone();
+        office.network.name = "office".to_string();
+        office.network.interface = "wg1".to_string();
+        let current = vec![home.clone(), office];
+        let next = desired(&current, parse(&content).unwrap(), keys()).unwrap();
+        assert_eq!(next.len(), 2);
+        assert_eq!(next[1].node_list.len(), 3);
+        let laptop = get(&next[0], "laptop");
+        assert_eq!(laptop.public_key.as_deref(), Some("public-2"));
+        assert_eq!(laptop.address, Some(vec!["10.66.66.3/24".parse().unwrap()]));
+        assert_eq!(
+            get(&next[0], "tablet").public_key.as_deref(),
+            Some("public-1")
+        );
+        let changes = backup::diff(&current, &next);
+        assert_eq!(
+            changes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>(),
+            [
+                "~ node home/laptop (mtu)",
+                "+ node home/tablet",
+                "- node home/phone",
+            ]
+        );
+        assert_eq!(
+            summary(&changes),
+            "Plan: 1 to add, 1 to change, 1 to destroy."
+        );
+    }
+}
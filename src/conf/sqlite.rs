@@ -0,0 +1,706 @@
+use std::ops::Not;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction, DbConn, EntityTrait,
+    IntoActiveModel, QueryFilter, QueryOrder, TransactionTrait,
+};
+
+use crate::clock::{Clock, SystemClock};
+use crate::conf::backup::{self, Change, NetworkState};
+use crate::conf::cipher::{self, Cipher, Secret};
+use crate::conf::models::{self, WireGuard};
+use crate::conf::NodeOpt;
+use crate::db::model::prelude::{AuditEvent as AuditEventEntity, Keystore, NodeRelay};
+use crate::db::model::{audit_event, keystore, network, node_relay};
+use crate::model::audit::{Action, AuditEvent};
+use crate::model::network::Network;
+use crate::model::validate;
+use crate::model::Node;
+use crate::standard::PASSPHRASE_ENV;
+
+// sqlite database storage
+pub struct SqliteStore {
+    db: DbConn,
+    network: String,
+    // key of the encrypted private keys, once unlocked
+    cipher: Option<Cipher>,
+    // time of the recorded audit events
+    clock: Arc<dyn Clock>,
+}
+
+impl SqliteStore {
+    pub fn new(db: DbConn, network: &str) -> Self {
+        Self {
+            db,
+            network: network.to_string(),
+            cipher: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    // unlock an encrypted store, the secret is not needed by a plaintext store
+    pub async fn unlock(&mut self, secret: Option<&Secret>) -> anyhow::Result<()> {
+        let keystore = match Keystore::find().one(&self.db).await? {
+            Some(keystore) => keystore,
+            None => return Ok(()),
+        };
+        let secret = secret.with_context(|| {
+            format!(
+                "the store is encrypted, run `wgsdc unlock` or set {}",
+                PASSPHRASE_ENV
+            )
+        })?;
+        let cipher = Cipher::new(secret, &STANDARD.decode(&keystore.salt)?)?;
+        cipher.verify(&keystore.verifier)?;
+        self.cipher = Some(cipher);
+        Ok(())
+    }
+
+    pub async fn is_encrypted(&self) -> anyhow::Result<bool> {
+        Ok(Keystore::find().one(&self.db).await?.is_some())
+    }
+
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_ref()
+    }
+
+    // re-encrypt the private keys of all networks with the new secret, decrypted
+    // without one; returns the number of re-encrypted keys
+    pub async fn rekey(&mut self, secret: Option<&Secret>) -> anyhow::Result<usize> {
+        if self.is_encrypted().await? && self.cipher.is_none() {
+            anyhow::bail!("the store is locked")
+        }
+        let salt = Cipher::salt();
+        let cipher = match secret {
+            Some(secret) => Some(Cipher::new(secret, &salt)?),
+            None => None,
+        };
+
+        let txn = self.db.begin().await?;
+        let model_list = NodeRelay::find().all(&txn).await?;
+        let count = model_list.len();
+        for model in model_list {
+            let rekey = |private_key: &str| -> anyhow::Result<String> {
+                let mut private_key = private_key.to_string();
+                if let Some(old) = &self.cipher {
+                    private_key = old.decrypt(&private_key)?;
+                }
+                if let Some(new) = &cipher {
+                    private_key = new.encrypt(&private_key)?;
+                }
+                Ok(private_key)
+            };
+            let private_key = rekey(&model.private_key)?;
+            let previous_private_key = match &model.previous_private_key {
+                Some(previous_private_key) => Some(rekey(previous_private_key)?),
+                None => None,
+            };
+            let mut active_model = model.into_active_model();
+            active_model.private_key = ActiveValue::Set(private_key);
+            active_model.previous_private_key = ActiveValue::Set(previous_private_key);
+            active_model.update(&txn).await?;
+        }
+        Keystore::delete_many().exec(&txn).await?;
+        if let Some(cipher) = &cipher {
+            keystore::ActiveModel {
+                salt: ActiveValue::Set(STANDARD.encode(salt)),
+                verifier: ActiveValue::Set(cipher.verifier()?),
+                ..Default::default()
+            }
+            .insert(&txn)
+            .await?;
+        }
+        txn.commit().await?;
+        self.cipher = cipher;
+        Ok(count)
+    }
+
+    // decrypt the private keys read from the database, never rendered encrypted
+    fn reveal(&self, node: &mut Node) -> anyhow::Result<()> {
+        for private_key in [&mut node.private_key, &mut node.previous_private_key]
+            .into_iter()
+            .flatten()
+        {
+            match &self.cipher {
+                Some(cipher) => *private_key = cipher.decrypt(private_key)?,
+                None if cipher::is_encrypted(private_key) => {
+                    anyhow::bail!("the store is locked")
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    // encrypt the private keys written to the database
+    fn seal(&self, node: &mut Node) -> anyhow::Result<()> {
+        if let Some(cipher) = &self.cipher {
+            for private_key in [&mut node.private_key, &mut node.previous_private_key]
+                .into_iter()
+                .flatten()
+            {
+                *private_key = cipher.encrypt(private_key)?;
+            }
+        }
+        Ok(())
+    }
+
+    // connect to the database in the configuration directory
+    pub async fn open(config_dir: &Path, network: &str) -> anyhow::Result<Self> {
+        Ok(Self::new(crate::db::connect(config_dir).await?, network))
+    }
+
+    async fn find_network(&self, name: &str) -> anyhow::Result<Option<network::Model>> {
+        let model = network::Entity::find()
+            .filter(network::Column::Name.eq(name))
+            .one(&self.db)
+            .await?;
+        Ok(model)
+    }
+
+    // id of the network the storage is scoped to
+    async fn network_id(&self) -> anyhow::Result<i32> {
+        let model = self
+            .find_network(&self.network)
+            .await?
+            .with_context(|| format!("network does not exist: {}", self.network))?;
+        Ok(model.id)
+    }
+
+    async fn find_by_name(&self, node_name: &str) -> anyhow::Result<Option<node_relay::Model>> {
+        let model = NodeRelay::find()
+            .filter(node_relay::Column::NetworkId.eq(self.network_id().await?))
+            .filter(node_relay::Column::Name.eq(node_name))
+            .one(&self.db)
+            .await?;
+        Ok(model)
+    }
+
+    async fn find_all(&self) -> anyhow::Result<Vec<node_relay::Model>> {
+        let model_list = NodeRelay::find()
+            .filter(node_relay::Column::NetworkId.eq(self.network_id().await?))
+            .order_by_asc(node_relay::Column::Id)
+            .all(&self.db)
+            .await?;
+        Ok(model_list)
+    }
+
+    async fn find_relays(&self, network_id: i32) -> anyhow::Result<Vec<node_relay::Model>> {
+        let model_list = NodeRelay::find()
+            .filter(node_relay::Column::NetworkId.eq(network_id))
+            .filter(node_relay::Column::Relay.eq(true))
+            .order_by_asc(node_relay::Column::Id)
+            .all(&self.db)
+            .await?;
+        Ok(model_list)
+    }
+
+    // conflicts of the node list after pushing the node
+    async fn check(&mut self, node: &Node) -> anyhow::Result<()> {
+        let network = self.network().await?;
+        let mut change_list = self.list().await?;
+        match change_list.iter().position(|n| n.name().eq(node.name())) {
+            Some(index) => WireGuard::map_set(&mut change_list[index], node.clone()),
+            None => change_list.push(node.clone()),
+        }
+        validate::check(&network, &change_list, node.name())
+    }
+
+    // rows to nodes, the primary relay is referenced by name
+    async fn to_node_list(&self, model_list: Vec<node_relay::Model>) -> anyhow::Result<Vec<Node>> {
+        let mut node_list = self.to_sealed_node_list(model_list).await?;
+        for node in &mut node_list {
+            self.reveal(node)?;
+        }
+        Ok(node_list)
+    }
+
+    // rows to nodes whose private keys are left as stored, enough for the audit trail
+    async fn to_sealed_node_list(
+        &self,
+        model_list: Vec<node_relay::Model>,
+    ) -> anyhow::Result<Vec<Node>> {
+        let relays = self.find_relays(self.network_id().await?).await?;
+        Ok(to_nodes(model_list, &relays))
+    }
+
+    // append an event to the audit trail of its network, the network of the storage
+    // when unset, in the transaction of the change
+    async fn record(&self, txn: &DatabaseTransaction, mut event: AuditEvent) -> anyhow::Result<()> {
+        if event.network.is_empty() {
+            event.network = self.network.clone();
+        }
+        event.created_at = self.clock.now();
+        audit_event::ActiveModel::from(event).insert(txn).await?;
+        Ok(())
+    }
+
+    // delete the rows and record them in the audit trail
+    async fn delete(&self, model_list: Vec<node_relay::Model>) -> anyhow::Result<()> {
+        let ids = model_list.iter().map(|m| m.id).collect::<Vec<i32>>();
+        let node_list = self.to_sealed_node_list(model_list).await?;
+        let txn = self.db.begin().await?;
+        NodeRelay::delete_many()
+            .filter(node_relay::Column::Id.is_in(ids))
+            .exec(&txn)
+            .await?;
+        for node in &node_list {
+            self.record(
+                &txn,
+                AuditEvent::new(Action::Delete, node.name(), Some(node), None),
+            )
+            .await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+}
+
+// id of the primary relay of the node among the relays written so far
+fn parent_id(relays: &[(String, i32)], node: &Node) -> anyhow::Result<Option<i32>> {
+    match &node.parent {
+        Some(parent) => Ok(Some(
+            relays
+                .iter()
+                .find(|(name, _)| name.eq(parent))
+                .map(|(_, id)| *id)
+                .with_context(|| format!("there is no peer relay node named '{}'", parent))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+// rows to nodes, the primary relay is looked up among the relays of the network
+fn to_nodes(model_list: Vec<node_relay::Model>, relays: &[node_relay::Model]) -> Vec<Node> {
+    model_list
+        .into_iter()
+        .map(|model| {
+            let parent = model
+                .parent_id
+                .and_then(|id| relays.iter().find(|r| r.id == id))
+                .map(|r| r.name.clone());
+            let mut node = Node::from(model);
+            node.with_parent(parent);
+            node
+        })
+        .collect()
+}
+
+#[async_trait]
+impl NodeOpt for SqliteStore {
+    async fn network(&mut self) -> anyhow::Result<Network> {
+        let model = self
+            .find_network(&self.network)
+            .await?
+            .with_context(|| format!("network does not exist: {}", self.network))?;
+        Network::try_from(model)
+    }
+
+    async fn push_network(&mut self, network: Network) -> anyhow::Result<()> {
+        let existing = self.find_network(&network.name).await?;
+        let mut active_model = network::ActiveModel::from(network);
+        match existing {
+            Some(model) => {
+                active_model.id = ActiveValue::Unchanged(model.id);
+                active_model.update(&self.db).await?;
+            }
+            None => {
+                active_model.insert(&self.db).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_networks(&mut self) -> anyhow::Result<Vec<Network>> {
+        network::Entity::find()
+            .order_by_asc(network::Column::Id)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(Network::try_from)
+            .collect()
+    }
+
+    async fn state(&mut self) -> anyhow::Result<Vec<NetworkState>> {
+        let mut network_list = Vec::new();
+        for model in network::Entity::find()
+            .order_by_asc(network::Column::Id)
+            .all(&self.db)
+            .await?
+        {
+            let model_list = NodeRelay::find()
+                .filter(node_relay::Column::NetworkId.eq(model.id))
+                .order_by_asc(node_relay::Column::Id)
+                .all(&self.db)
+                .await?;
+            let relays = model_list
+                .iter()
+                .filter(|m| m.relay)
+                .cloned()
+                .collect::<Vec<node_relay::Model>>();
+            let mut node_list = to_nodes(model_list, &relays);
+            for node in &mut node_list {
+                self.reveal(node)?;
+            }
+            network_list.push(NetworkState {
+                network: Network::try_from(model)?,
+                node_list,
+            });
+        }
+        Ok(network_list)
+    }
+
+    async fn restore(&mut self, network_list: Vec<NetworkState>) -> anyhow::Result<()> {
+        let events = backup::diff(&self.state().await?, &network_list)
+            .iter()
+            .filter_map(Change::audit_event)
+            .collect::<Vec<AuditEvent>>();
+        let txn = self.db.begin().await?;
+        NodeRelay::delete_many().exec(&txn).await?;
+        network::Entity::delete_many().exec(&txn).await?;
+        for state in network_list {
+            let network_id = network::ActiveModel::from(state.network)
+                .insert(&txn)
+                .await?
+                .id;
+            // the relays come before their leaves
+            let mut relays: Vec<(String, i32)> = Vec::new();
+            for mut node in state.node_list {
+                let parent_id = parent_id(&relays, &node)?;
+                let name = node.name().to_string();
+                let relay = node.relay;
+                self.seal(&mut node)?;
+                let mut active_model = node_relay::ActiveModel::from(node);
+                active_model.network_id = ActiveValue::Set(network_id);
+                active_model.parent_id = ActiveValue::Set(parent_id);
+                let id = active_model.insert(&txn).await?.id;
+                if relay {
+                    relays.push((name, id));
+                }
+            }
+        }
+        for event in events {
+            self.record(&txn, event).await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn apply(&mut self, state: NetworkState) -> anyhow::Result<()> {
+        let current = self
+            .state()
+            .await?
+            .into_iter()
+            .filter(|s| s.network.name.eq(&state.network.name))
+            .collect::<Vec<NetworkState>>();
+        let changes = backup::diff(&current, std::slice::from_ref(&state));
+        let txn = self.db.begin().await?;
+        let existing = network::Entity::find()
+            .filter(network::Column::Name.eq(state.network.name.as_str()))
+            .one(&txn)
+            .await?;
+        let mut active_model = network::ActiveModel::from(state.network);
+        let network_id = match existing {
+            Some(model) => {
+                active_model.id = ActiveValue::Unchanged(model.id);
+                active_model.update(&txn).await?.id
+            }
+            None => active_model.insert(&txn).await?.id,
+        };
+        let model_list = NodeRelay::find()
+            .filter(node_relay::Column::NetworkId.eq(network_id))
+            .all(&txn)
+            .await?;
+        let id_of = |name: &str| model_list.iter().find(|m| m.name.eq(name)).map(|m| m.id);
+        // the relays come before their leaves, the unchanged ones included
+        let mut relays = model_list
+            .iter()
+            .filter(|m| m.relay)
+            .map(|m| (m.name.clone(), m.id))
+            .collect::<Vec<(String, i32)>>();
+        let mut deleted = Vec::new();
+        for change in &changes {
+            if let Change::Node {
+                action,
+                before,
+                after,
+                ..
+            } = change
+            {
+                match (action, before, after) {
+                    (Action::Delete, Some(node), _) => deleted.extend(id_of(node.name())),
+                    (_, _, Some(node)) => {
+                        let mut node = node.as_ref().clone();
+                        let parent_id = parent_id(&relays, &node)?;
+                        let name = node.name().to_string();
+                        let relay = node.relay;
+                        self.seal(&mut node)?;
+                        let mut active_model = node_relay::ActiveModel::from(node);
+                        active_model.network_id = ActiveValue::Set(network_id);
+                        active_model.parent_id = ActiveValue::Set(parent_id);
+                        let id = match id_of(&name) {
+                            Some(id) => {
+                                active_model.id = ActiveValue::Unchanged(id);
+                                active_model.update(&txn).await?.id
+                            }
+                            None => active_model.insert(&txn).await?.id,
+                        };
+                        if relay && !relays.iter().any(|(n, _)| n.eq(&name)) {
+                            relays.push((name, id));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(event) = change.audit_event() {
+                self.record(&txn, event).await?;
+            }
+        }
+        // the leaves of a deleted relay are deleted or moved by the changes above
+        NodeRelay::delete_many()
+            .filter(node_relay::Column::Id.is_in(deleted))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn get_by_name(&mut self, node_name: &str) -> anyhow::Result<Node> {
+        let model = self
+            .find_by_name(node_name)
+            .await?
+            .with_context(|| format!("node does not exist: {}", node_name))?;
+        let mut node_list = self.to_node_list(vec![model]).await?;
+        Ok(node_list.remove(0))
+    }
+
+    async fn push(&mut self, node: Node) -> anyhow::Result<()> {
+        let network_id = self.network_id().await?;
+        let relays = self.find_relays(network_id).await?;
+        if node.relay.not() {
+            // no has relay node
+            if relays.is_empty() {
+                return Err(anyhow::anyhow!("please add peer relay node first"));
+            }
+        }
+
+        // primary relay
+        let parent_id = match node.parent {
+            Some(ref parent) => {
+                if node.relay {
+                    return Err(anyhow::anyhow!(format!(
+                        "peer relay node {} can not have a primary relay",
+                        node.name()
+                    )));
+                }
+                let relay = relays
+                    .iter()
+                    .find(|r| r.name.eq(parent))
+                    .with_context(|| format!("there is no peer relay node named '{}'", parent))?;
+                Some(relay.id)
+            }
+            None => None,
+        };
+
+        match self.find_by_name(node.name()).await? {
+            Some(model) => {
+                // duplicate name
+                if model.relay.ne(&node.relay) {
+                    return Err(anyhow::anyhow!(format!(
+                        "Duplicate node {} name",
+                        node.name()
+                    )));
+                }
+                self.check(&node).await?;
+                let id = model.id;
+                let parent_id = parent_id.or(model.parent_id);
+                let before = self.to_node_list(vec![model]).await?.remove(0);
+                let mut change = before.clone();
+                WireGuard::map_set(&mut change, node);
+                let event =
+                    AuditEvent::new(Action::Update, change.name(), Some(&before), Some(&change));
+                self.seal(&mut change)?;
+                let mut active_model = node_relay::ActiveModel::from(change);
+                active_model.id = ActiveValue::Unchanged(id);
+                active_model.network_id = ActiveValue::Unchanged(network_id);
+                active_model.parent_id = ActiveValue::Set(parent_id);
+                let txn = self.db.begin().await?;
+                active_model.update(&txn).await?;
+                self.record(&txn, event).await?;
+                txn.commit().await?;
+            }
+            None => {
+                self.check(&node).await?;
+                let event = AuditEvent::new(Action::Create, node.name(), None, Some(&node));
+                let mut node = node;
+                self.seal(&mut node)?;
+                let mut active_model = node_relay::ActiveModel::from(node);
+                active_model.network_id = ActiveValue::Set(network_id);
+                active_model.parent_id = ActiveValue::Set(parent_id);
+                let txn = self.db.begin().await?;
+                active_model.insert(&txn).await?;
+                self.record(&txn, event).await?;
+                txn.commit().await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_keys(&mut self, node: &Node) -> anyhow::Result<()> {
+        let model = self
+            .find_by_name(node.name())
+            .await?
+            .with_context(|| format!("node does not exist: {}", node.name()))?;
+        let before = self
+            .to_sealed_node_list(vec![model.clone()])
+            .await?
+            .remove(0);
+        let event = AuditEvent::new(Action::Rotate, node.name(), Some(&before), Some(node));
+        let mut node = node.clone();
+        self.seal(&mut node)?;
+        let mut active_model = model.into_active_model();
+        active_model.public_key = ActiveValue::Set(node.public_key.unwrap_or_default());
+        active_model.private_key = ActiveValue::Set(node.private_key.unwrap_or_default());
+        active_model.previous_public_key = ActiveValue::Set(node.previous_public_key);
+        active_model.previous_private_key = ActiveValue::Set(node.previous_private_key);
+        active_model.previous_key_expires_at = ActiveValue::Set(node.previous_key_expires_at);
+        let txn = self.db.begin().await?;
+        active_model.update(&txn).await?;
+        self.record(&txn, event).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn audit_list(&mut self) -> anyhow::Result<Vec<AuditEvent>> {
+        AuditEventEntity::find()
+            .filter(audit_event::Column::Network.eq(self.network.as_str()))
+            .order_by_asc(audit_event::Column::Id)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(AuditEvent::try_from)
+            .collect()
+    }
+
+    async fn list_by_relay(&mut self, relay: bool) -> anyhow::Result<Vec<Node>> {
+        let node_list = NodeRelay::find()
+            .filter(node_relay::Column::NetworkId.eq(self.network_id().await?))
+            .filter(node_relay::Column::Relay.eq(relay))
+            .order_by_asc(node_relay::Column::Id)
+            .all(&self.db)
+            .await?;
+        self.to_node_list(node_list).await
+    }
+
+    async fn list(&mut self) -> anyhow::Result<Vec<Node>> {
+        let node_list = self.find_all().await?;
+        self.to_node_list(node_list).await
+    }
+
+    async fn remove_all(&mut self) -> anyhow::Result<()> {
+        let model_list = self.find_all().await?;
+        self.delete(model_list).await
+    }
+
+    async fn remove_by_name(&mut self, node_name: &str) -> anyhow::Result<()> {
+        let model = self
+            .find_by_name(node_name)
+            .await?
+            .with_context(|| format!("there is no node named '{}'", node_name))?;
+        self.delete(vec![model]).await
+    }
+
+    async fn edit(
+        &mut self,
+        node_name: &str,
+        node: Node,
+        new_name: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let network_id = self.network_id().await?;
+        let name = new_name.unwrap_or(node_name);
+        if new_name.is_some() && self.find_by_name(name).await?.is_some() {
+            anyhow::bail!("node {} already exists", name)
+        }
+        let model = self
+            .find_by_name(node_name)
+            .await?
+            .with_context(|| format!("node does not exist: {}", node_name))?;
+        if model.relay.ne(&node.relay) {
+            anyhow::bail!("node {} can not change its role", node_name)
+        }
+        let id = model.id;
+        let parent_id = model.parent_id;
+        let before = self.to_node_list(vec![model]).await?.remove(0);
+        let mut after = models::edited(&before, node, name);
+
+        // the node list after the change, written only without conflicts
+        let mut network = self.network().await?;
+        let mut change_list = self.list().await?;
+        for n in change_list.iter_mut() {
+            if n.name().eq(node_name) {
+                *n = after.clone();
+            } else if n.parent.as_deref() == Some(node_name) {
+                n.with_parent(Some(name.to_string()));
+            }
+        }
+        validate::check(&network, &change_list, name)?;
+
+        let event = AuditEvent::new(Action::Update, name, Some(&before), Some(&after));
+        self.seal(&mut after)?;
+        // the leaves refer to their relay by id
+        let mut active_model = node_relay::ActiveModel::from(after);
+        active_model.id = ActiveValue::Unchanged(id);
+        active_model.network_id = ActiveValue::Unchanged(network_id);
+        active_model.parent_id = ActiveValue::Set(parent_id);
+        let txn = self.db.begin().await?;
+        active_model.update(&txn).await?;
+        if name.ne(node_name) && network.acl.rename(node_name, name) {
+            let mut active_model = network::ActiveModel::from(network);
+            active_model.id = ActiveValue::Unchanged(network_id);
+            active_model.update(&txn).await?;
+        }
+        self.record(&txn, event).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_by_names(&mut self, node_names: &[String]) -> anyhow::Result<()> {
+        let model_list = NodeRelay::find()
+            .filter(node_relay::Column::NetworkId.eq(self.network_id().await?))
+            .filter(node_relay::Column::Name.is_in(node_names.to_vec()))
+            .all(&self.db)
+            .await?;
+        if let Some(node_name) = node_names
+            .iter()
+            .find(|name| !model_list.iter().any(|m| m.name.eq(*name)))
+        {
+            anyhow::bail!("there is no node named '{}'", node_name)
+        }
+        self.delete(model_list).await
+    }
+
+    async fn remove(&mut self, index: usize) -> anyhow::Result<()> {
+        let model = self
+            .find_all()
+            .await?
+            .into_iter()
+            .nth(index)
+            .with_context(|| format!("index data {} out of bounds", index))?;
+        self.delete(vec![model]).await
+    }
+
+    async fn clear(&mut self) -> anyhow::Result<()> {
+        self.remove_all().await
+    }
+}
@@ -0,0 +1,519 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use tokio::signal::unix::{signal, SignalKind};
+use wireguard_uapi::tools::quick::WgQuick;
+use wireguard_uapi::{
+    Backend, Device, DeviceUpdate, InterfaceName, Key, KeyPair, PeerConfigBuilder, PeerInfo,
+};
+
+use crate::clock::{self, Clock};
+use crate::conf::NodeOpt;
+use crate::model::endpoint::Peer;
+use crate::model::network::Network;
+use crate::model::{hook, topology, Node};
+use crate::roaming::{self, Resolver};
+use crate::runner::Runner;
+
+// peers of the relay interface: its leaves and the other relays
+pub(crate) fn relay_peers(
+    relay: &Node,
+    node_list: &[Node],
+) -> anyhow::Result<Vec<PeerConfigBuilder>> {
+    topology::relay_peers(relay, node_list)
+        .into_iter()
+        .map(|n| Peer::from(n).to_peer_config())
+        .collect()
+}
+
+// relay private key
+fn relay_key_pair(relay: &Node) -> anyhow::Result<KeyPair> {
+    let private_key = relay
+        .private_key
+        .as_deref()
+        .context("relay private key is undefined")?;
+    let key = Key::from_base64(private_key).context("invalid relay private key")?;
+    Ok(KeyPair::from_private(key))
+}
+
+// desired state of the relay interface
+pub(crate) fn relay_update(relay: &Node, node_list: &[Node]) -> anyhow::Result<DeviceUpdate> {
+    let mut update = DeviceUpdate::new()
+        .set_keypair(relay_key_pair(relay)?)
+        .add_peers(&relay_peers(relay, node_list)?);
+    if let Some(listen_port) = relay.listen_port {
+        update = update.set_listen_port(listen_port);
+    }
+    Ok(update)
+}
+
+// Update of the live relay device after the key of the node was rotated from the old
+// public key, a single update swaps the keys. None when the device does not serve the node.
+pub(crate) fn rotation_update(
+    device_key: &Key,
+    old_public_key: &str,
+    node: &Node,
+    node_list: &[Node],
+) -> anyhow::Result<Option<DeviceUpdate>> {
+    let device_key = device_key.to_base64();
+    // the rotated node is the relay of the device
+    if node.relay && device_key.eq(old_public_key) {
+        return Ok(Some(DeviceUpdate::new().set_keypair(relay_key_pair(node)?)));
+    }
+    let relay = match node_list
+        .iter()
+        .find(|n| n.relay && n.public_key.as_deref() == Some(device_key.as_str()))
+    {
+        Some(relay) => relay,
+        None => return Ok(None),
+    };
+    match topology::relay_peers(relay, node_list)
+        .into_iter()
+        .find(|n| n.name().eq(node.name()))
+    {
+        Some(peer) => {
+            let old_key =
+                Key::from_base64(old_public_key).context("invalid previous public key")?;
+            Ok(Some(
+                DeviceUpdate::new()
+                    .remove_peer_by_key(&old_key)
+                    .add_peer(Peer::from(peer).to_peer_config()?),
+            ))
+        }
+        None => Ok(None),
+    }
+}
+
+// update of the live relay device after the node was edited, the peer's allowed ips are
+// replaced, none when the device does not serve the node
+pub(crate) fn edit_update(
+    device_key: &Key,
+    node: &Node,
+    node_list: &[Node],
+) -> anyhow::Result<Option<DeviceUpdate>> {
+    let device_key = device_key.to_base64();
+    let relay = match node_list
+        .iter()
+        .find(|n| n.relay && n.public_key.as_deref() == Some(device_key.as_str()))
+    {
+        Some(relay) => relay,
+        None => return Ok(None),
+    };
+    match topology::relay_peers(relay, node_list)
+        .into_iter()
+        .find(|n| n.name().eq(node.name()))
+    {
+        Some(peer) => Ok(Some(
+            DeviceUpdate::new().add_peer(Peer::from(peer).to_peer_config()?.replace_allowed_ips()),
+        )),
+        None => Ok(None),
+    }
+}
+
+// removal of the expired nodes that are still peers of the live device
+pub(crate) fn expired_update(peers: &[PeerInfo], node_list: &[Node], now: i64) -> DeviceUpdate {
+    let mut update = DeviceUpdate::new();
+    for node in node_list.iter().filter(|n| n.is_expired(now)) {
+        let key = match node.public_key.as_deref().map(Key::from_base64) {
+            Some(Ok(key)) => key,
+            _ => continue,
+        };
+        if peers.iter().any(|p| p.config.public_key == key) {
+            log::info!(
+                "peer {} expired at {}, removing it",
+                node.name(),
+                clock::format(node.expires_at.unwrap_or_default())
+            );
+            update = update.remove_peer_by_key(&key);
+        }
+    }
+    update
+}
+
+// create the relay interface from scratch, with its addresses and routes
+pub(crate) fn bring_up(
+    interface: &InterfaceName,
+    relay: &Node,
+    node_list: &[Node],
+    backend: Backend,
+) -> anyhow::Result<()> {
+    let mut quick = WgQuick::new(&interface.to_string())?
+        .set_keypair(relay_key_pair(relay)?)
+        .set_address_list(relay.address.as_deref().unwrap_or_default())
+        .replace_peers()
+        .add_peers(&relay_peers(relay, node_list)?);
+    if let Some(listen_port) = relay.listen_port {
+        quick = quick.set_listen_port(listen_port);
+    }
+    if let Some(mtu) = relay.mtu {
+        quick = quick.set_mtu(mtu as u32);
+    }
+    quick.apply(backend)?;
+    Ok(())
+}
+
+// the named relay, or the first relay of the network
+pub(crate) fn select_relay<'a>(
+    name: Option<&str>,
+    node_list: &'a [Node],
+) -> anyhow::Result<&'a Node> {
+    let mut relays = node_list.iter().filter(|n| n.relay);
+    match name {
+        Some(name) => relays
+            .find(|n| n.name().eq(name))
+            .with_context(|| format!("there is no peer relay node named '{}'", name)),
+        None => relays.next().context("please add peer relay node first"),
+    }
+}
+
+// the desired state of the relay interface at the time of the daemon's clock
+struct Desired {
+    network: Network,
+    relay: Node,
+    // all nodes of the network, the expired ones included
+    all_nodes: Vec<Node>,
+    // the nodes of the relay interface
+    node_list: Vec<Node>,
+    now: i64,
+}
+
+pub(crate) struct Daemon<'a> {
+    store: Box<dyn NodeOpt>,
+    relay: Option<String>,
+    resolver: &'a dyn Resolver,
+    clock: &'a dyn Clock,
+    backend: Backend,
+    reresolve_interval: Duration,
+    last_reresolve: Option<Instant>,
+    // seconds a hook of the relay may run
+    hook_timeout: Duration,
+    // the interface and the relay with rendered hooks it runs, torn down on exit
+    running: Option<(InterfaceName, Node)>,
+}
+
+impl<'a> Daemon<'a> {
+    pub fn new(
+        store: Box<dyn NodeOpt>,
+        relay: Option<String>,
+        resolver: &'a dyn Resolver,
+        clock: &'a dyn Clock,
+        reresolve_interval: Duration,
+        hook_timeout: Duration,
+        backend: Backend,
+    ) -> Self {
+        Self {
+            store,
+            relay,
+            resolver,
+            clock,
+            backend,
+            reresolve_interval,
+            last_reresolve: None,
+            hook_timeout,
+            running: None,
+        }
+    }
+
+    // Keep the relay interface in sync with the node storage until SIGTERM/SIGINT,
+    // SIGHUP forces an immediate reload.
+    pub async fn run(&mut self, interval: Duration) -> anyhow::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = hangup.recv() => {
+                    log::info!("received SIGHUP, reloading");
+                    self.last_reresolve = None;
+                }
+                _ = terminate.recv() => break,
+                _ = interrupt.recv() => break,
+            }
+            if let Err(e) = self.sync().await {
+                log::error!("failed to synchronize the relay interface: {:#}", e);
+            }
+        }
+        self.teardown().await
+    }
+
+    async fn desired(&mut self) -> anyhow::Result<Desired> {
+        let now = self.clock.now();
+        let network = self.store.network().await?;
+        let all_nodes = self.store.list().await?;
+        let relay = select_relay(self.relay.as_deref(), &all_nodes)?.clone();
+        // the expired peers are revoked from the interface
+        let node_list = topology::unexpired(&all_nodes, relay.name(), now);
+        Ok(Desired {
+            network,
+            relay,
+            all_nodes,
+            node_list,
+            now,
+        })
+    }
+
+    // reconcile the live device with the node storage once
+    pub async fn sync(&mut self) -> anyhow::Result<()> {
+        let Desired {
+            network,
+            mut relay,
+            all_nodes,
+            node_list,
+            now,
+        } = self.desired().await?;
+        hook::render_hooks(&network, &mut relay)?;
+        let relay = &relay;
+        let interface = network.interface.parse::<InterfaceName>()?;
+        if matches!(&self.running, Some((i, _)) if *i != interface) {
+            self.teardown().await?;
+        }
+        self.running = Some((interface, relay.clone()));
+
+        let mut device = match Device::get(&interface, self.backend) {
+            Ok(device) => device,
+            Err(e) => {
+                log::warn!(
+                    "interface {} is not available ({}), bringing it up",
+                    interface,
+                    e
+                );
+                let backend = self.backend;
+                return Runner::new(interface.to_string(), self.hook_timeout)
+                    .up(
+                        relay,
+                        || bring_up(&interface, relay, &node_list, backend),
+                        || Ok(Device::get(&interface, backend)?.delete()?),
+                    )
+                    .await;
+            }
+        };
+
+        let expired = expired_update(&device.peers, &all_nodes, now);
+        if !expired.is_empty() {
+            expired.apply(&interface, self.backend)?;
+            device = Device::get(&interface, self.backend)?;
+        }
+
+        let update = relay_update(relay, &node_list)?.diff(&device);
+        if !update.is_empty() {
+            log::info!("applying node changes to interface {}", interface);
+            log::debug!("{:?}", update);
+            update.apply(&interface, self.backend)?;
+        }
+
+        if !matches!(self.last_reresolve, Some(last) if last.elapsed() < self.reresolve_interval) {
+            self.last_reresolve = Some(Instant::now());
+            let update = roaming::reresolve(
+                self.resolver,
+                &device.peers,
+                &node_list,
+                self.clock.system_time(),
+            )
+            .await;
+            if !update.is_empty() {
+                update.apply(&interface, self.backend)?;
+            }
+        }
+        Ok(())
+    }
+
+    // remove the interface owned by the daemon, running the down hooks of its relay
+    async fn teardown(&mut self) -> anyhow::Result<()> {
+        if let Some((interface, relay)) = self.running.take() {
+            log::info!("tearing down interface {}", interface);
+            if let Ok(device) = Device::get(&interface, self.backend) {
+                Runner::new(interface.to_string(), self.hook_timeout)
+                    .down(&relay, || Ok(device.delete()?))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::conf::Configuration;
+    use crate::model::test_util::{self, home};
+
+    // node with a key pair the device takes
+    fn node(name: &str, relay: bool, address: &str) -> Node {
+        let key_pair = KeyPair::generate();
+        let mut node = test_util::node(name, relay, address);
+        node.with_public_key(Some(key_pair.public.to_base64()))
+            .with_private_key(Some(key_pair.private.to_base64()))
+            .with_persistent_keepalive(Some(21));
+        node
+    }
+
+    #[test]
+    fn test_relay_peers() {
+        let relay = node("wg0", true, "10.66.66.1/24");
+        let mut laptop = node("laptop", false, "10.66.66.2/24");
+        laptop.with_allowed_ips(Some(vec!["192.168.1.0/24".parse().unwrap()]));
+        let node_list = vec![relay.clone(), laptop.clone()];
+
+        let peers = relay_peers(&relay, &node_list).unwrap();
+        let key = Key::from_base64(laptop.public_key.as_deref().unwrap()).unwrap();
+        let expected = PeerConfigBuilder::new(&key)
+            .add_allowed_ip("192.168.1.0".parse().unwrap(), 24)
+            .add_allowed_ip("10.66.66.2".parse().unwrap(), 32)
+            .set_persistent_keepalive_interval(21);
+        assert_eq!(peers, vec![expected]);
+    }
+
+    #[test]
+    fn test_rotation_update() {
+        let relay = node("wg0", true, "10.66.66.1/24");
+        let laptop = node("laptop", false, "10.66.66.2/24");
+        let relay_key = Key::from_base64(relay.public_key.as_deref().unwrap()).unwrap();
+        let old_key = laptop.public_key.clone().unwrap();
+
+        let mut rotated = laptop.clone();
+        let key_pair = KeyPair::generate();
+        rotated.rotate_key(key_pair.public.to_base64(), key_pair.private.to_base64(), 0);
+        let node_list = vec![relay.clone(), rotated.clone()];
+        let update = rotation_update(&relay_key, &old_key, &rotated, &node_list)
+            .unwrap()
+            .unwrap();
+        let expected = DeviceUpdate::new()
+            .remove_peer_by_key(&Key::from_base64(&old_key).unwrap())
+            .add_peer(
+                PeerConfigBuilder::new(&key_pair.public)
+                    .add_allowed_ip("10.66.66.2".parse().unwrap(), 32)
+                    .set_persistent_keepalive_interval(21),
+            );
+        assert_eq!(update, expected);
+
+        // the device of another relay does not serve the node
+        let other = KeyPair::generate().public;
+        assert!(rotation_update(&other, &old_key, &rotated, &node_list)
+            .unwrap()
+            .is_none());
+
+        // the relay of the device itself
+        let mut rotated = relay.clone();
+        let key_pair = KeyPair::generate();
+        rotated.rotate_key(key_pair.public.to_base64(), key_pair.private.to_base64(), 0);
+        let node_list = vec![rotated.clone(), laptop];
+        let update = rotation_update(&relay_key, &relay_key.to_base64(), &rotated, &node_list)
+            .unwrap()
+            .unwrap();
+        assert_eq!(update, DeviceUpdate::new().set_keypair(key_pair));
+    }
+
+    #[test]
+    fn test_edit_update() {
+        let relay = node("wg0", true, "10.66.66.1/24");
+        let mut laptop = node("laptop", false, "10.66.66.2/24");
+        laptop
+            .with_allowed_ips(Some(vec!["192.168.1.0/24".parse().unwrap()]))
+            .with_persistent_keepalive(Some(5));
+        let relay_key = Key::from_base64(relay.public_key.as_deref().unwrap()).unwrap();
+        let node_list = vec![relay.clone(), laptop.clone()];
+        let update = edit_update(&relay_key, &laptop, &node_list)
+            .unwrap()
+            .unwrap();
+        let key = Key::from_base64(laptop.public_key.as_deref().unwrap()).unwrap();
+        let expected = DeviceUpdate::new().add_peer(
+            PeerConfigBuilder::new(&key)
+                .add_allowed_ip("192.168.1.0".parse().unwrap(), 24)
+                .add_allowed_ip("10.66.66.2".parse().unwrap(), 32)
+                .set_persistent_keepalive_interval(5)
+                .replace_allowed_ips(),
+        );
+        assert_eq!(update, expected);
+
+        // the device of another relay does not serve the node
+        let other = KeyPair::generate().public;
+        assert!(edit_update(&other, &laptop, &node_list).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_expired_update() {
+        let relay = node("wg0", true, "10.66.66.1/24");
+        let mut laptop = node("laptop", false, "10.66.66.2/24");
+        laptop.with_expires_at(Some(100));
+        let phone = node("phone", false, "10.66.66.3/24");
+        let node_list = vec![relay.clone(), laptop.clone(), phone.clone()];
+        let device_peers = relay_peers(&relay, &node_list)
+            .unwrap()
+            .into_iter()
+            .map(|config| PeerInfo {
+                config: config.into_peer_config(),
+                stats: Default::default(),
+            })
+            .collect::<Vec<PeerInfo>>();
+
+        assert!(expired_update(&device_peers, &node_list, 99).is_empty());
+        let laptop_key = Key::from_base64(laptop.public_key.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            expired_update(&device_peers, &node_list, 100),
+            DeviceUpdate::new().remove_peer_by_key(&laptop_key)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_daemon_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wgsdc.yaml");
+        let mut store = Configuration::load(path.clone(), "home").await.unwrap();
+        store.push_network(home()).await.unwrap();
+        let relay = node("wg0", true, "10.66.66.1/24");
+        let mut laptop = node("laptop", false, "10.66.66.2/24");
+        laptop.with_expires_at(Some(100));
+        let phone = node("phone", false, "10.66.66.3/24");
+        for node in [relay.clone(), laptop, phone.clone()] {
+            store.push(node).await.unwrap();
+        }
+
+        // the relay leaves the peer out once the daemon's clock passed its expiry
+        for (now, expected) in [
+            (99, vec!["wg0", "laptop", "phone"]),
+            (100, vec!["wg0", "phone"]),
+        ] {
+            let clock = FixedClock(now);
+            let store = Configuration::load(path.clone(), "home").await.unwrap();
+            let mut daemon = Daemon::new(
+                Box::new(store),
+                None,
+                &roaming::DnsResolver,
+                &clock,
+                Duration::from_secs(60),
+                Duration::from_secs(30),
+                Backend::Userspace,
+            );
+            let desired = daemon.desired().await.unwrap();
+            assert_eq!(desired.now, now);
+            assert_eq!(desired.all_nodes.len(), 3);
+            let names = desired
+                .node_list
+                .iter()
+                .map(|n| n.name())
+                .collect::<Vec<&str>>();
+            assert_eq!(names, expected);
+        }
+
+        // the desired state of the relay leaves the expired peer out
+        let clock = FixedClock(100);
+        let store = Configuration::load(path, "home").await.unwrap();
+        let mut daemon = Daemon::new(
+            Box::new(store),
+            None,
+            &roaming::DnsResolver,
+            &clock,
+            Duration::from_secs(60),
+            Duration::from_secs(30),
+            Backend::Userspace,
+        );
+        let desired = daemon.desired().await.unwrap();
+        assert_eq!(
+            relay_update(&desired.relay, &desired.node_list).unwrap(),
+            relay_update(&relay, &[relay.clone(), phone]).unwrap()
+        );
+    }
+}
@@ -1,9 +1,31 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
-use sea_orm::{sea_query, ConnectionTrait, DbConn, DbErr};
+use sea_orm::sea_query::{Alias, Table, TableCreateStatement};
+use sea_orm::{sea_query, ConnectionTrait, DbConn, DbErr, Statement, TransactionTrait};
+
+use crate::standard::{DEFAULT_INTERFACE_ADDRESS, DEFAULT_NETWORK};
 
 pub mod model;
 
+const DATABASE_FILE: &str = "db";
+
+// version of the schema, kept in the user_version pragma of the database
+const SCHEMA_VERSION: i32 = 1;
+
+// connect to the database in the configuration directory, it is created if it does not exist
+// and migrated if it has an earlier schema
+pub async fn connect(config_dir: &Path) -> anyhow::Result<DbConn> {
+    if !config_dir.exists() {
+        tokio::fs::create_dir_all(config_dir).await?;
+    }
+    let database_path = config_dir.join(DATABASE_FILE);
+    initialize_database(database_path.clone()).await?;
+    let db = sea_orm::Database::connect(format!("sqlite:{}", database_path.display())).await?;
+    migrate(&db).await?;
+    Ok(db)
+}
+
 // initialize database, the database path does not have to exist
 pub async fn initialize_database(database_path: PathBuf) -> anyhow::Result<(), DbErr> {
     if database_path.is_file() {
@@ -14,17 +36,16 @@ pub async fn initialize_database(database_path: PathBuf) -> anyhow::Result<(), D
     } else if database_path.is_dir() {
         panic!("the {} is a directory, not a file", database_path.display())
     } else {
-        match tokio::fs::File::create(&database_path).await {
-            Ok(_) => {
-                log::debug!(
-                    "the {} database file has been created",
-                    database_path.display()
-                );
-                log::debug!("begins initializing the database table");
-                let db = sea_orm::Database::connect(format!("sqlite:{}", database_path.display()))
-                    .await?;
-                initialize_table(&db).await?
-            }
+        // the database holds the private keys, only readable by its owner
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        match options.open(&database_path).await {
+            Ok(_) => log::debug!(
+                "the {} database file has been created",
+                database_path.display()
+            ),
             Err(e) => {
                 panic!("failed to create database file, error: {}", e)
             }
@@ -33,51 +54,392 @@ pub async fn initialize_database(database_path: PathBuf) -> anyhow::Result<(), D
     Ok(())
 }
 
-async fn initialize_table(db: &DbConn) -> anyhow::Result<(), DbErr> {
+// bring the schema up to date: the missing tables are created and the missing columns added,
+// the node table of the first schema is rebuilt into the default network
+pub(crate) async fn migrate(db: &DbConn) -> anyhow::Result<(), DbErr> {
+    let builder = db.get_database_backend();
+    let version = db
+        .query_one(Statement::from_string(
+            builder,
+            "PRAGMA user_version".to_string(),
+        ))
+        .await?
+        .map(|row| row.try_get::<i32>("", "user_version"))
+        .transpose()?
+        .unwrap_or_default();
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+    log::debug!(
+        "begins migrating the database schema from version {} to {}",
+        version,
+        SCHEMA_VERSION
+    );
+
+    let txn = db.begin().await?;
+    let node_columns = column_names(&txn, "node").await?;
+    let legacy = !node_columns.is_empty() && !node_columns.contains("network_id");
+    if legacy {
+        txn.execute_unprepared("ALTER TABLE node RENAME TO node_v0")
+            .await?;
+    }
+    initialize_table(&txn).await?;
+    for table in tables() {
+        let name = match table.get_table_name() {
+            Some(sea_query::TableRef::Table(name)) => name.to_string(),
+            _ => continue,
+        };
+        let columns = column_names(&txn, &name).await?;
+        for column in table.get_columns() {
+            if columns.contains(&column.get_column_name()) {
+                continue;
+            }
+            log::debug!("add column {}.{}", name, column.get_column_name());
+            let stmt = Table::alter()
+                .table(Alias::new(&name))
+                .add_column(&mut column.clone())
+                .to_owned();
+            txn.execute(builder.build(&stmt)).await?;
+        }
+    }
+    if legacy {
+        migrate_legacy_nodes(&txn).await?;
+    }
+    txn.execute_unprepared(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+        .await?;
+    txn.commit().await
+}
+
+// the nodes of the first schema belong to no network, they move into the default network of
+// their relay
+async fn migrate_legacy_nodes<C: ConnectionTrait>(db: &C) -> anyhow::Result<(), DbErr> {
+    let builder = db.get_database_backend();
+    let count = db
+        .query_one(Statement::from_string(
+            builder,
+            "SELECT COUNT(*) AS count FROM node_v0".to_string(),
+        ))
+        .await?
+        .map(|row| row.try_get::<i64>("", "count"))
+        .transpose()?
+        .unwrap_or_default();
+    if count > 0 {
+        let cidr = DEFAULT_INTERFACE_ADDRESS
+            .parse::<ipnet::IpNet>()
+            .expect("invalid default interface address")
+            .trunc();
+        db.execute(Statement::from_sql_and_values(
+            builder,
+            r#"INSERT INTO network (name, cidr, interface, listen_port, topology)
+            SELECT ?, ?, name, CAST(listen_port AS INTEGER), 'hub'
+            FROM node_v0 WHERE relay ORDER BY id LIMIT 1"#,
+            [DEFAULT_NETWORK.into(), cidr.to_string().into()],
+        ))
+        .await?;
+        db.execute(Statement::from_sql_and_values(
+            builder,
+            r#"INSERT INTO node (id, parent_id, network_id, relay, name, public_key, private_key,
+                listen_port, dns, allowed_ips, endpoint_allowed_ips, endpoint,
+                persistent_keepalive, mtu, post_up, post_down, pre_up, pre_down)
+            SELECT id, parent_id, (SELECT id FROM network WHERE name = ?), relay, name,
+                public_key, private_key, CAST(listen_port AS INTEGER), dns, allowed_ips,
+                endpoint_allowed_ips, endpoint, persistent_keepalive, CAST(mtu AS INTEGER),
+                post_up, post_down, pre_up, pre_down
+            FROM node_v0"#,
+            [DEFAULT_NETWORK.into()],
+        ))
+        .await?;
+        log::debug!(
+            "moved {} nodes of the first schema into network {}",
+            count,
+            DEFAULT_NETWORK
+        );
+    }
+    db.execute_unprepared("DROP TABLE node_v0").await?;
+    Ok(())
+}
+
+// column names of the table, none if the table does not exist
+async fn column_names<C: ConnectionTrait>(
+    db: &C,
+    table: &str,
+) -> anyhow::Result<HashSet<String>, DbErr> {
+    let rows = db
+        .query_all(Statement::from_string(
+            db.get_database_backend(),
+            format!("PRAGMA table_info({})", table),
+        ))
+        .await?;
+    rows.iter()
+        .map(|row| row.try_get::<String>("", "name"))
+        .collect()
+}
+
+pub(crate) async fn initialize_table<C: ConnectionTrait>(db: &C) -> anyhow::Result<(), DbErr> {
+    let builder = db.get_database_backend();
+    for stmt in tables() {
+        let result = db.execute(builder.build(&stmt)).await?;
+        log::debug!("create table {:?}: {:?}", stmt.get_table_name(), result);
+    }
+    Ok(())
+}
+
+// the tables of the current schema
+fn tables() -> Vec<TableCreateStatement> {
     use model::*;
     use sea_query::*;
 
-    let stmt = Table::create()
-        .table(node_relay::Entity)
-        .if_not_exists()
-        .col(
-            ColumnDef::new(node_relay::Column::Id)
-                .integer()
-                .not_null()
-                .auto_increment()
-                .primary_key(),
-        )
-        .col(ColumnDef::new(node_relay::Column::ParentId).integer())
-        .col(
-            ColumnDef::new(node_relay::Column::Relay)
-                .integer()
-                .not_null()
-                .default(Value::Bool(Some(false))),
-        )
-        .col(
-            ColumnDef::new(node_relay::Column::Name)
-                .string()
-                .not_null()
-                .unique_key(),
+    vec![
+        Table::create()
+            .table(network::Entity)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(network::Column::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(
+                ColumnDef::new(network::Column::Name)
+                    .string()
+                    .not_null()
+                    .unique_key(),
+            )
+            .col(ColumnDef::new(network::Column::Cidr).string().not_null())
+            .col(ColumnDef::new(network::Column::CidrV6).string())
+            .col(
+                ColumnDef::new(network::Column::Interface)
+                    .string()
+                    .not_null(),
+            )
+            .col(ColumnDef::new(network::Column::ListenPort).integer())
+            .col(ColumnDef::new(network::Column::Dns).string())
+            .col(
+                ColumnDef::new(network::Column::Topology)
+                    .string()
+                    .not_null()
+                    .default("hub"),
+            )
+            .col(ColumnDef::new(network::Column::Acl).string())
+            .to_owned(),
+        Table::create()
+            .table(node_relay::Entity)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(node_relay::Column::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(node_relay::Column::ParentId).integer())
+            .col(
+                ColumnDef::new(node_relay::Column::NetworkId)
+                    .integer()
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(node_relay::Column::Relay)
+                    .integer()
+                    .not_null()
+                    .default(Value::Bool(Some(false))),
+            )
+            .col(ColumnDef::new(node_relay::Column::Name).string().not_null())
+            .col(ColumnDef::new(node_relay::Column::Address).string())
+            .col(ColumnDef::new(node_relay::Column::PublicKey).string())
+            .col(ColumnDef::new(node_relay::Column::PrivateKey).string())
+            .col(ColumnDef::new(node_relay::Column::PreviousPublicKey).string())
+            .col(ColumnDef::new(node_relay::Column::PreviousPrivateKey).string())
+            .col(ColumnDef::new(node_relay::Column::PreviousKeyExpiresAt).big_integer())
+            .col(ColumnDef::new(node_relay::Column::ListenPort).integer())
+            .col(ColumnDef::new(node_relay::Column::Dns).string())
+            .col(ColumnDef::new(node_relay::Column::AllowedIps).string())
+            .col(ColumnDef::new(node_relay::Column::Routes).string())
+            .col(ColumnDef::new(node_relay::Column::EndpointAllowedIps).string())
+            .col(ColumnDef::new(node_relay::Column::Endpoint).string())
+            .col(ColumnDef::new(node_relay::Column::PersistentKeepalive).string())
+            .col(ColumnDef::new(node_relay::Column::Mtu).integer())
+            .col(ColumnDef::new(node_relay::Column::PostUp).string())
+            .col(ColumnDef::new(node_relay::Column::PostDown).string())
+            .col(ColumnDef::new(node_relay::Column::PreUp).string())
+            .col(ColumnDef::new(node_relay::Column::PreDown).string())
+            .col(ColumnDef::new(node_relay::Column::ExpiresAt).big_integer())
+            .col(ColumnDef::new(node_relay::Column::Tags).string())
+            .foreign_key(
+                ForeignKey::create()
+                    .from(node_relay::Entity, node_relay::Column::NetworkId)
+                    .to(network::Entity, network::Column::Id)
+                    .on_delete(ForeignKeyAction::Cascade),
+            )
+            // node names are unique within a network
+            .index(
+                Index::create()
+                    .unique()
+                    .col(node_relay::Column::NetworkId)
+                    .col(node_relay::Column::Name),
+            )
+            .to_owned(),
+        Table::create()
+            .table(keystore::Entity)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(keystore::Column::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(keystore::Column::Salt).string().not_null())
+            .col(
+                ColumnDef::new(keystore::Column::Verifier)
+                    .string()
+                    .not_null(),
+            )
+            .to_owned(),
+        // append-only history of the node changes
+        Table::create()
+            .table(audit_event::Entity)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(audit_event::Column::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(
+                ColumnDef::new(audit_event::Column::CreatedAt)
+                    .big_integer()
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(audit_event::Column::Network)
+                    .string()
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(audit_event::Column::Action)
+                    .string()
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(audit_event::Column::Node)
+                    .string()
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(audit_event::Column::User)
+                    .string()
+                    .not_null(),
+            )
+            .col(ColumnDef::new(audit_event::Column::Uid).big_integer())
+            .col(ColumnDef::new(audit_event::Column::Before).string())
+            .col(ColumnDef::new(audit_event::Column::After).string())
+            .to_owned(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::sqlite::SqliteStore;
+    use crate::conf::NodeOpt;
+    use crate::model::Node;
+
+    // the node table of the first schema
+    const BASELINE_SCHEMA: &str = r#"CREATE TABLE IF NOT EXISTS "node" (
+        "id" integer NOT NULL PRIMARY KEY AUTOINCREMENT, "parent_id" integer,
+        "relay" integer NOT NULL DEFAULT FALSE, "name" text NOT NULL UNIQUE, "public_key" text,
+        "private_key" text, "listen_port" text, "dns" text, "allowed_ips" text,
+        "endpoint_allowed_ips" text, "endpoint" text, "persistent_keepalive" text, "mtu" text,
+        "post_up" text, "post_down" text, "pre_up" text, "pre_down" text)"#;
+
+    async fn schema_version(db: &DbConn) -> i32 {
+        db.query_one(Statement::from_string(
+            db.get_database_backend(),
+            "PRAGMA user_version".to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get("", "user_version")
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_migrate_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DATABASE_FILE);
+        std::fs::File::create(&path).unwrap();
+        let db = sea_orm::Database::connect(format!("sqlite:{}", path.display()))
+            .await
+            .unwrap();
+        db.execute_unprepared(BASELINE_SCHEMA).await.unwrap();
+        db.execute_unprepared(
+            r#"INSERT INTO node (id, relay, name, public_key, private_key, listen_port, endpoint, mtu)
+            VALUES (1, 1, 'wg0', 'wg0-public', 'wg0-private', '51820', 'vpn.example.com:51820', '1420');
+            INSERT INTO node (id, parent_id, relay, name, public_key, private_key, allowed_ips,
+                persistent_keepalive, mtu)
+            VALUES (2, 1, 0, 'laptop', 'laptop-public', 'laptop-private', '10.66.66.2/32', '21', '1420')"#,
         )
-        .col(ColumnDef::new(node_relay::Column::PublicKey).string())
-        .col(ColumnDef::new(node_relay::Column::PrivateKey).string())
-        .col(ColumnDef::new(node_relay::Column::ListenPort).string())
-        .col(ColumnDef::new(node_relay::Column::Dns).string())
-        .col(ColumnDef::new(node_relay::Column::AllowedIps).string())
-        .col(ColumnDef::new(node_relay::Column::EndpointAllowedIps).string())
-        .col(ColumnDef::new(node_relay::Column::Endpoint).string())
-        .col(ColumnDef::new(node_relay::Column::PersistentKeepalive).string())
-        .col(ColumnDef::new(node_relay::Column::Mtu).string())
-        .col(ColumnDef::new(node_relay::Column::PostUp).string())
-        .col(ColumnDef::new(node_relay::Column::PostDown).string())
-        .col(ColumnDef::new(node_relay::Column::PreUp).string())
-        .col(ColumnDef::new(node_relay::Column::PreDown).string())
-        .to_owned();
+        .await
+        .unwrap();
+        db.close().await.unwrap();
 
-    let builder = db.get_database_backend();
-    let result = db.execute(builder.build(&stmt)).await?;
-    println!("Create table node: {:?}", result);
+        let db = connect(dir.path()).await.unwrap();
+        assert_eq!(schema_version(&db).await, SCHEMA_VERSION);
+        let mut store = SqliteStore::new(db, DEFAULT_NETWORK);
+        let network = store.network().await.unwrap();
+        assert_eq!(network.interface, "wg0");
+        assert_eq!(network.listen_port, Some(51820));
+        let laptop = store.get_by_name("laptop").await.unwrap();
+        assert_eq!(laptop.parent.as_deref(), Some("wg0"));
+        assert_eq!(laptop.private_key.as_deref(), Some("laptop-private"));
+        assert_eq!(laptop.mtu, Some(1420));
 
-    Ok(())
+        // the columns of the later schemas are usable
+        let mut phone = Node::default();
+        phone
+            .with_name(Some("phone".to_string()))
+            .with_address(Some(vec!["10.66.66.3/24".parse().unwrap()]))
+            .with_allowed_ips(Some(vec!["10.66.66.3/32".parse().unwrap()]))
+            .with_routes(Some(vec!["192.168.1.0/24".parse().unwrap()]))
+            .with_expires_at(Some(1_000))
+            .with_tags(Some(vec!["mobile".to_string()]));
+        store.push(phone).await.unwrap();
+        let names = |nodes: Vec<Node>| {
+            nodes
+                .iter()
+                .map(|n| n.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(store.list().await.unwrap()),
+            ["wg0", "laptop", "phone"]
+        );
+        assert_eq!(store.audit_list().await.unwrap().len(), 1);
+
+        // a migrated database is left as is
+        let db = connect(dir.path()).await.unwrap();
+        let mut store = SqliteStore::new(db, DEFAULT_NETWORK);
+        assert_eq!(store.list().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_missing_columns() {
+        let db = sea_orm::Database::connect("sqlite::memory:").await.unwrap();
+        initialize_table(&db).await.unwrap();
+        db.execute_unprepared(
+            "ALTER TABLE node DROP COLUMN tags; ALTER TABLE network DROP COLUMN acl",
+        )
+        .await
+        .unwrap();
+        assert!(!column_names(&db, "node").await.unwrap().contains("tags"));
+
+        migrate(&db).await.unwrap();
+        assert!(column_names(&db, "node").await.unwrap().contains("tags"));
+        assert!(column_names(&db, "network").await.unwrap().contains("acl"));
+        assert_eq!(schema_version(&db).await, SCHEMA_VERSION);
+    }
 }
@@ -1,2 +1,5 @@
+pub mod audit_event;
+pub mod keystore;
+pub mod network;
 pub mod node_relay;
 pub mod prelude;
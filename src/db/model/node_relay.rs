@@ -10,20 +10,31 @@ pub struct Model {
     pub id: i32,
     // wireguard node server ID
     pub parent_id: Option<i32>,
+    // wireguard network ID
+    pub network_id: i32,
     // wireguard server relay node
     pub relay: bool,
     // wireguard node name
     pub name: String,
+    // wireguard node interface address
+    pub address: Option<String>,
     // wireguard node public key
     pub public_key: String,
     // wireguard node private key 
     pub private_key: String,
+    // wireguard node key pair before the last rotation
+    pub previous_public_key: Option<String>,
+    pub previous_private_key: Option<String>,
+    // wireguard node previous key pair expiry, unix timestamp
+    pub previous_key_expires_at: Option<i64>,
     // wireguard node listen port
     pub listen_port: Option<u16>,
     // wireguard node dns parser
     pub dns: Option<String>,
     // wireguard node allowed ips
     pub allowed_ips: Option<String>,
+    // wireguard node advertised routes
+    pub routes: Option<String>,
     // wireguard endpoint route allowed ips
     pub endpoint_allowed_ips: Option<String>,
     pub persistent_keepalive: Option<String>,
@@ -35,9 +46,27 @@ pub struct Model {
     pub post_up: Option<String>,
     pub pre_down: Option<String>,
     pub post_down: Option<String>,
+    // wireguard node expiry, unix timestamp
+    pub expires_at: Option<i64>,
+    // wireguard node tags, comma separated
+    pub tags: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::network::Entity",
+        from = "Column::NetworkId",
+        to = "super::network::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Network,
+}
+
+impl Related<super::network::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Network.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
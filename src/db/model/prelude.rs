@@ -1,3 +1,6 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.10.6
 
+pub use super::audit_event::Entity as AuditEvent;
+pub use super::keystore::Entity as Keystore;
+pub use super::network::Entity as Network;
 pub use super::node_relay::Entity as NodeRelay;
@@ -1,34 +1,1135 @@
-use crate::args;
+use crate::clock::{self, Clock};
+use crate::conf::backup::{self, Archive};
+use crate::conf::cipher::{Cipher, Secret};
+use crate::conf::models::WireGuard;
+use crate::conf::sqlite::SqliteStore;
+use crate::conf::{export, listing, render, spec, NodeOpt};
+use crate::model::audit::{self, Action, AuditEvent};
+use crate::model::endpoint::Endpoint;
+use crate::model::network::{self, Network};
+use crate::model::{hook, topology, validate, Node};
+use crate::runner::Runner;
+use crate::standard::{BACKUP_PASSPHRASE_ENV, CLEAR_ANSWER, SESSION_ENV};
+use crate::{args, daemon, nft, parser, roaming};
 
 use anyhow::Context;
+use inquire::{Confirm, Password, Select, Text};
+use ipnet::IpNet;
+use wireguard_uapi::{Backend, Device, InterfaceName, KeyPair};
 
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
 const PEER_TYPE: &str = "peer";
 const PEER_SERVER_TYPE: &str = "peer-relay";
 
 pub(crate) async fn subcommand_new_handler(
-    _add_server: args::NewPeerRelayNetwork,
-    _config: PathBuf,
+    add_server: args::NewPeerRelayNetwork,
+    network_name: &str,
+    store: &mut dyn NodeOpt,
 ) -> anyhow::Result<()> {
+    if store.network().await.is_ok() {
+        if let Some(relay) = store.list_by_relay(true).await?.first() {
+            anyhow::bail!(
+                "network {} already has peer relay node {}",
+                network_name,
+                relay.name()
+            )
+        }
+    }
+    if let Some(network) = store
+        .list_networks()
+        .await?
+        .into_iter()
+        .find(|n| n.name.ne(network_name) && n.interface.eq(&add_server.relay.name))
+    {
+        anyhow::bail!(
+            "interface {} is already used by network {}",
+            network.interface,
+            network.name
+        )
+    }
+    let mut address = add_server.address;
+    if add_server.ula {
+        if address.iter().any(|a| matches!(a, IpNet::V6(_))) {
+            anyhow::bail!("address already has an IPv6 range")
+        }
+        let ula = network::random_ula();
+        let host = ula.hosts().nth(1).context("address is undefined")?;
+        address.push(IpNet::new(host, ula.prefix_len())?);
+    }
+    // the IPv4 range comes first in a dual-stack network
+    address.sort_by_key(|a| matches!(a, IpNet::V6(_)));
+    let cidr = address.first().context("address is undefined")?.trunc();
+    let mut network = Network::new(
+        network_name.to_string(),
+        cidr,
+        add_server.relay.name.clone(),
+    );
+    network.cidr_v6 = address
+        .iter()
+        .skip(1)
+        .find(|a| matches!(a, IpNet::V6(_)))
+        .map(IpNet::trunc);
+    network.listen_port = Some(add_server.relay.listen_port);
+    network.dns = add_server.dns;
+    network.topology = add_server.topology;
+    store.push_network(network).await?;
+
+    let mut node = Node::from(add_server.relay);
+    node.with_address(Some(address));
+    let name = node.name().to_string();
+    store.push(node).await?;
+    log::info!("peer relay node {} has been created", name);
+    Ok(())
+}
+
+pub(crate) async fn subcommand_add_relay_handler(
+    add_relay: args::AddRelay,
+    store: &mut dyn NodeOpt,
+) -> anyhow::Result<()> {
+    let network = store.network().await?;
+    let mut node = Node::from(add_relay.relay);
+    let name = node.name().to_string();
+    if store.get_by_name(&name).await.is_ok() {
+        anyhow::bail!("Duplicate node {} name", name)
+    }
+    let address = match add_relay.address {
+        Some(address) => address,
+        None => network.allocate(&store.list().await?)?,
+    };
+    node.with_address(Some(address));
+    store.push(node).await?;
+    log::info!(
+        "peer relay node {} has joined network {}",
+        name,
+        network.name
+    );
     Ok(())
 }
 
 pub(crate) async fn subcommand_add_peer_handler(
-    _add_peer: args::AddPeer,
-    _config: PathBuf,
+    add_peer: args::AddPeer,
+    store: &mut dyn NodeOpt,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    let network = store.network().await?;
+    let now = clock.now();
+    let expires_at = match add_peer.expires_in {
+        Some(expires_in) => Some(
+            i64::try_from(expires_in.as_secs())
+                .ok()
+                .and_then(|secs| now.checked_add(secs))
+                .with_context(|| format!("peer {} would expire too late", add_peer.name))?,
+        ),
+        None => add_peer.expires_at,
+    };
+    if matches!(expires_at, Some(expires_at) if expires_at <= now) {
+        anyhow::bail!("peer {} would expire in the past", add_peer.name)
+    }
+    let mut node = Node::from(add_peer);
+    node.with_expires_at(expires_at);
+    let name = node.name().to_string();
+    if store.get_by_name(&name).await.is_ok() {
+        anyhow::bail!("Duplicate node {} name", name)
+    }
+    // addresses are allocated per network
+    if node.address.is_none() {
+        node.with_address(Some(network.allocate(&store.list().await?)?));
+    }
+    if node.endpoint_allowed_ips.is_none() {
+        node.with_endpoint_allowed_ips(Some(network.prefixes()));
+    }
+    // the first relay serves the peer unless told otherwise
+    if node.parent.is_none() {
+        let relay = store.list_by_relay(true).await?;
+        let relay = relay.first().context("please add peer relay node first")?;
+        node.with_parent(Some(relay.name().to_string()));
+    }
+    store.push(node).await?;
+    if let Some(expires_at) = expires_at {
+        log::info!(
+            "{} {} expires at {}",
+            PEER_TYPE,
+            name,
+            clock::format(expires_at)
+        );
+    }
+    print_and_qrcode(render::node_config_at(
+        &network,
+        &name,
+        &store.list().await?,
+        now,
+    )?)
+}
+
+pub(crate) async fn subcommand_list_handler(
+    list: args::List,
+    store: &mut dyn NodeOpt,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    let now = clock.now();
+    let node_list = match list.role {
+        Some(role) => store.list_by_relay(role == listing::Role::Relay).await?,
+        None => store.list().await?,
+    }
+    .into_iter()
+    .filter(|n| !list.expired || n.is_expired(now))
+    .filter(|n| list.tag.iter().all(|tag| n.has_tag(tag)))
+    .collect::<Vec<Node>>();
+    // the handshakes are only known while the interface is up
+    let network = store.network().await?;
+    let handshakes = network
+        .interface
+        .parse::<InterfaceName>()
+        .ok()
+        .and_then(|interface| Device::get(&interface, Backend::default()).ok())
+        .map(|device| {
+            device
+                .peers
+                .iter()
+                .filter_map(|peer| {
+                    let handshake = peer
+                        .stats
+                        .last_handshake_time?
+                        .duration_since(UNIX_EPOCH)
+                        .ok()?
+                        .as_secs() as i64;
+                    (handshake > 0).then(|| (peer.config.public_key.to_base64(), handshake))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut rows = listing::rows(&node_list, &store.audit_list().await?, &handshakes);
+    if let Some(key) = list.sort {
+        listing::sort(&mut rows, key);
+    }
+    print!("{}", listing::render(&rows, list.output)?);
+    Ok(())
+}
+
+pub(crate) async fn subcommand_revoke_peer_handler(
+    revoke_peer: args::RevokePeer,
+    store: &mut dyn NodeOpt,
+) -> anyhow::Result<()> {
+    if let Some(tag) = revoke_peer.tag {
+        let peers = store
+            .list_by_relay(false)
+            .await?
+            .iter()
+            .filter(|n| n.has_tag(&tag))
+            .map(|n| n.name().to_string())
+            .collect::<Vec<String>>();
+        if peers.is_empty() {
+            anyhow::bail!("there is no {} tagged {}", PEER_TYPE, tag)
+        }
+        let confirm = revoke_peer.yes
+            || Confirm::new(&format!(
+                "Revoke {} peers tagged {}: {}?",
+                peers.len(),
+                tag,
+                peers.join(", ")
+            ))
+            .with_default(false)
+            .prompt()?;
+        if confirm {
+            store.remove_by_names(&peers).await?;
+            log::info!("{} peers tagged {} have been revoked", peers.len(), tag);
+        }
+        return Ok(());
+    }
+    let peers = store
+        .list_by_relay(false)
+        .await?
+        .iter()
+        .map(|n| n.name().to_string())
+        .collect::<Vec<String>>();
+    if peers.is_empty() {
+        anyhow::bail!("there is no {} to revoke", PEER_TYPE)
+    }
+    let name = Select::new("Which peer do you want to revoke?", peers).prompt()?;
+    let confirm = Confirm::new(&format!("Revoke {}?", name))
+        .with_default(false)
+        .prompt()?;
+    if confirm {
+        store.remove_by_name(&name).await?;
+        log::info!("{} {} has been revoked", PEER_TYPE, name);
+    }
+    Ok(())
+}
+
+pub(crate) async fn subcommand_edit_peer_handler(
+    edit_peer: args::EditPeer,
+    store: &mut dyn NodeOpt,
+) -> anyhow::Result<()> {
+    let name = edit_peer.name.clone();
+    let before = store.get_by_name(&name).await?;
+    let live = edit_peer.live;
+    let interactive = edit_peer.interactive;
+    let clear = edit_peer.clear;
+    let mut rename = edit_peer.rename.clone();
+    let mut after = before.clone();
+    WireGuard::map_set(&mut after, Node::from(edit_peer));
+    clear.apply(&mut after);
+    if interactive {
+        rename = prompt_edit(&mut after, rename.as_deref())?;
+    }
+    let rename = rename.filter(|new_name| new_name.ne(&name));
+
+    let settings_changed = serde_json::to_value(&after)?.ne(&serde_json::to_value(&before)?);
+    if !settings_changed && rename.is_none() {
+        println!("nothing to change for {}", name);
+        return Ok(());
+    }
+    if let Some(new_name) = &rename {
+        after.with_name(Some(new_name.clone()));
+    }
+
+    // the store refuses an edit that conflicts with the other nodes
+    let network = store.network().await?;
+    store
+        .edit(&name, after.clone(), rename.as_deref())
+        .await
+        .with_context(|| format!("failed to edit {}", name))?;
+    if store.network().await?.acl.ne(&network.acl) {
+        log::info!("the ACL refers to {} as {}", name, after.name());
+    }
+    log::info!("{} {} has been updated", PEER_TYPE, after.name());
+
+    if live {
+        crate::sudo()?;
+        let backend = Backend::default();
+        let interface = network.interface.parse::<InterfaceName>()?;
+        let device = Device::get(&interface, backend)
+            .with_context(|| format!("interface {} is not up", interface))?;
+        let device_key = device
+            .public_key
+            .with_context(|| format!("interface {} has no public key", interface))?;
+        let node_list = store.list().await?;
+        let node = store.get_by_name(after.name()).await?;
+        match daemon::edit_update(&device_key, &node, &node_list)? {
+            Some(update) => {
+                update.apply(&interface, backend)?;
+                log::info!(
+                    "interface {} uses the new settings of {}",
+                    interface,
+                    node.name()
+                );
+            }
+            None => log::warn!(
+                "interface {} does not serve {}, reload its relay to apply the change",
+                interface,
+                node.name()
+            ),
+        }
+    }
+    Ok(())
+}
+
+// prompt the settings of the node pre-filled with the current values, an unchanged or
+// empty answer leaves the setting as is and `-` clears it; the new name is returned
+fn prompt_edit(node: &mut Node, rename: Option<&str>) -> anyhow::Result<Option<String>> {
+    fn prompt<T>(
+        message: &str,
+        initial: String,
+        parse: impl Fn(&str) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Option<Option<T>>> {
+        loop {
+            let input = Text::new(message).with_initial_value(&initial).prompt()?;
+            match answer(&input, &initial) {
+                None => return Ok(None),
+                Some(None) => return Ok(Some(None)),
+                Some(Some(input)) => match parse(input) {
+                    Ok(value) => return Ok(Some(Some(value))),
+                    Err(e) => println!("{}", e),
+                },
+            }
+        }
+    }
+    fn join<T: ToString>(values: &Option<Vec<T>>) -> String {
+        values
+            .iter()
+            .flatten()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+    fn number(value: Option<u16>) -> String {
+        value.map(|v| v.to_string()).unwrap_or_default()
+    }
+    let text = |s: &str| Ok(s.to_string());
+
+    // the name and the address can not be cleared
+    let current = node.clone();
+    let new_name = prompt("Name:", rename.unwrap_or(current.name()).to_string(), text)?.flatten();
+    node.with_endpoint(
+        prompt(
+            "Endpoint:",
+            current
+                .endpoint
+                .as_ref()
+                .map(Endpoint::to_string)
+                .unwrap_or_default(),
+            |s| s.parse::<Endpoint>(),
+        )?
+        .unwrap_or(current.endpoint.clone()),
+    )
+    .with_address(
+        prompt(
+            "Address:",
+            join(&current.address),
+            parser::parser_interface_address,
+        )?
+        .flatten()
+        .or(current.address.clone()),
+    )
+    .with_allowed_ips(
+        prompt(
+            "AllowedIPs:",
+            join(&current.allowed_ips),
+            parser::parser_address_in_range,
+        )?
+        .unwrap_or(current.allowed_ips.clone()),
+    )
+    .with_routes(
+        prompt(
+            "Routes:",
+            join(&current.routes),
+            parser::parser_address_in_range,
+        )?
+        .unwrap_or(current.routes.clone()),
+    )
+    .with_mtu(prompt("MTU:", number(current.mtu), parser::parser_mtu)?.unwrap_or(current.mtu))
+    .with_persistent_keepalive(
+        prompt(
+            "PersistentKeepalive:",
+            number(current.persistent_keepalive),
+            |s| Ok(s.parse::<u16>()?),
+        )?
+        .unwrap_or(current.persistent_keepalive),
+    )
+    .with_post_up(
+        prompt("PostUp:", current.post_up.clone().unwrap_or_default(), text)?
+            .unwrap_or(current.post_up.clone()),
+    )
+    .with_post_down(
+        prompt(
+            "PostDown:",
+            current.post_down.clone().unwrap_or_default(),
+            text,
+        )?
+        .unwrap_or(current.post_down.clone()),
+    )
+    .with_pre_up(
+        prompt("PreUp:", current.pre_up.clone().unwrap_or_default(), text)?
+            .unwrap_or(current.pre_up.clone()),
+    )
+    .with_pre_down(
+        prompt(
+            "PreDown:",
+            current.pre_down.clone().unwrap_or_default(),
+            text,
+        )?
+        .unwrap_or(current.pre_down.clone()),
+    )
+    .with_tags(
+        prompt("Tags:", join(&current.tags), |s| {
+            s.split(',')
+                .map(|t| parser::parser_tag(t.trim()))
+                .collect::<anyhow::Result<Vec<String>>>()
+                .map(crate::model::tags)
+        })?
+        .map(Option::flatten)
+        .unwrap_or(current.tags.clone()),
+    );
+    Ok(new_name.or(rename.map(ToString::to_string)))
+}
+
+// answer to a prompt pre-filled with `initial`: none when the setting is left as is,
+// some none when it is cleared, else the new value
+fn answer<'a>(input: &'a str, initial: &str) -> Option<Option<&'a str>> {
+    let input = input.trim();
+    if input.is_empty() || input.eq(initial) {
+        None
+    } else if input.eq(CLEAR_ANSWER) {
+        Some(None)
+    } else {
+        Some(Some(input))
+    }
+}
+
+pub(crate) async fn subcommand_validate_handler(store: &mut dyn NodeOpt) -> anyhow::Result<()> {
+    let network = store.network().await?;
+    let node_list = store.list().await?;
+    let conflicts = validate::validate(&network, &node_list);
+    let findings = network.acl.check(&network, &node_list);
+    if conflicts.is_empty() && findings.is_empty() {
+        println!("no conflicts in network {}", network.name);
+        return Ok(());
+    }
+    for conflict in &conflicts {
+        println!("{}", conflict);
+    }
+    for finding in &findings {
+        println!("acl: {}", finding);
+    }
+    if findings.is_empty() {
+        anyhow::bail!("{} conflicts in network {}", conflicts.len(), network.name)
+    }
+    anyhow::bail!(
+        "{} conflicts and {} ACL findings in network {}",
+        conflicts.len(),
+        findings.len(),
+        network.name
+    )
+}
+
+pub(crate) async fn subcommand_log_handler(
+    audit_log: args::Log,
+    store: &mut dyn NodeOpt,
 ) -> anyhow::Result<()> {
+    let filter = audit::Filter {
+        node: audit_log.node,
+        action: audit_log.action,
+        user: audit_log.user,
+        since: audit_log.since,
+        until: audit_log.until,
+    };
+    let events = store
+        .audit_list()
+        .await?
+        .into_iter()
+        .filter(|e| filter.matches(e))
+        .collect::<Vec<AuditEvent>>();
+    // the latest events are kept
+    let skip = match audit_log.limit {
+        Some(limit) => events.len().saturating_sub(limit),
+        None => 0,
+    };
+    for event in &events[skip..] {
+        let uid = event
+            .uid
+            .map(|uid| format!("({})", uid))
+            .unwrap_or_default();
+        println!(
+            "{} {}{} {} {}",
+            clock::format(event.created_at),
+            event.user,
+            uid,
+            event.action.as_str(),
+            event.node
+        );
+        if audit_log.verbose {
+            if let Some(before) = &event.before {
+                println!("  before: {}", before);
+            }
+            if let Some(after) = &event.after {
+                println!("  after:  {}", after);
+            }
+        }
+    }
     Ok(())
 }
 
-pub(crate) async fn subcommand_revoke_peer_handler(_config: PathBuf) -> anyhow::Result<()> {
+pub(crate) async fn subcommand_export_handler(
+    export: args::Export,
+    store: &mut dyn NodeOpt,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    let network = store.network().await?;
+    let content = export::export(
+        export.format,
+        &network,
+        export.name.as_deref(),
+        export.tag.as_deref(),
+        &store.list().await?,
+        clock.now(),
+    )?;
+    let path = export.output.unwrap_or_else(|| {
+        let name = match (&export.name, &export.tag) {
+            (Some(name), _) => name.clone(),
+            (None, Some(tag)) => format!("{}-{}", network.name, tag),
+            (None, None) => network.name.clone(),
+        };
+        PathBuf::from(format!("{}.{}", name, export.format.extension()))
+    });
+    // the exported files hold private keys
+    write_private(&path, &content)?;
+    log::info!("exported {}", path.display());
     Ok(())
 }
 
-pub(crate) async fn subcommand_print_peer_handler(_config: PathBuf) -> anyhow::Result<()> {
+pub(crate) async fn subcommand_rotate_key_handler(
+    rotate_key: args::RotateKey,
+    store: &mut dyn NodeOpt,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    let network = store.network().await?;
+    let before = store.get_by_name(&rotate_key.name).await?;
+    let old_public_key = before
+        .public_key
+        .clone()
+        .context("node public key is undefined")?;
+    let now = clock.now();
+    let mut node = before.clone();
+    if rotate_key.rollback {
+        node.rollback_key(now)?;
+        if let Some(event) = store
+            .audit_list()
+            .await?
+            .into_iter()
+            .rev()
+            .find(|e| e.action == Action::Rotate && e.node.eq(node.name()))
+        {
+            log::info!(
+                "rolling back the rotation of {} made by {} at {}",
+                node.name(),
+                event.user,
+                event.created_at
+            );
+        }
+    } else {
+        let key_pair = KeyPair::generate();
+        node.rotate_key(
+            key_pair.public.to_base64(),
+            key_pair.private.to_base64(),
+            grace_expiry(now, rotate_key.grace)?,
+        );
+    }
+    store.set_keys(&node).await?;
+    let node_list = store.list().await?;
+
+    // swap the keys on the live relay device, if it serves the node
+    let backend = Backend::default();
+    let interface = network.interface.parse::<InterfaceName>()?;
+    match Device::get(&interface, backend) {
+        Ok(device) => {
+            if let Some(device_key) = &device.public_key {
+                if let Some(update) =
+                    daemon::rotation_update(device_key, &old_public_key, &node, &node_list)?
+                {
+                    update.apply(&interface, backend)?;
+                    log::info!(
+                        "interface {} uses the new key of {}",
+                        interface,
+                        node.name()
+                    );
+                }
+            }
+        }
+        Err(e) => log::debug!("interface {} is not available: {}", interface, e),
+    }
+
+    // the rotated node and the peers whose configurations hold its public key
+    let public_key = node.public_key.clone().unwrap_or_default();
+    let mut changed = vec![(
+        node.name().to_string(),
+        render::node_config_at(&network, node.name(), &node_list, now)?,
+    )];
+    for other in node_list.iter().filter(|n| n.name().ne(node.name())) {
+        let config = render::node_config_at(&network, other.name(), &node_list, now)?;
+        if config.contains(&public_key) {
+            changed.push((other.name().to_string(), config));
+        }
+    }
+    match rotate_key.output_dir {
+        Some(output_dir) => {
+            std::fs::create_dir_all(&output_dir)?;
+            for (name, config) in &changed {
+                let path = output_dir.join(format!("{}.conf", name));
+                write_private(&path, config.as_bytes())?;
+                log::info!("regenerated {}", path.display());
+            }
+        }
+        None => {
+            for (name, config) in &changed {
+                println!("# ---------------- {} ----------------\n{}", name, config);
+            }
+        }
+    }
     Ok(())
 }
 
+// time the previous key pair of a rotation expires, `grace` hours after `now`
+fn grace_expiry(now: i64, grace: u64) -> anyhow::Result<i64> {
+    i64::try_from(grace)
+        .ok()
+        .and_then(|grace| grace.checked_mul(3600))
+        .and_then(|secs| now.checked_add(secs))
+        .with_context(|| format!("a grace period of {} hours is too long", grace))
+}
+
+pub(crate) async fn subcommand_apply_handler(
+    apply: args::Apply,
+    store: &mut dyn NodeOpt,
+) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(&apply.file)
+        .with_context(|| format!("failed to read {}", apply.file.display()))?;
+    let spec = spec::parse(&content)?;
+    let network_name = spec.network.name.clone();
+    let current = store.state().await?;
+    let network_list = spec::desired(&current, spec, || {
+        let key_pair = KeyPair::generate();
+        (key_pair.public.to_base64(), key_pair.private.to_base64())
+    })?;
+    backup::validate(&network_list)?;
+
+    let changes = backup::diff(&current, &network_list);
+    if changes.is_empty() {
+        println!(
+            "network {} already matches {}",
+            network_name,
+            apply.file.display()
+        );
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    println!("\n{}", spec::summary(&changes));
+    if apply.dry_run {
+        return Ok(());
+    }
+    if !apply.yes
+        && !Confirm::new("Apply the plan?")
+            .with_default(false)
+            .prompt()?
+    {
+        return Ok(());
+    }
+    let state = network_list
+        .into_iter()
+        .find(|s| s.network.name.eq(&network_name))
+        .context("the applied network is missing from the desired state")?;
+    store.apply(state).await?;
+    log::info!(
+        "applied {} to network {}",
+        apply.file.display(),
+        network_name
+    );
+    Ok(())
+}
+
+pub(crate) async fn subcommand_backup_handler(
+    backup: args::Backup,
+    store: &mut dyn NodeOpt,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    let secret = if backup.encrypt {
+        Some(backup_passphrase(true)?)
+    } else {
+        None
+    };
+    let network_list = store.state().await?;
+    let archive = Archive::new(network_list, clock.now(), secret.as_ref())?;
+    // an archive without encryption holds the private keys in plaintext
+    write_private(&backup.file, archive.to_content(&backup.file)?.as_bytes())?;
+    log::info!(
+        "backed up {} networks to {}",
+        archive.network_list.len(),
+        backup.file.display()
+    );
+    Ok(())
+}
+
+pub(crate) async fn subcommand_restore_handler(
+    restore: args::Restore,
+    store: &mut dyn NodeOpt,
+) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(&restore.file)
+        .with_context(|| format!("failed to read {}", restore.file.display()))?;
+    let archive = Archive::parse(&content)?;
+    let secret = if archive.is_encrypted() {
+        Some(backup_passphrase(false)?)
+    } else {
+        None
+    };
+    let network_list = archive.into_network_list(secret.as_ref())?;
+    backup::validate(&network_list)?;
+
+    let changes = backup::diff(&store.state().await?, &network_list);
+    if changes.is_empty() {
+        println!("the store already matches {}", restore.file.display());
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    if restore.dry_run {
+        return Ok(());
+    }
+    if !restore.yes
+        && !Confirm::new(&format!("Apply {} changes?", changes.len()))
+            .with_default(false)
+            .prompt()?
+    {
+        return Ok(());
+    }
+    store.restore(network_list).await?;
+    log::info!("restored {}", restore.file.display());
+    Ok(())
+}
+
+// passphrase of an encrypted backup, from the environment or prompted
+fn backup_passphrase(confirm: bool) -> anyhow::Result<Secret> {
+    if let Ok(passphrase) = std::env::var(BACKUP_PASSPHRASE_ENV) {
+        return Ok(Secret::Passphrase(passphrase));
+    }
+    let mut prompt = Password::new("Backup passphrase:");
+    if !confirm {
+        prompt = prompt.without_confirmation();
+    }
+    Ok(Secret::Passphrase(prompt.prompt()?))
+}
+
+pub(crate) async fn subcommand_unlock_handler(
+    config_dir: &Path,
+    network: &str,
+    secret: Option<Secret>,
+) -> anyhow::Result<()> {
+    let mut store = SqliteStore::open(config_dir, network).await?;
+    if !store.is_encrypted().await? {
+        anyhow::bail!("the store is not encrypted, run `wgsdc rekey-store` to encrypt it")
+    }
+    let secret = match secret {
+        Some(secret) => secret,
+        None => Secret::Passphrase(
+            Password::new("Store passphrase:")
+                .without_confirmation()
+                .prompt()?,
+        ),
+    };
+    store.unlock(Some(&secret)).await?;
+    let cipher = store.cipher().context("the store is locked")?;
+    println!("export {}={}", SESSION_ENV, cipher.session());
+    Ok(())
+}
+
+pub(crate) async fn subcommand_rekey_store_handler(
+    rekey_store: args::RekeyStore,
+    config_dir: &Path,
+    network: &str,
+    secret: Option<Secret>,
+) -> anyhow::Result<()> {
+    let mut store = SqliteStore::open(config_dir, network).await?;
+    if store.is_encrypted().await? {
+        let secret = match secret {
+            Some(secret) => secret,
+            None => Secret::Passphrase(
+                Password::new("Current store passphrase:")
+                    .without_confirmation()
+                    .prompt()?,
+            ),
+        };
+        store.unlock(Some(&secret)).await?;
+    }
+    let new_secret = if rekey_store.plaintext {
+        None
+    } else if let Some(path) = rekey_store.new_key_file {
+        if !path.exists() {
+            write_private(&path, format!("{}\n", Cipher::generate_key()).as_bytes())?;
+            log::info!("generated key file {}", path.display());
+        }
+        Some(Secret::KeyFile(path))
+    } else {
+        Some(Secret::Passphrase(
+            Password::new("New store passphrase:").prompt()?,
+        ))
+    };
+    let count = store.rekey(new_secret.as_ref()).await?;
+    match new_secret {
+        Some(_) => log::info!("{} private keys have been encrypted", count),
+        None => log::info!("{} private keys have been decrypted", count),
+    }
+    Ok(())
+}
+
+pub(crate) async fn subcommand_print_peer_handler(
+    store: &mut dyn NodeOpt,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    let network = store.network().await?;
+    let node_list = store.list().await?;
+    let options = node_list
+        .iter()
+        .map(|n| {
+            let node_type = if n.relay { PEER_SERVER_TYPE } else { PEER_TYPE };
+            format!("{} ({})", n.name(), node_type)
+        })
+        .collect::<Vec<String>>();
+    if options.is_empty() {
+        anyhow::bail!("please add peer relay node first")
+    }
+    let selected = Select::new("Which configuration do you want to print?", options)
+        .raw_prompt()?
+        .index;
+    let node = &node_list[selected];
+    let config = render::node_config_at(&network, node.name(), &node_list, clock.now())?;
+    if node.relay {
+        println!("{}", config);
+        Ok(())
+    } else {
+        print_and_qrcode(config)
+    }
+}
+
+pub(crate) async fn subcommand_status_handler(
+    store: &mut dyn NodeOpt,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    crate::sudo()?;
+    let network = store.network().await?;
+    let node_list = store.list().await?;
+    let backend = Backend::default();
+    let interface = network.interface.parse::<InterfaceName>()?;
+    let mut device = Device::get(&interface, backend)
+        .with_context(|| format!("interface {} is not up", interface))?;
+    // the expired peers are revoked from the interface
+    let expired = daemon::expired_update(&device.peers, &node_list, clock.now());
+    if !expired.is_empty() {
+        expired.apply(&interface, backend)?;
+        device = Device::get(&interface, backend)?;
+    }
+
+    println!("interface: {}", interface);
+    if let Some(listen_port) = device.listen_port {
+        println!("  listening port: {}", listen_port);
+    }
+    for peer in &device.peers {
+        let public_key = peer.config.public_key.to_base64();
+        let name = node_list
+            .iter()
+            .find(|n| n.public_key.as_deref() == Some(public_key.as_str()))
+            .map_or("(unknown)", Node::name);
+        println!("\npeer: {} ({})", name, public_key);
+        if let Some(endpoint) = peer.config.endpoint {
+            println!("  endpoint: {}", endpoint);
+        }
+        let latest_handshake = peer
+            .stats
+            .last_handshake_time
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .filter(|secs| *secs > 0);
+        println!(
+            "  latest handshake: {}",
+            latest_handshake.map_or("never".to_string(), clock::format)
+        );
+        println!(
+            "  transfer: {} B received, {} B sent",
+            peer.stats.rx_bytes, peer.stats.tx_bytes
+        );
+    }
+    Ok(())
+}
+
+pub(crate) async fn subcommand_firewall_handler(
+    firewall: args::Firewall,
+    store: &mut dyn NodeOpt,
+) -> anyhow::Result<()> {
+    let network = store.network().await?;
+    // rules that match no node still drop the traffic of the peers
+    let acl = if network.acl.is_enforced() {
+        Some(network.acl.compile(&store.list().await?))
+    } else {
+        None
+    };
+    let mut ruleset = nft::Ruleset::new(&network);
+    ruleset
+        .with_egress(firewall.egress)
+        .with_isolate(firewall.isolate)
+        .with_acl(acl);
+    if firewall.dry_run {
+        if firewall.remove {
+            print!("{}", nft::remove_script(&ruleset.table));
+        } else {
+            print!("{}", ruleset.apply_script());
+        }
+        return Ok(());
+    }
+    crate::sudo()?;
+    if firewall.remove {
+        nft::remove(&ruleset.table)?;
+        log::info!("removed table {}", ruleset.table);
+    } else {
+        nft::apply(&ruleset)?;
+        log::info!(
+            "loaded table {} for interface {}",
+            ruleset.table,
+            ruleset.interface
+        );
+    }
+    Ok(())
+}
+
+pub(crate) async fn subcommand_acl_handler(
+    acl: args::Acl,
+    store: &mut dyn NodeOpt,
+) -> anyhow::Result<()> {
+    let mut network = store.network().await?;
+    match acl.command {
+        args::AclCommand::List => {
+            for (group, nodes) in &network.acl.groups {
+                println!("group:{} = {}", group, nodes.join(", "));
+            }
+            if network.acl.rules.is_empty() {
+                println!(
+                    "no rules, the relay forwards all traffic of network {}",
+                    network.name
+                );
+            }
+            for (i, rule) in network.acl.rules.iter().enumerate() {
+                println!("{:>3}  {}", i + 1, rule);
+            }
+            return Ok(());
+        }
+        args::AclCommand::Allow { rule } => {
+            if network.acl.rules.contains(&rule) {
+                anyhow::bail!("rule `{}` already exists", rule)
+            }
+            log::info!("allow {}", rule);
+            network.acl.rules.push(rule);
+        }
+        args::AclCommand::Remove { number } => {
+            if number == 0 || number > network.acl.rules.len() {
+                anyhow::bail!("no rule number {}", number)
+            }
+            let rule = network.acl.rules.remove(number - 1);
+            log::info!("removed {}", rule);
+        }
+        args::AclCommand::Group { name, nodes } => {
+            if nodes.is_empty() {
+                if network.acl.groups.remove(&name).is_none() {
+                    anyhow::bail!("group {} is not defined", name)
+                }
+                log::info!("removed group {}", name);
+            } else {
+                log::info!("group {} = {}", name, nodes.join(", "));
+                network.acl.groups.insert(name, nodes);
+            }
+        }
+    }
+    for finding in network.acl.check(&network, &store.list().await?) {
+        log::warn!("{}", finding);
+    }
+    store.push_network(network).await?;
+    log::info!("reload the relay ruleset and the peer configurations to apply the rules");
+    Ok(())
+}
+
+pub(crate) async fn subcommand_up_handler(
+    up: args::UpDown,
+    store: &mut dyn NodeOpt,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    crate::sudo()?;
+    let network = store.network().await?;
+    let node_list = store.list().await?;
+    let mut relay = daemon::select_relay(up.relay.as_deref(), &node_list)?.clone();
+    hook::render_hooks(&network, &mut relay)?;
+    let node_list = topology::unexpired(&node_list, relay.name(), clock.now());
+    let backend = Backend::default();
+    let interface = network.interface.parse::<InterfaceName>()?;
+    if Device::get(&interface, backend).is_ok() {
+        anyhow::bail!("interface {} is already up", interface)
+    }
+    let runner = Runner::new(interface.to_string(), Duration::from_secs(up.hook_timeout));
+    runner
+        .up(
+            &relay,
+            || daemon::bring_up(&interface, &relay, &node_list, backend),
+            || Ok(Device::get(&interface, backend)?.delete()?),
+        )
+        .await?;
+    log::info!("interface {} is up", interface);
+    Ok(())
+}
+
+pub(crate) async fn subcommand_down_handler(
+    down: args::UpDown,
+    store: &mut dyn NodeOpt,
+) -> anyhow::Result<()> {
+    crate::sudo()?;
+    let network = store.network().await?;
+    let node_list = store.list().await?;
+    let mut relay = daemon::select_relay(down.relay.as_deref(), &node_list)?.clone();
+    hook::render_hooks(&network, &mut relay)?;
+    let backend = Backend::default();
+    let interface = network.interface.parse::<InterfaceName>()?;
+    let device = Device::get(&interface, backend)
+        .with_context(|| format!("interface {} is not up", interface))?;
+    let runner = Runner::new(
+        interface.to_string(),
+        Duration::from_secs(down.hook_timeout),
+    );
+    runner.down(&relay, || Ok(device.delete()?)).await?;
+    log::info!("interface {} is down", interface);
+    Ok(())
+}
+
+pub(crate) async fn subcommand_reresolve_handler(
+    reresolve: args::Reresolve,
+    mut store: Box<dyn NodeOpt>,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    crate::sudo()?;
+    roaming::run(
+        store.as_mut(),
+        &roaming::DnsResolver,
+        clock,
+        Duration::from_secs(reresolve.interval),
+        Backend::default(),
+    )
+    .await
+}
+
+pub(crate) async fn subcommand_daemon_handler(
+    daemon: args::Daemon,
+    store: Box<dyn NodeOpt>,
+    clock: &dyn Clock,
+) -> anyhow::Result<()> {
+    crate::sudo()?;
+    daemon::Daemon::new(
+        store,
+        daemon.relay,
+        &roaming::DnsResolver,
+        clock,
+        Duration::from_secs(daemon.reresolve_interval),
+        Duration::from_secs(daemon.hook_timeout),
+        Backend::default(),
+    )
+    .run(Duration::from_secs(daemon.interval))
+    .await
+}
+
+#[cfg(feature = "api")]
+pub(crate) async fn subcommand_serve_handler(
+    serve: args::Serve,
+    store: Box<dyn NodeOpt>,
+    clock: std::sync::Arc<dyn Clock>,
+) -> anyhow::Result<()> {
+    let state = crate::api::ApiState::new(store, serve.token, clock, Backend::default())?;
+    crate::api::serve(serve.listen, state).await
+}
+
+// write a file only readable by its owner, the mode only applies to a new file so an
+// existing one is restricted before the content is written
+fn write_private(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| {
+            #[cfg(unix)]
+            file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+            file.write_all(content)
+        })
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
 fn print_and_qrcode(string: String) -> anyhow::Result<()> {
     let repeat_bounds = "-".repeat(70);
     println!(
@@ -37,3 +1138,40 @@ fn print_and_qrcode(string: String) -> anyhow::Result<()> {
     );
     qr2term::print_qr(string).context("Failed to generate QRCode configuration")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer() {
+        assert_eq!(answer("", "1420"), None);
+        assert_eq!(answer(" 1420 ", "1420"), None);
+        assert_eq!(answer("-", "1420"), Some(None));
+        assert_eq!(answer(" - ", ""), Some(None));
+        assert_eq!(answer("1280", "1420"), Some(Some("1280")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("laptop.conf");
+        std::fs::write(&path, "public").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, b"private").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "private");
+    }
+
+    #[test]
+    fn test_grace_expiry() {
+        assert_eq!(grace_expiry(1_700_000_000, 24).unwrap(), 1_700_086_400);
+        assert_eq!(grace_expiry(1_700_000_000, 0).unwrap(), 1_700_000_000);
+        assert!(grace_expiry(1_700_000_000, u64::MAX).is_err());
+        assert!(grace_expiry(1_700_000_000, i64::MAX as u64 / 3600).is_err());
+    }
+}
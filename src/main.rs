@@ -1,61 +1,152 @@
 extern crate core;
 
-use std::{path::PathBuf, str::FromStr};
+use std::sync::Arc;
 
 use anyhow::anyhow;
-use dirs::home_dir;
-use sea_orm::{ActiveValue, Database, EntityTrait};
+use args::SubCommands;
+use clock::{Clock, SystemClock};
 
+#[cfg(feature = "api")]
+mod api;
 mod args;
+mod clock;
 mod conf;
+mod daemon;
 pub mod db;
 mod handler;
 pub mod model;
+mod nft;
 mod parser;
+mod roaming;
+mod runner;
 pub mod standard;
 mod wg;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<(), Box<dyn std::error::Error>> {
-    let test = dirs::home_dir().unwrap().join("db");
-    let db_path = test.as_path();
-    println!("{}", db_path.display());
-   
-    
-    db::initialize_database(test.clone()).await?;
-
-    let node = db::model::node_relay::ActiveModel {
-        name: ActiveValue::Set("HappyTest".to_owned()),
-        relay: ActiveValue::Set(true),
-        ..Default::default()
-    };
-    let db = sea_orm::Database::connect(format!("sqlite:{}", test.display())).await?;
-    let result = db::model::prelude::NodeRelay::insert(node).exec(&db).await?;
-    println!("{:?}", result);
-
     use clap::Parser;
     let wgsdc = args::Opt::parse();
     // enabled debug mode
     init_log(wgsdc.debug);
-    // match wgsdc.commands {
-    //     Some(SubCommands::New(add_interface)) => {
-    //         handler::subcommand_new_handler(add_interface, wgsdc.dir).await?
-    //     }
-    //
-    //     Some(SubCommands::AddPeer(add_peer)) => {
-    //         handler::subcommand_add_peer_handler(add_peer, wgsdc.dir).await?
-    //     }
-    //
-    //     Some(SubCommands::RevokePeer) => {
-    //         handler::subcommand_revoke_peer_handler(wgsdc.dir).await?
-    //     }
-    //
-    //     Some(SubCommands::PrintPeer) => {
-    //         handler::subcommand_print_peer_handler(wgsdc.dir).await?;
-    //     }
-    //
-    //     None => {}
-    // }
+    let commands = match wgsdc.commands {
+        Some(commands) => commands,
+        None => return Ok(()),
+    };
+    let secret = conf::cipher::Secret::from_env(wgsdc.key_file);
+    // the store key commands open the sqlite store themselves
+    let commands = match commands {
+        SubCommands::Unlock => {
+            return Ok(
+                handler::subcommand_unlock_handler(&wgsdc.dir, &wgsdc.network, secret).await?,
+            )
+        }
+        SubCommands::RekeyStore(rekey_store) => {
+            return Ok(handler::subcommand_rekey_store_handler(
+                rekey_store,
+                &wgsdc.dir,
+                &wgsdc.network,
+                secret,
+            )
+            .await?)
+        }
+        commands => commands,
+    };
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let mut store = conf::open(
+        wgsdc.store,
+        &wgsdc.dir,
+        &wgsdc.network,
+        secret.as_ref(),
+        clock.clone(),
+    )
+    .await?;
+    match commands {
+        SubCommands::New(add_interface) => {
+            handler::subcommand_new_handler(add_interface, &wgsdc.network, store.as_mut()).await?
+        }
+
+        SubCommands::AddRelay(add_relay) => {
+            handler::subcommand_add_relay_handler(add_relay, store.as_mut()).await?
+        }
+
+        SubCommands::AddPeer(add_peer) => {
+            handler::subcommand_add_peer_handler(add_peer, store.as_mut(), clock.as_ref()).await?
+        }
+
+        SubCommands::EditPeer(edit_peer) => {
+            handler::subcommand_edit_peer_handler(edit_peer, store.as_mut()).await?
+        }
+
+        SubCommands::RevokePeer(revoke_peer) => {
+            handler::subcommand_revoke_peer_handler(revoke_peer, store.as_mut()).await?
+        }
+
+        SubCommands::PrintPeer => {
+            handler::subcommand_print_peer_handler(store.as_mut(), clock.as_ref()).await?;
+        }
+
+        SubCommands::List(list) => {
+            handler::subcommand_list_handler(list, store.as_mut(), clock.as_ref()).await?
+        }
+
+        SubCommands::Status => {
+            handler::subcommand_status_handler(store.as_mut(), clock.as_ref()).await?
+        }
+
+        SubCommands::Validate => handler::subcommand_validate_handler(store.as_mut()).await?,
+
+        SubCommands::Log(audit_log) => {
+            handler::subcommand_log_handler(audit_log, store.as_mut()).await?
+        }
+
+        SubCommands::Export(export) => {
+            handler::subcommand_export_handler(export, store.as_mut(), clock.as_ref()).await?
+        }
+
+        SubCommands::RotateKey(rotate_key) => {
+            handler::subcommand_rotate_key_handler(rotate_key, store.as_mut(), clock.as_ref())
+                .await?
+        }
+
+        SubCommands::Apply(apply) => {
+            handler::subcommand_apply_handler(apply, store.as_mut()).await?
+        }
+
+        SubCommands::Backup(backup) => {
+            handler::subcommand_backup_handler(backup, store.as_mut(), clock.as_ref()).await?
+        }
+
+        SubCommands::Restore(restore) => {
+            handler::subcommand_restore_handler(restore, store.as_mut()).await?
+        }
+
+        SubCommands::Reresolve(reresolve) => {
+            handler::subcommand_reresolve_handler(reresolve, store, clock.as_ref()).await?
+        }
+
+        SubCommands::Daemon(daemon) => {
+            handler::subcommand_daemon_handler(daemon, store, clock.as_ref()).await?
+        }
+
+        #[cfg(feature = "api")]
+        SubCommands::Serve(serve) => {
+            handler::subcommand_serve_handler(serve, store, clock.clone()).await?
+        }
+
+        SubCommands::Firewall(firewall) => {
+            handler::subcommand_firewall_handler(firewall, store.as_mut()).await?
+        }
+
+        SubCommands::Acl(acl) => handler::subcommand_acl_handler(acl, store.as_mut()).await?,
+
+        SubCommands::Up(up) => {
+            handler::subcommand_up_handler(up, store.as_mut(), clock.as_ref()).await?
+        }
+
+        SubCommands::Down(down) => handler::subcommand_down_handler(down, store.as_mut()).await?,
+
+        SubCommands::Unlock | SubCommands::RekeyStore(_) => unreachable!(),
+    }
     Ok(())
 }
 
@@ -0,0 +1,595 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::model::network::Network;
+use crate::model::topology::{self, Topology};
+use crate::model::Node;
+
+// nodes a rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    // every node and every destination
+    Any,
+    Node(String),
+    Group(String),
+    // the nodes of a tag
+    Tag(String),
+    // an address range, e.g. the LAN behind the relay
+    Cidr(IpNet),
+}
+
+impl std::str::FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(Selector::Any);
+        }
+        if let Some(name) = s.strip_prefix("node:") {
+            return Ok(Selector::Node(name.to_string()));
+        }
+        if let Some(name) = s.strip_prefix("group:") {
+            return Ok(Selector::Group(name.to_string()));
+        }
+        if let Some(tag) = s.strip_prefix("tag:") {
+            return Ok(Selector::Tag(tag.to_string()));
+        }
+        match s.parse::<IpNet>() {
+            Ok(cidr) => Ok(Selector::Cidr(cidr)),
+            Err(_) => anyhow::bail!(
+                "unknown selector {}, expected *, node:NAME, group:NAME, tag:TAG or an address range",
+                s
+            ),
+        }
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::Any => f.write_str("*"),
+            Selector::Node(name) => write!(f, "node:{}", name),
+            Selector::Group(name) => write!(f, "group:{}", name),
+            Selector::Tag(tag) => write!(f, "tag:{}", tag),
+            Selector::Cidr(cidr) => write!(f, "{}", cidr),
+        }
+    }
+}
+
+// traffic a rule allows, a port range of a protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    Tcp(Option<(u16, u16)>),
+    Udp(Option<(u16, u16)>),
+    Icmp,
+}
+
+impl Port {
+    // nft match of the port, icmp depends on the address family
+    fn to_nft(self, v6: bool) -> String {
+        let range = |protocol: &str, range: Option<(u16, u16)>| match range {
+            None => format!("meta l4proto {}", protocol),
+            Some((first, last)) if first == last => format!("{} dport {}", protocol, first),
+            Some((first, last)) => format!("{} dport {}-{}", protocol, first, last),
+        };
+        match self {
+            Port::Tcp(ports) => range("tcp", ports),
+            Port::Udp(ports) => range("udp", ports),
+            Port::Icmp if v6 => "meta l4proto ipv6-icmp".to_string(),
+            Port::Icmp => "meta l4proto icmp".to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for Port {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (protocol, ports) = match s.split_once('/') {
+            Some((protocol, ports)) => (protocol, Some(ports)),
+            None => (s, None),
+        };
+        let range = match ports {
+            Some(ports) => {
+                let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+                let (first, last) = (first.parse::<u16>()?, last.parse::<u16>()?);
+                if first > last {
+                    anyhow::bail!("invalid port range {}", ports)
+                }
+                Some((first, last))
+            }
+            None => None,
+        };
+        match protocol {
+            "tcp" => Ok(Port::Tcp(range)),
+            "udp" => Ok(Port::Udp(range)),
+            "icmp" if range.is_none() => Ok(Port::Icmp),
+            _ => anyhow::bail!(
+                "unknown port {}, expected tcp/PORT, udp/FIRST-LAST or icmp",
+                s
+            ),
+        }
+    }
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (protocol, range) = match self {
+            Port::Tcp(range) => ("tcp", range),
+            Port::Udp(range) => ("udp", range),
+            Port::Icmp => return f.write_str("icmp"),
+        };
+        match range {
+            None => f.write_str(protocol),
+            Some((first, last)) if first == last => write!(f, "{}/{}", protocol, first),
+            Some((first, last)) => write!(f, "{}/{}-{}", protocol, first, last),
+        }
+    }
+}
+
+// traffic allowed from the source to the destination, every port when none is given,
+// written as `group:dev -> node:db tcp/5432,icmp`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rule {
+    pub from: Selector,
+    pub to: Selector,
+    pub ports: Vec<Port>,
+}
+
+impl std::str::FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, rest) = s.split_once("->").ok_or_else(|| {
+            anyhow::anyhow!("invalid rule {}, expected SOURCE -> DESTINATION [PORTS]", s)
+        })?;
+        let mut rest = rest.split_whitespace();
+        let to = rest
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("rule {} has no destination", s))?;
+        let mut ports = Vec::new();
+        for port in rest
+            .flat_map(|ports| ports.split(','))
+            .filter(|p| !p.is_empty())
+        {
+            if port != "*" {
+                ports.push(port.parse()?);
+            }
+        }
+        Ok(Rule {
+            from: from.trim().parse()?,
+            to: to.parse()?,
+            ports,
+        })
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.from, self.to)?;
+        if !self.ports.is_empty() {
+            let ports = self
+                .ports
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>();
+            write!(f, " {}", ports.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Rule> for String {
+    fn from(rule: Rule) -> Self {
+        rule.to_string()
+    }
+}
+
+// access control of a network, once there is a rule the relay drops the traffic of
+// its leaves that no rule allows
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Acl {
+    // named sets of nodes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+}
+
+impl Acl {
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty() && self.rules.is_empty()
+    }
+
+    // whether the relay filters the traffic of its leaves
+    pub fn is_enforced(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    fn matches(&self, selector: &Selector, node: &Node) -> bool {
+        match selector {
+            Selector::Any => true,
+            Selector::Node(name) => node.name().eq(name),
+            Selector::Group(group) => matches!(
+                self.groups.get(group),
+                Some(members) if members.iter().any(|m| m.eq(node.name()))
+            ),
+            Selector::Tag(tag) => node.has_tag(tag),
+            Selector::Cidr(cidr) => node
+                .address
+                .iter()
+                .flatten()
+                .any(|a| cidr.contains(&a.addr())),
+        }
+    }
+
+    // address ranges of the selector, the addresses and routes of its nodes; none for any
+    fn networks(&self, selector: &Selector, node_list: &[Node]) -> Option<Vec<IpNet>> {
+        match selector {
+            Selector::Any => None,
+            Selector::Cidr(cidr) => Some(vec![*cidr]),
+            _ => Some(
+                node_list
+                    .iter()
+                    .filter(|n| self.matches(selector, n))
+                    .flat_map(topology::node_routes)
+                    .collect(),
+            ),
+        }
+    }
+
+    // follow a renamed node, whether a rule or a group referred to it
+    pub fn rename(&mut self, node_name: &str, new_name: &str) -> bool {
+        let mut renamed = false;
+        for selector in self.rules.iter_mut().flat_map(|r| [&mut r.from, &mut r.to]) {
+            if matches!(selector, Selector::Node(name) if name == node_name) {
+                *selector = Selector::Node(new_name.to_string());
+                renamed = true;
+            }
+        }
+        for member in self
+            .groups
+            .values_mut()
+            .flatten()
+            .filter(|m| m.as_str() == node_name)
+        {
+            *member = new_name.to_string();
+            renamed = true;
+        }
+        renamed
+    }
+
+    // nft matches of the rules, each line accepts the traffic of a rule for one address family
+    pub fn compile(&self, node_list: &[Node]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for rule in &self.rules {
+            let from = self.networks(&rule.from, node_list);
+            let to = self.networks(&rule.to, node_list);
+            for v6 in [false, true] {
+                let family = |networks: &Option<Vec<IpNet>>| {
+                    networks.as_ref().map(|networks| {
+                        networks
+                            .iter()
+                            .filter(|n| matches!(n, IpNet::V6(_)) == v6)
+                            .map(ToString::to_string)
+                            .collect::<Vec<String>>()
+                    })
+                };
+                let (from, to) = (family(&from), family(&to));
+                // a rule between nodes without addresses of the family
+                if matches!(&from, Some(from) if from.is_empty())
+                    || matches!(&to, Some(to) if to.is_empty())
+                {
+                    continue;
+                }
+                let prefix = if v6 { "ip6" } else { "ip" };
+                let mut matches = Vec::new();
+                if let Some(from) = from {
+                    matches.push(format!("{} saddr {{ {} }}", prefix, from.join(", ")));
+                }
+                if let Some(to) = to {
+                    matches.push(format!("{} daddr {{ {} }}", prefix, to.join(", ")));
+                }
+                let ports = match rule.ports.is_empty() {
+                    true => vec![None],
+                    false => rule.ports.iter().map(|p| Some(p.to_nft(v6))).collect(),
+                };
+                for port in ports {
+                    let line = matches
+                        .iter()
+                        .cloned()
+                        .chain(port)
+                        .chain(std::iter::once("accept".to_string()))
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    // a rule without addresses is the same for both families
+                    if !lines.contains(&line) {
+                        lines.push(line);
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    // address ranges the node exchanges traffic with through the relay, none when the
+    // node is not restricted
+    pub fn allowed_ips(&self, node: &Node, node_list: &[Node]) -> Option<Vec<IpNet>> {
+        if !self.is_enforced() {
+            return None;
+        }
+        let own = topology::node_routes(node).collect::<Vec<IpNet>>();
+        let mut allowed_ips = Vec::new();
+        for rule in &self.rules {
+            // the destination answers the source, so both ends are allowed
+            for (end, other) in [(&rule.from, &rule.to), (&rule.to, &rule.from)] {
+                if self.matches(end, node) {
+                    allowed_ips.extend(self.networks(other, node_list)?);
+                }
+            }
+        }
+        allowed_ips.retain(|n| !own.contains(n));
+        allowed_ips.sort();
+        allowed_ips.dedup();
+        Some(allowed_ips)
+    }
+
+    // rules that cannot match and rules that allow more than they should
+    pub fn check(&self, network: &Network, node_list: &[Node]) -> Vec<String> {
+        let mut findings = Vec::new();
+        let exists = |name: &str| node_list.iter().any(|n| n.name().eq(name));
+        for (group, members) in &self.groups {
+            for member in members.iter().filter(|m| !exists(m)) {
+                findings.push(format!("group {}: node {} does not exist", group, member));
+            }
+        }
+        for rule in &self.rules {
+            for selector in [&rule.from, &rule.to] {
+                match selector {
+                    Selector::Node(name) if !exists(name) => findings.push(format!(
+                        "rule `{}` is unreachable, node {} does not exist",
+                        rule, name
+                    )),
+                    Selector::Group(group) if !self.groups.contains_key(group) => {
+                        findings.push(format!(
+                            "rule `{}` is unreachable, group {} is not defined",
+                            rule, group
+                        ))
+                    }
+                    Selector::Group(group)
+                        if !node_list.iter().any(|n| self.matches(selector, n)) =>
+                    {
+                        findings.push(format!(
+                            "rule `{}` is unreachable, group {} has no nodes",
+                            rule, group
+                        ))
+                    }
+                    Selector::Tag(tag) if !node_list.iter().any(|n| n.has_tag(tag)) => findings
+                        .push(format!(
+                            "rule `{}` is unreachable, no node is tagged {}",
+                            rule, tag
+                        )),
+                    _ => {}
+                }
+            }
+            // the relay receives the traffic addressed to itself, it does not forward it
+            if !matches!(rule.to, Selector::Any | Selector::Cidr(_)) {
+                for relay in node_list
+                    .iter()
+                    .filter(|n| n.relay && self.matches(&rule.to, n))
+                {
+                    findings.push(format!(
+                        "rule `{}` does not apply to relay {}, its own traffic is not forwarded",
+                        rule,
+                        relay.name()
+                    ));
+                }
+            }
+            if rule.ports.is_empty() && rule.to == Selector::Any {
+                findings.push(format!(
+                    "rule `{}` is over-permissive, it allows all traffic to any destination",
+                    rule
+                ));
+            } else if rule.ports.is_empty() && rule.from == Selector::Any {
+                findings.push(format!(
+                    "rule `{}` is over-permissive, it allows every node all traffic to {}",
+                    rule, rule.to
+                ));
+            }
+        }
+        if self.is_enforced() && network.topology != Topology::Hub {
+            findings.push(format!(
+                "the {} topology lets leaves peer directly, the rules only apply to the traffic through the relay",
+                network.topology.as_str()
+            ));
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::{home, node};
+
+    fn node_list() -> Vec<Node> {
+        let mut node_list = vec![
+            node("wg0", true, "10.66.66.1/24"),
+            node("laptop", false, "10.66.66.2/24"),
+            node("phone", false, "10.66.66.3/24"),
+            node("db", false, "10.66.66.4/24"),
+        ];
+        node_list[3].with_address(Some(vec![
+            "10.66.66.4/24".parse().unwrap(),
+            "fd00::4/64".parse().unwrap(),
+        ]));
+        node_list
+    }
+
+    fn acl(rules: &[&str]) -> Acl {
+        Acl {
+            groups: BTreeMap::from([(
+                "dev".to_string(),
+                vec!["laptop".to_string(), "phone".to_string()],
+            )]),
+            rules: rules.iter().map(|r| r.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule: Rule = "group:dev -> node:db tcp/5432,udp/60000-61000 icmp"
+            .parse()
+            .unwrap();
+        assert_eq!(rule.from, Selector::Group("dev".to_string()));
+        assert_eq!(rule.to, Selector::Node("db".to_string()));
+        assert_eq!(
+            rule.ports,
+            [
+                Port::Tcp(Some((5432, 5432))),
+                Port::Udp(Some((60000, 61000))),
+                Port::Icmp
+            ]
+        );
+        assert_eq!(
+            rule.to_string(),
+            "group:dev -> node:db tcp/5432,udp/60000-61000,icmp"
+        );
+        let rule: Rule = "192.168.1.0/24->*".parse().unwrap();
+        assert_eq!(rule.to_string(), "192.168.1.0/24 -> *");
+
+        assert!("node:db".parse::<Rule>().is_err());
+        assert!("laptop -> node:db".parse::<Rule>().is_err());
+        assert!("node:laptop -> node:db tcp/80-20".parse::<Rule>().is_err());
+        assert!("node:laptop -> node:db icmp/8".parse::<Rule>().is_err());
+
+        let yaml = serde_yaml::to_string(&acl(&["group:dev -> node:db tcp/5432"])).unwrap();
+        assert!(yaml.contains("- group:dev -> node:db tcp/5432"));
+        assert_eq!(
+            serde_yaml::from_str::<Acl>(&yaml).unwrap(),
+            acl(&["group:dev -> node:db tcp/5432"])
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut acl = acl(&["group:dev -> node:db tcp/5432", "node:phone -> *"]);
+        assert!(acl.rename("phone", "tablet"));
+        assert_eq!(acl.groups["dev"], ["laptop", "tablet"]);
+        assert_eq!(acl.rules[1].to_string(), "node:tablet -> *");
+        assert!(!acl.rename("nas", "storage"));
+    }
+
+    #[test]
+    fn test_compile() {
+        let acl = acl(&[
+            "group:dev -> node:db tcp/5432",
+            "node:db -> * icmp",
+            "node:laptop -> 192.168.1.0/24",
+        ]);
+        assert_eq!(
+            acl.compile(&node_list()),
+            [
+                "ip saddr { 10.66.66.2/32, 10.66.66.3/32 } ip daddr { 10.66.66.4/32 } tcp dport 5432 accept",
+                "ip saddr { 10.66.66.4/32 } meta l4proto icmp accept",
+                "ip6 saddr { fd00::4/128 } meta l4proto ipv6-icmp accept",
+                "ip saddr { 10.66.66.2/32 } ip daddr { 192.168.1.0/24 } accept",
+            ]
+        );
+        assert_eq!(Acl::default().compile(&node_list()), Vec::<String>::new());
+
+        // the nodes of a tag
+        let mut node_list = node_list();
+        node_list[2].with_tags(Some(vec!["ops".to_string()]));
+        assert_eq!(
+            self::acl(&["tag:ops -> node:db tcp/22"]).compile(&node_list),
+            ["ip saddr { 10.66.66.3/32 } ip daddr { 10.66.66.4/32 } tcp dport 22 accept"]
+        );
+    }
+
+    #[test]
+    fn test_allowed_ips() {
+        let node_list = node_list();
+        let acl = acl(&[
+            "group:dev -> node:db tcp/5432",
+            "node:laptop -> 192.168.1.0/24",
+        ]);
+        let allowed_ips = |name: &str| {
+            let node = node_list.iter().find(|n| n.name().eq(name)).unwrap();
+            acl.allowed_ips(node, &node_list).map(|nets| {
+                nets.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+            })
+        };
+        assert_eq!(
+            allowed_ips("laptop").unwrap(),
+            ["10.66.66.4/32", "192.168.1.0/24", "fd00::4/128"]
+        );
+        assert_eq!(
+            allowed_ips("phone").unwrap(),
+            ["10.66.66.4/32", "fd00::4/128"]
+        );
+        assert_eq!(
+            allowed_ips("db").unwrap(),
+            ["10.66.66.2/32", "10.66.66.3/32"]
+        );
+        // a destination of any address is not narrowed
+        let acl = self::acl(&["node:phone -> *"]);
+        assert!(acl.allowed_ips(&node_list[2], &node_list).is_none());
+        // the others only reach the phone, which may reach them
+        assert_eq!(
+            acl.allowed_ips(&node_list[1], &node_list).unwrap(),
+            ["10.66.66.3/32".parse::<IpNet>().unwrap()]
+        );
+        assert!(Acl::default()
+            .allowed_ips(&node_list[1], &node_list)
+            .is_none());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut network = home();
+        let mut acl = acl(&[
+            "group:dev -> node:db tcp/5432",
+            "group:ops -> node:nas",
+            "* -> node:wg0",
+            "node:laptop -> *",
+        ]);
+        acl.groups
+            .insert("admin".to_string(), vec!["tablet".to_string()]);
+        acl.rules.push("group:admin -> node:db".parse().unwrap());
+        acl.rules.push("tag:ops -> node:db tcp/22".parse().unwrap());
+        network.topology = Topology::Mesh;
+        assert_eq!(
+            acl.check(&network, &node_list()),
+            [
+                "group admin: node tablet does not exist",
+                "rule `group:ops -> node:nas` is unreachable, group ops is not defined",
+                "rule `group:ops -> node:nas` is unreachable, node nas does not exist",
+                "rule `* -> node:wg0` does not apply to relay wg0, its own traffic is not forwarded",
+                "rule `* -> node:wg0` is over-permissive, it allows every node all traffic to node:wg0",
+                "rule `node:laptop -> *` is over-permissive, it allows all traffic to any destination",
+                "rule `group:admin -> node:db` is unreachable, group admin has no nodes",
+                "rule `tag:ops -> node:db tcp/22` is unreachable, no node is tagged ops",
+                "the mesh topology lets leaves peer directly, the rules only apply to the traffic through the relay",
+            ]
+        );
+        network.topology = Topology::Hub;
+        assert!(self::acl(&["group:dev -> node:db tcp/5432"])
+            .check(&network, &node_list())
+            .is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+use sea_orm::ActiveValue;
+use serde::{Deserialize, Serialize};
+
+use crate::db::model::audit_event;
+use crate::model::Node;
+
+// change made to a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+    // new key pair of the node
+    Rotate,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Rotate => "rotate",
+        }
+    }
+}
+
+impl std::str::FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(Action::Create),
+            "update" => Ok(Action::Update),
+            "delete" => Ok(Action::Delete),
+            "rotate" => Ok(Action::Rotate),
+            _ => anyhow::bail!("unknown audit action: {}", s),
+        }
+    }
+}
+
+// who changed which node of a network and how, never updated once recorded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    // unix timestamp of the change, set by the storage
+    pub created_at: i64,
+    // network of the node, set by the storage
+    pub network: String,
+    pub action: Action,
+    pub node: String,
+    // user that made the change, the user behind sudo if any
+    pub user: String,
+    pub uid: Option<u32>,
+    // node as JSON before and after the change, without private keys
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: Action, node: &str, before: Option<&Node>, after: Option<&Node>) -> Self {
+        Self {
+            created_at: 0,
+            network: String::new(),
+            action,
+            node: node.to_string(),
+            user: current_user(),
+            uid: current_uid(),
+            before: before.map(snapshot),
+            after: after.map(snapshot),
+        }
+    }
+}
+
+// conditions the listed events have to match, unset conditions match any event
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub node: Option<String>,
+    pub action: Option<Action>,
+    pub user: Option<String>,
+    // unix timestamps the events were recorded in between, both inclusive
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+impl Filter {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        (self.node.is_none() || self.node.as_deref() == Some(event.node.as_str()))
+            && (self.action.is_none() || self.action == Some(event.action))
+            && (self.user.is_none() || self.user.as_deref() == Some(event.user.as_str()))
+            && !matches!(self.since, Some(since) if event.created_at < since)
+            && !matches!(self.until, Some(until) if event.created_at > until)
+    }
+}
+
+// node as JSON, the private keys are left out
+fn snapshot(node: &Node) -> String {
+    let mut node = node.clone();
+    node.with_private_key(None);
+    node.previous_private_key = None;
+    serde_json::to_string(&node).unwrap_or_default()
+}
+
+// name of the user that made the change, looked up from its uid rather than read from
+// the environment, which the user controls
+#[cfg(unix)]
+fn current_user() -> String {
+    match current_uid() {
+        Some(uid) => user_name(uid).unwrap_or_else(|| uid.to_string()),
+        None => "unknown".to_string(),
+    }
+}
+
+#[cfg(not(unix))]
+fn current_user() -> String {
+    ["USER", "USERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(unix)]
+fn current_uid() -> Option<u32> {
+    // SAFETY: getuid has no preconditions and can not fail
+    let uid = unsafe { libc::getuid() };
+    Some(invoking_uid(uid, std::env::var("SUDO_UID").ok().as_deref()))
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> Option<u32> {
+    None
+}
+
+// uid of the user behind sudo, sudo runs the command as root and records the uid of the
+// invoking user; the variable is ignored unless the process runs as root
+fn invoking_uid(uid: u32, sudo_uid: Option<&str>) -> u32 {
+    match sudo_uid.and_then(|sudo_uid| sudo_uid.parse().ok()) {
+        Some(sudo_uid) if uid == 0 => sudo_uid,
+        _ => uid,
+    }
+}
+
+// login name of the uid in the user database
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: the entry and the buffer outlive the call, the buffer length is passed along
+    let code = unsafe {
+        libc::getpwuid_r(
+            uid,
+            passwd.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if code != 0 || result.is_null() {
+        return None;
+    }
+    // SAFETY: a found entry is initialized and its name points into the buffer
+    let name = unsafe { std::ffi::CStr::from_ptr((*result).pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+impl TryFrom<audit_event::Model> for AuditEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(model: audit_event::Model) -> Result<Self, Self::Error> {
+        Ok(AuditEvent {
+            created_at: model.created_at,
+            network: model.network,
+            action: model.action.parse()?,
+            node: model.node,
+            user: model.user,
+            uid: model.uid.and_then(|uid| u32::try_from(uid).ok()),
+            before: model.before,
+            after: model.after,
+        })
+    }
+}
+
+impl From<AuditEvent> for audit_event::ActiveModel {
+    fn from(event: AuditEvent) -> Self {
+        audit_event::ActiveModel {
+            created_at: ActiveValue::Set(event.created_at),
+            network: ActiveValue::Set(event.network),
+            action: ActiveValue::Set(event.action.as_str().to_string()),
+            node: ActiveValue::Set(event.node),
+            user: ActiveValue::Set(event.user),
+            uid: ActiveValue::Set(event.uid.map(i64::from)),
+            before: ActiveValue::Set(event.before),
+            after: ActiveValue::Set(event.after),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util;
+
+    fn node(name: &str, mtu: u16) -> Node {
+        let mut node = test_util::node(name, false, "10.66.66.2/24");
+        node.with_mtu(Some(mtu));
+        node
+    }
+
+    fn event(action: Action, name: &str, user: &str, created_at: i64) -> AuditEvent {
+        let mut event = AuditEvent::new(action, name, None, Some(&node(name, 1420)));
+        event.user = user.to_string();
+        event.created_at = created_at;
+        event
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let event = AuditEvent::new(
+            Action::Update,
+            "laptop",
+            Some(&node("laptop", 1420)),
+            Some(&node("laptop", 1280)),
+        );
+        let before: Node = serde_json::from_str(event.before.as_deref().unwrap()).unwrap();
+        let after: Node = serde_json::from_str(event.after.as_deref().unwrap()).unwrap();
+        assert_eq!((before.mtu, after.mtu), (Some(1420), Some(1280)));
+        assert_eq!(after.public_key.as_deref(), Some("laptop-public"));
+        assert!(after.private_key.is_none());
+        assert!(!event.after.unwrap().contains("laptop-private"));
+    }
+
+    #[test]
+    fn test_invoking_uid() {
+        assert_eq!(invoking_uid(0, Some("1000")), 1000);
+        assert_eq!(invoking_uid(0, None), 0);
+        assert_eq!(invoking_uid(0, Some("alice")), 0);
+        // only root runs on behalf of a sudo user
+        assert_eq!(invoking_uid(1001, Some("0")), 1001);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_user_name() {
+        assert_eq!(user_name(0).as_deref(), Some("root"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let events = [
+            event(Action::Create, "laptop", "alice", 100),
+            event(Action::Update, "laptop", "bob", 200),
+            event(Action::Delete, "phone", "alice", 300),
+        ];
+        let matched = |filter: Filter| {
+            events
+                .iter()
+                .filter(|e| filter.matches(e))
+                .map(|e| e.created_at)
+                .collect::<Vec<i64>>()
+        };
+        assert_eq!(matched(Filter::default()), [100, 200, 300]);
+        assert_eq!(
+            matched(Filter {
+                node: Some("laptop".to_string()),
+                ..Default::default()
+            }),
+            [100, 200]
+        );
+        assert_eq!(
+            matched(Filter {
+                user: Some("alice".to_string()),
+                action: Some(Action::Delete),
+                ..Default::default()
+            }),
+            [300]
+        );
+        assert_eq!(
+            matched(Filter {
+                since: Some(200),
+                until: Some(300),
+                ..Default::default()
+            }),
+            [200, 300]
+        );
+    }
+}
@@ -2,6 +2,8 @@ use crate::model::Node;
 use anyhow::Context;
 use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use wireguard_uapi::{Key, PeerConfigBuilder};
 
 // interface configuration of wireguard
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -184,6 +186,26 @@ impl Peer {
         self.endpoint = endpoint;
         self
     }
+
+    // Peer configuration for a live device. Domain endpoints are left to the resolver.
+    pub fn to_peer_config(&self) -> anyhow::Result<PeerConfigBuilder> {
+        let public_key = self.public_key()?;
+        let key = Key::from_base64(public_key)
+            .with_context(|| format!("invalid public key: {}", public_key))?;
+        let mut peer = PeerConfigBuilder::new(&key);
+        for allowed_ip in self.allowed_ips.as_deref().unwrap_or_default() {
+            peer = peer.add_allowed_ip(allowed_ip.addr(), allowed_ip.prefix_len());
+        }
+        if let Some(keepalive) = self.persistent_keepalive {
+            peer = peer.set_persistent_keepalive_interval(keepalive);
+        }
+        if let Some(ref endpoint) = self.endpoint {
+            if let Ok(address) = endpoint.address().parse::<IpAddr>() {
+                peer = peer.set_endpoint(SocketAddr::new(address, endpoint.port()));
+            }
+        }
+        Ok(peer)
+    }
 }
 
 impl From<Node> for Peer {
@@ -200,7 +222,15 @@ impl From<Node> for Peer {
             // peer relay address: 10.6.0.1/24
             // examples:
             let mut allowed_ips = node.allowed_ips.unwrap_or_default();
-            allowed_ips.extend(node.address.unwrap_or_default());
+            // only the node itself is routed through its address, e.g. 10.6.0.2/32
+            allowed_ips.extend(
+                node.address
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|address| IpNet::from(address.addr())),
+            );
+            // subnets advertised behind the node, e.g. 192.168.1.0/24
+            allowed_ips.extend(node.routes.unwrap_or_default());
             peer.with_public_key(node.public_key)
                 .with_persistent_keepalive(node.persistent_keepalive)
                 // peer relay allowed_ips
@@ -227,10 +257,41 @@ impl Endpoint {
             port,
         }
     }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    // the address is a domain name that has to be resolved
+    pub fn is_hostname(&self) -> bool {
+        self.address.parse::<std::net::IpAddr>().is_err()
+    }
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, port) = s
+            .rsplit_once(':')
+            .with_context(|| format!("endpoint '{}' has no port", s))?;
+        let port = port
+            .parse::<u16>()
+            .with_context(|| format!("endpoint '{}' has an invalid port", s))?;
+        let address = address.trim_start_matches('[').trim_end_matches(']');
+        Ok(Self::new(address.to_string(), port))
+    }
 }
 
 impl ToString for Endpoint {
     fn to_string(&self) -> String {
+        if self.address.contains(':') {
+            return format!("[{}]:{}", self.address, self.port);
+        }
         format!("{}:{}", self.address, self.port)
     }
 }
@@ -0,0 +1,258 @@
+use crate::model::network::Network;
+use crate::model::Node;
+use crate::standard::{
+    PRESET_FORWARD_POST_DOWN, PRESET_FORWARD_POST_UP, PRESET_NAT_POST_DOWN, PRESET_NAT_POST_UP,
+};
+use serde::{Deserialize, Serialize};
+
+// named PostUp/PostDown templates of common firewall setups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    // masquerade the network's traffic leaving through another interface
+    NatMasquerade,
+    // forward traffic in and out of the tunnel
+    Forward,
+}
+
+impl Preset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Preset::NatMasquerade => "nat-masquerade",
+            Preset::Forward => "forward",
+        }
+    }
+
+    // PostUp and PostDown templates of the preset
+    pub fn hooks(&self) -> (String, String) {
+        match self {
+            // the traffic leaving through any interface but the tunnel
+            Preset::NatMasquerade => masquerade("! -o {interface}"),
+            Preset::Forward => (
+                PRESET_FORWARD_POST_UP.to_string(),
+                PRESET_FORWARD_POST_DOWN.to_string(),
+            ),
+        }
+    }
+}
+
+// PostUp and PostDown of the nat-masquerade preset for the traffic leaving through the
+// interfaces the iptables match selects, e.g. `-o eth0`
+pub fn masquerade(out: &str) -> (String, String) {
+    (
+        PRESET_NAT_POST_UP.replace("{out}", out),
+        PRESET_NAT_POST_DOWN.replace("{out}", out),
+    )
+}
+
+impl std::str::FromStr for Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nat-masquerade" => Ok(Preset::NatMasquerade),
+            "forward" => Ok(Preset::Forward),
+            _ => anyhow::bail!("unknown hook preset: {}", s),
+        }
+    }
+}
+
+// PostUp and PostDown with the templates of the presets appended to the user's commands
+pub fn with_presets(
+    post_up: Option<String>,
+    post_down: Option<String>,
+    presets: &[Preset],
+) -> (Option<String>, Option<String>) {
+    presets
+        .iter()
+        .fold((post_up, post_down), |(post_up, post_down), preset| {
+            let (up, down) = preset.hooks();
+            (
+                super::with_hook(post_up, up),
+                super::with_hook(post_down, down),
+            )
+        })
+}
+
+// value of a hook variable of the node
+fn variable(network: &Network, node: &Node, name: &str) -> anyhow::Result<String> {
+    let undefined = || anyhow::anyhow!("hook variable {{{}}} is undefined", name);
+    match name {
+        // a relay runs the network's interface, a peer the one named after its configuration file
+        "interface" if node.relay => Ok(network.interface.clone()),
+        "interface" => Ok(node.name().to_string()),
+        "address" => node
+            .address
+            .iter()
+            .flatten()
+            .next()
+            .map(|address| address.addr().to_string())
+            .ok_or_else(undefined),
+        "subnet" => Ok(network.cidr.to_string()),
+        "listen_port" => node
+            .listen_port
+            .map(|port| port.to_string())
+            .ok_or_else(undefined),
+        _ => anyhow::bail!("unknown hook variable {{{}}}", name),
+    }
+}
+
+// replace the `{name}` variables of a hook; braces around anything but a name, shell
+// expansions like `${name}` and single-quoted text like awk programs are kept as they are
+// so that shell scripts and nft rule sets pass through
+pub fn render(template: &str, network: &Network, node: &Node) -> anyhow::Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    // quote the text is in so far
+    let mut quote = None;
+    while let Some(start) = rest.find(['{', '\'', '"']) {
+        rendered.push_str(&rest[..start]);
+        let c = rest.as_bytes()[start] as char;
+        let tail = &rest[start + 1..];
+        if c != '{' {
+            // a quote inside the other one is literal
+            match quote {
+                None => quote = Some(c),
+                Some(open) if open == c => quote = None,
+                Some(_) => {}
+            }
+            rendered.push(c);
+            rest = tail;
+            continue;
+        }
+        let name = tail
+            .find('}')
+            .map(|end| &tail[..end])
+            .filter(|name| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            })
+            .filter(|_| quote != Some('\'') && !rendered.ends_with('$'));
+        match name {
+            Some(name) => {
+                rendered.push_str(&variable(network, node, name)?);
+                rest = &tail[name.len() + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = tail;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+// render every hook of the node in place
+pub fn render_hooks(network: &Network, node: &mut Node) -> anyhow::Result<()> {
+    let rendered = [&node.pre_up, &node.post_up, &node.pre_down, &node.post_down]
+        .into_iter()
+        .map(|hook| match hook {
+            Some(hook) => render(hook, network, node).map(Some),
+            None => Ok(None),
+        })
+        .collect::<anyhow::Result<Vec<Option<String>>>>()
+        .map_err(|e| anyhow::anyhow!("{} in the hooks of {}", e, node.name()))?;
+    let [pre_up, post_up, pre_down, post_down]: [Option<String>; 4] =
+        rendered.try_into().expect("four hooks");
+    node.with_pre_up(pre_up)
+        .with_post_up(post_up)
+        .with_pre_down(pre_down)
+        .with_post_down(post_down);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::{home, node};
+
+    #[test]
+    fn test_render() {
+        let network = home();
+        let mut relay = node("wg0", true, "10.66.66.2/24");
+        relay.with_listen_port(Some(51820));
+        let laptop = node("laptop", false, "10.66.66.2/24");
+
+        let template = "echo {interface} {address} {subnet} {listen_port} %i";
+        assert_eq!(
+            render(template, &network, &relay).unwrap(),
+            "echo wg0 10.66.66.2 10.66.66.0/24 51820 %i"
+        );
+        assert_eq!(
+            render("ip link set {interface} up", &network, &laptop).unwrap(),
+            "ip link set laptop up"
+        );
+        // braces that do not hold a variable name are kept
+        let nft = "nft add chain inet wgsdc {interface} { type filter hook forward priority 0; }";
+        assert_eq!(
+            render(nft, &network, &relay).unwrap(),
+            "nft add chain inet wgsdc wg0 { type filter hook forward priority 0; }"
+        );
+        assert_eq!(render("{ {}", &network, &relay).unwrap(), "{ {}");
+        // so are shell expansions and single-quoted text
+        assert_eq!(
+            render("echo ${home} ${HOME} {interface}", &network, &relay).unwrap(),
+            "echo ${home} ${HOME} wg0"
+        );
+        let awk = r#"awk '{print}' /proc/net/dev; echo "{interface}'s {subnet}""#;
+        assert_eq!(
+            render(awk, &network, &relay).unwrap(),
+            r#"awk '{print}' /proc/net/dev; echo "wg0's 10.66.66.0/24""#
+        );
+
+        let err = render("echo {lan}", &network, &relay).unwrap_err();
+        assert_eq!(err.to_string(), "unknown hook variable {lan}");
+        let mut laptop = laptop;
+        laptop.with_pre_up(Some("echo {listen_port}".to_string()));
+        let err = render_hooks(&network, &mut laptop).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "hook variable {listen_port} is undefined in the hooks of laptop"
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        let (post_up, post_down) = with_presets(
+            Some("echo up".to_string()),
+            None,
+            &[Preset::Forward, Preset::NatMasquerade],
+        );
+        let mut relay = node("wg0", true, "10.66.66.2/24");
+        relay.with_post_up(post_up).with_post_down(post_down);
+        render_hooks(&home(), &mut relay).unwrap();
+        assert_eq!(
+            relay.post_up.as_deref(),
+            Some(
+                "echo up; sysctl -w net.ipv4.ip_forward=1; \
+                 iptables -A FORWARD -i wg0 -j ACCEPT; iptables -A FORWARD -o wg0 -j ACCEPT; \
+                 iptables -t nat -A POSTROUTING -s 10.66.66.0/24 ! -o wg0 -j MASQUERADE"
+            )
+        );
+        assert_eq!(
+            relay.post_down.as_deref(),
+            Some(
+                "iptables -D FORWARD -i wg0 -j ACCEPT; iptables -D FORWARD -o wg0 -j ACCEPT; \
+                 iptables -t nat -D POSTROUTING -s 10.66.66.0/24 ! -o wg0 -j MASQUERADE"
+            )
+        );
+        assert!(relay.pre_up.is_none());
+        assert_eq!(
+            "nat-masquerade".parse::<Preset>().unwrap(),
+            Preset::NatMasquerade
+        );
+        assert_eq!(Preset::Forward.as_str(), "forward");
+
+        // masquerading through a LAN interface
+        assert_eq!(
+            masquerade("-o eth0"),
+            (
+                "iptables -t nat -A POSTROUTING -s {subnet} -o eth0 -j MASQUERADE".to_string(),
+                "iptables -t nat -D POSTROUTING -s {subnet} -o eth0 -j MASQUERADE".to_string()
+            )
+        );
+    }
+}
@@ -1,28 +1,48 @@
-use crate::args::{AddPeer, NewPeerRelayNetwork};
+use crate::args::{AddPeer, ClearPeer, EditPeer, Relay};
+use crate::db::model::node_relay;
 use crate::model::endpoint::Endpoint;
-use crate::wg;
+use crate::{parser, wg};
 use ipnet::IpNet;
+use sea_orm::ActiveValue;
 use serde::{Deserialize, Serialize};
 
+pub mod acl;
+pub mod audit;
 pub mod endpoint;
+pub mod hook;
+pub mod network;
+#[cfg(test)]
+pub mod test_util;
+pub mod topology;
+pub mod validate;
 
 // node configuration of wireguard
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Node {
     // relay node
+    #[serde(default)]
     pub relay: bool,
     // node name
     pub name: Option<String>,
+    // name of the node's primary relay
+    pub parent: Option<String>,
     // server node address
     pub address: Option<Vec<IpNet>>,
     // node's public key
     pub public_key: Option<String>,
     // node's private key
     pub private_key: Option<String>,
+    // node's key pair before the last rotation, kept for a rollback until it expires
+    pub previous_public_key: Option<String>,
+    pub previous_private_key: Option<String>,
+    // unix timestamp the previous key pair expires at
+    pub previous_key_expires_at: Option<i64>,
     // node's listen port
     pub listen_port: Option<u16>,
     // node's router allowed ips
     pub allowed_ips: Option<Vec<IpNet>>,
+    // subnets behind the node, routed to it by every other node
+    pub routes: Option<Vec<IpNet>>,
     // node's endpoint router allowed ips
     pub endpoint_allowed_ips: Option<Vec<IpNet>>,
     // node's keep alive interval
@@ -39,6 +59,11 @@ pub struct Node {
     pub pre_down: Option<String>,
     // node's PostDown
     pub post_down: Option<String>,
+    // unix timestamp the node expires at, an expired node is revoked from the relays
+    pub expires_at: Option<i64>,
+    // labels selecting the node in bulk operations and access control rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
 }
 
 impl Node {
@@ -54,6 +79,10 @@ impl Node {
         self.name = name;
         self
     }
+    pub fn with_parent(&mut self, parent: Option<String>) -> &mut Node {
+        self.parent = parent;
+        self
+    }
     pub fn with_address(&mut self, address: Option<Vec<IpNet>>) -> &mut Node {
         self.address = address;
         self
@@ -66,6 +95,17 @@ impl Node {
         self.private_key = private_key;
         self
     }
+    pub fn with_previous_key(
+        &mut self,
+        public_key: Option<String>,
+        private_key: Option<String>,
+        expires_at: Option<i64>,
+    ) -> &mut Node {
+        self.previous_public_key = public_key;
+        self.previous_private_key = private_key;
+        self.previous_key_expires_at = expires_at;
+        self
+    }
     pub fn with_listen_port(&mut self, listen_port: Option<u16>) -> &mut Node {
         self.listen_port = listen_port;
         self
@@ -74,6 +114,10 @@ impl Node {
         self.allowed_ips = allowed_ips;
         self
     }
+    pub fn with_routes(&mut self, routes: Option<Vec<IpNet>>) -> &mut Node {
+        self.routes = routes;
+        self
+    }
     pub fn with_endpoint_allowed_ips(
         &mut self,
         endpoint_allowed_ips: Option<Vec<IpNet>>,
@@ -109,51 +153,292 @@ impl Node {
         self.post_down = post_down;
         self
     }
+    pub fn with_expires_at(&mut self, expires_at: Option<i64>) -> &mut Node {
+        self.expires_at = expires_at;
+        self
+    }
+    pub fn with_tags(&mut self, tags: Option<Vec<String>>) -> &mut Node {
+        self.tags = tags;
+        self
+    }
     pub fn name(&self) -> &str {
         self.name.as_deref().expect("peer is not named")
     }
 }
 
-impl From<NewPeerRelayNetwork> for Node {
-    fn from(add_peer_relay: NewPeerRelayNetwork) -> Self {
+impl Node {
+    pub fn is_expired(&self, now: i64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().flatten().any(|t| t.eq(tag))
+    }
+
+    // Replace the key pair, the current one is kept as the previous key pair until it
+    // expires so the rotation can be rolled back.
+    pub fn rotate_key(&mut self, public_key: String, private_key: String, expires_at: i64) {
+        let previous_public_key = self.public_key.replace(public_key);
+        let previous_private_key = self.private_key.replace(private_key);
+        self.with_previous_key(previous_public_key, previous_private_key, Some(expires_at));
+    }
+
+    // restore the previous key pair, if it has not expired at the time
+    pub fn rollback_key(&mut self, now: i64) -> anyhow::Result<()> {
+        match (
+            self.previous_public_key.take(),
+            self.previous_private_key.take(),
+            self.previous_key_expires_at.take(),
+        ) {
+            (Some(public_key), Some(private_key), Some(expires_at)) if now < expires_at => {
+                self.with_public_key(Some(public_key))
+                    .with_private_key(Some(private_key));
+                Ok(())
+            }
+            (Some(_), Some(_), Some(_)) => {
+                anyhow::bail!("the previous key of {} has expired", self.name())
+            }
+            _ => anyhow::bail!("node {} has no previous key", self.name()),
+        }
+    }
+}
+
+impl From<Relay> for Node {
+    fn from(add_peer_relay: Relay) -> Self {
         let mut node = Node::default();
         let key_pair = wg::WireGuardCommand::generate_key_pair(false).unwrap();
+        let (post_up, post_down) = hook::with_presets(
+            add_peer_relay.post_up,
+            add_peer_relay.post_down,
+            &add_peer_relay.presets,
+        );
         node.with_relay(true)
             .with_name(Some(add_peer_relay.name))
             .with_endpoint(Some(Endpoint::new(
                 add_peer_relay.endpoint,
                 add_peer_relay.listen_port,
             )))
-            .with_address(Some(add_peer_relay.address))
             .with_listen_port(Some(add_peer_relay.listen_port))
             .with_mtu(Some(add_peer_relay.mtu))
             .with_public_key(Some(key_pair.public_key().to_string()))
             .with_private_key(Some(key_pair.private_key().to_string()))
-            .with_post_up(add_peer_relay.post_up)
-            .with_post_down(add_peer_relay.post_down)
+            .with_post_up(post_up)
+            .with_post_down(post_down)
             .with_pre_up(add_peer_relay.pre_up)
-            .with_pre_down(add_peer_relay.pre_down);
+            .with_pre_down(add_peer_relay.pre_down)
+            .with_tags(tags(add_peer_relay.tags));
         node
     }
 }
 
+// append a hook template to the user's command
+fn with_hook(command: Option<String>, template: String) -> Option<String> {
+    match command {
+        Some(command) => Some(format!("{}; {}", command, template)),
+        None => Some(template),
+    }
+}
+
+// tags of the command line, none when no tag is given
+pub(crate) fn tags(tags: Vec<String>) -> Option<Vec<String>> {
+    let mut unique = Vec::new();
+    for tag in tags {
+        if !unique.contains(&tag) {
+            unique.push(tag);
+        }
+    }
+    (!unique.is_empty()).then_some(unique)
+}
+
 impl From<AddPeer> for Node {
     fn from(add_peer: AddPeer) -> Self {
         let mut node = Node::default();
         let key_pair = wg::WireGuardCommand::generate_key_pair(false).unwrap();
+        let presets = topology::forwarding_presets(
+            add_peer.forward || add_peer.nat.is_some(),
+            &add_peer.presets,
+        );
+        let (mut post_up, mut post_down) =
+            hook::with_presets(add_peer.post_up, add_peer.post_down, &presets);
+        // the forwarded traffic leaves masqueraded through the LAN interface
+        if let Some(lan) = &add_peer.nat {
+            let (up, down) = hook::masquerade(&format!("-o {}", lan));
+            post_up = with_hook(post_up, up);
+            post_down = with_hook(post_down, down);
+        }
         node.with_relay(false)
             .with_name(Some(add_peer.name))
-            .with_address(Some(add_peer.address))
+            .with_parent(add_peer.relay)
+            // a peer reachable by the others listens on its endpoint's port
+            .with_listen_port(add_peer.endpoint.as_ref().map(Endpoint::port))
+            .with_endpoint(add_peer.endpoint)
+            .with_address(add_peer.address)
             .with_allowed_ips(Some(add_peer.allowed_ips))
-            .with_endpoint_allowed_ips(Some(add_peer.endpoint_allowed_ips))
+            .with_routes(add_peer.routes)
+            .with_endpoint_allowed_ips(add_peer.endpoint_allowed_ips)
             .with_mtu(Some(add_peer.mtu))
             .with_persistent_keepalive(Some(add_peer.persistent_keepalive))
             .with_public_key(Some(key_pair.public_key().to_string()))
             .with_private_key(Some(key_pair.private_key().to_string()))
-            .with_post_up(add_peer.post_up)
-            .with_post_down(add_peer.post_down)
+            .with_post_up(post_up)
+            .with_post_down(post_down)
             .with_pre_up(add_peer.pre_up)
-            .with_pre_down(add_peer.pre_down);
+            .with_pre_down(add_peer.pre_down)
+            .with_tags(tags(add_peer.tags));
+        node
+    }
+}
+
+// the changed settings of the peer, the others are left unset
+impl From<EditPeer> for Node {
+    fn from(edit_peer: EditPeer) -> Self {
+        let mut node = Node::default();
+        node.with_name(Some(edit_peer.name))
+            .with_endpoint(edit_peer.endpoint)
+            .with_address(edit_peer.address)
+            .with_allowed_ips(edit_peer.allowed_ips)
+            .with_routes(edit_peer.routes)
+            .with_endpoint_allowed_ips(edit_peer.endpoint_allowed_ips)
+            .with_mtu(edit_peer.mtu)
+            .with_persistent_keepalive(edit_peer.persistent_keepalive)
+            .with_post_up(edit_peer.post_up)
+            .with_post_down(edit_peer.post_down)
+            .with_pre_up(edit_peer.pre_up)
+            .with_pre_down(edit_peer.pre_down)
+            .with_tags(tags(edit_peer.tags));
+        node
+    }
+}
+
+impl ClearPeer {
+    // unset the settings the flags clear
+    pub fn apply(&self, node: &mut Node) {
+        if self.clear_endpoint {
+            node.with_endpoint(None);
+        }
+        if self.clear_allowed_ips {
+            node.with_allowed_ips(None);
+        }
+        if self.clear_mtu {
+            node.with_mtu(None);
+        }
+        if self.clear_persistent_keepalive {
+            node.with_persistent_keepalive(None);
+        }
+        if self.clear_routes {
+            node.with_routes(None);
+        }
+        if self.clear_endpoint_allowed_ips {
+            node.with_endpoint_allowed_ips(None);
+        }
+        if self.clear_post_up {
+            node.with_post_up(None);
+        }
+        if self.clear_post_down {
+            node.with_post_down(None);
+        }
+        if self.clear_pre_up {
+            node.with_pre_up(None);
+        }
+        if self.clear_pre_down {
+            node.with_pre_down(None);
+        }
+        if self.clear_tags {
+            node.with_tags(None);
+        }
+    }
+}
+
+impl From<node_relay::Model> for Node {
+    fn from(model: node_relay::Model) -> Self {
+        let parse_ips = |s: String| parser::parser_address_in_range(&s).ok();
+        let mut node = Node::default();
+        node.with_relay(model.relay)
+            .with_name(Some(model.name))
+            .with_address(model.address.and_then(parse_ips))
+            .with_public_key(Some(model.public_key))
+            .with_private_key(Some(model.private_key))
+            .with_previous_key(
+                model.previous_public_key,
+                model.previous_private_key,
+                model.previous_key_expires_at,
+            )
+            .with_listen_port(model.listen_port)
+            .with_allowed_ips(model.allowed_ips.and_then(parse_ips))
+            .with_routes(model.routes.and_then(parse_ips))
+            .with_endpoint_allowed_ips(model.endpoint_allowed_ips.and_then(parse_ips))
+            .with_persistent_keepalive(model.persistent_keepalive.and_then(|v| v.parse().ok()))
+            .with_endpoint(model.endpoint.and_then(|v| v.parse().ok()))
+            .with_mtu(model.mtu.and_then(|v| u16::try_from(v).ok()))
+            .with_post_up(model.post_up)
+            .with_post_down(model.post_down)
+            .with_pre_up(model.pre_up)
+            .with_pre_down(model.pre_down)
+            .with_expires_at(model.expires_at)
+            .with_tags(model.tags.map(|tags| {
+                tags.split(',')
+                    .filter(|t| !t.is_empty())
+                    .map(ToString::to_string)
+                    .collect()
+            }));
         node
     }
 }
+
+impl From<Node> for node_relay::ActiveModel {
+    fn from(node: Node) -> Self {
+        let join_ips = |list: Vec<IpNet>| {
+            list.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        node_relay::ActiveModel {
+            relay: ActiveValue::Set(node.relay),
+            name: ActiveValue::Set(node.name.unwrap_or_default()),
+            address: ActiveValue::Set(node.address.map(join_ips)),
+            public_key: ActiveValue::Set(node.public_key.unwrap_or_default()),
+            private_key: ActiveValue::Set(node.private_key.unwrap_or_default()),
+            previous_public_key: ActiveValue::Set(node.previous_public_key),
+            previous_private_key: ActiveValue::Set(node.previous_private_key),
+            previous_key_expires_at: ActiveValue::Set(node.previous_key_expires_at),
+            listen_port: ActiveValue::Set(node.listen_port),
+            allowed_ips: ActiveValue::Set(node.allowed_ips.map(join_ips)),
+            routes: ActiveValue::Set(node.routes.map(join_ips)),
+            endpoint_allowed_ips: ActiveValue::Set(node.endpoint_allowed_ips.map(join_ips)),
+            persistent_keepalive: ActiveValue::Set(
+                node.persistent_keepalive.map(|v| v.to_string()),
+            ),
+            endpoint: ActiveValue::Set(node.endpoint.map(|v| v.to_string())),
+            mtu: ActiveValue::Set(node.mtu.map(u32::from)),
+            pre_up: ActiveValue::Set(node.pre_up),
+            post_up: ActiveValue::Set(node.post_up),
+            pre_down: ActiveValue::Set(node.pre_down),
+            post_down: ActiveValue::Set(node.post_down),
+            expires_at: ActiveValue::Set(node.expires_at),
+            tags: ActiveValue::Set(node.tags.map(|tags| tags.join(","))),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::test_util::node;
+
+    #[test]
+    fn test_rollback_key() {
+        let mut laptop = node("laptop", false, "10.66.66.2/24");
+        let old_key = laptop.public_key.clone();
+        laptop.rotate_key("new-public".to_string(), "new-private".to_string(), 100);
+        assert_eq!(laptop.previous_public_key, old_key);
+        assert_eq!(laptop.public_key.as_deref(), Some("new-public"));
+
+        let mut expired = laptop.clone();
+        assert!(expired.rollback_key(100).is_err());
+        laptop.rollback_key(99).unwrap();
+        assert_eq!(laptop.public_key, old_key);
+        assert!(laptop.previous_public_key.is_none());
+        assert!(laptop.rollback_key(99).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::db::model::network;
+use crate::model::acl::Acl;
+use crate::model::topology::Topology;
+use crate::model::Node;
+use crate::parser;
+use anyhow::Context;
+use ipnet::{IpNet, Ipv6Net};
+use rand_core::RngCore;
+use sea_orm::ActiveValue;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv6Addr};
+
+// wireguard relay network, the nodes of a network share its address range
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Network {
+    // network name
+    pub name: String,
+    // network address range
+    pub cidr: IpNet,
+    // IPv6 address range of a dual-stack network
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cidr_v6: Option<IpNet>,
+    // relay interface name
+    pub interface: String,
+    // relay listen port
+    pub listen_port: Option<u16>,
+    // peer's DNS servers
+    pub dns: Option<Vec<IpAddr>>,
+    // how the peers reach each other
+    #[serde(default)]
+    pub topology: Topology,
+    // access control of the traffic the relay forwards between its leaves
+    #[serde(default, skip_serializing_if = "Acl::is_empty")]
+    pub acl: Acl,
+}
+
+impl Network {
+    pub fn new(name: String, cidr: IpNet, interface: String) -> Self {
+        Self {
+            name,
+            cidr,
+            cidr_v6: None,
+            interface,
+            listen_port: None,
+            dns: None,
+            topology: Topology::default(),
+            acl: Acl::default(),
+        }
+    }
+
+    // address ranges of the network, one per address family
+    pub fn prefixes(&self) -> Vec<IpNet> {
+        std::iter::once(self.cidr).chain(self.cidr_v6).collect()
+    }
+
+    // first host address of each address range that no node uses yet
+    pub fn allocate(&self, node_list: &[Node]) -> anyhow::Result<Vec<IpNet>> {
+        let used = node_list
+            .iter()
+            .flat_map(|n| n.address.iter().flatten())
+            .map(|address| address.addr())
+            .collect::<Vec<IpAddr>>();
+        let mut address_list = Vec::new();
+        for prefix in self.prefixes() {
+            // the subnet-router anycast address of an IPv6 range is not a host
+            let address = prefix
+                .hosts()
+                .filter(|address| address.ne(&prefix.network()))
+                .find(|address| !used.contains(address))
+                .with_context(|| {
+                    format!(
+                        "network {} has no free address left in {}",
+                        self.name, prefix
+                    )
+                })?;
+            address_list.push(IpNet::new(address, prefix.prefix_len())?);
+        }
+        Ok(address_list)
+    }
+}
+
+// random unique local IPv6 /64 (RFC 4193), fdXX:XXXX:XXXX::/64 with a random global ID
+pub fn random_ula() -> IpNet {
+    let mut global_id = [0u8; 5];
+    rand_core::OsRng.fill_bytes(&mut global_id);
+    let mut octets = [0u8; 16];
+    octets[0] = 0xfd;
+    octets[1..6].copy_from_slice(&global_id);
+    IpNet::V6(Ipv6Net::new(Ipv6Addr::from(octets), 64).expect("valid prefix length"))
+}
+
+impl TryFrom<network::Model> for Network {
+    type Error = anyhow::Error;
+
+    fn try_from(model: network::Model) -> Result<Self, Self::Error> {
+        let dns = match model.dns {
+            Some(dns) => Some(parser::parser_dns(&dns)?),
+            None => None,
+        };
+        Ok(Network {
+            name: model.name,
+            cidr: model.cidr.parse()?,
+            cidr_v6: match model.cidr_v6 {
+                Some(cidr_v6) => Some(cidr_v6.parse()?),
+                None => None,
+            },
+            interface: model.interface,
+            listen_port: model.listen_port,
+            dns,
+            topology: model.topology.parse()?,
+            acl: match model.acl {
+                Some(acl) => serde_json::from_str(&acl)?,
+                None => Acl::default(),
+            },
+        })
+    }
+}
+
+impl From<Network> for network::ActiveModel {
+    fn from(network: Network) -> Self {
+        network::ActiveModel {
+            name: ActiveValue::Set(network.name),
+            cidr: ActiveValue::Set(network.cidr.to_string()),
+            cidr_v6: ActiveValue::Set(network.cidr_v6.map(|cidr_v6| cidr_v6.to_string())),
+            interface: ActiveValue::Set(network.interface),
+            listen_port: ActiveValue::Set(network.listen_port),
+            dns: ActiveValue::Set(network.dns.map(|dns| {
+                dns.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(",")
+            })),
+            topology: ActiveValue::Set(network.topology.as_str().to_string()),
+            acl: ActiveValue::Set(
+                (!network.acl.is_empty())
+                    .then(|| serde_json::to_string(&network.acl).expect("serializable acl")),
+            ),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::{home, network, node};
+
+    #[test]
+    fn test_allocate() {
+        let network = network("home", "10.66.66.0/29", "wg0");
+        assert_eq!(
+            network.allocate(&[]).unwrap(),
+            ["10.66.66.1/29".parse().unwrap()]
+        );
+
+        let node_list = vec![
+            node("wg0", true, "10.66.66.1/29"),
+            node("laptop", false, "10.66.66.3/29"),
+        ];
+        assert_eq!(
+            network.allocate(&node_list).unwrap(),
+            ["10.66.66.2/29".parse().unwrap()]
+        );
+
+        let node_list = (1..=6)
+            .map(|i| node(&format!("node{}", i), false, &format!("10.66.66.{}/29", i)))
+            .collect::<Vec<Node>>();
+        assert!(network.allocate(&node_list).is_err());
+    }
+
+    #[test]
+    fn test_allocate_dual_stack() {
+        let mut network = home();
+        network.cidr_v6 = Some("fd00:66:66::/64".parse().unwrap());
+
+        let mut relay = Node::default();
+        relay.with_address(Some(vec![
+            "10.66.66.1/24".parse().unwrap(),
+            "fd00:66:66::1/64".parse().unwrap(),
+        ]));
+        assert_eq!(
+            network.allocate(&[relay]).unwrap(),
+            [
+                "10.66.66.2/24".parse().unwrap(),
+                "fd00:66:66::2/64".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_random_ula() {
+        let ula = random_ula();
+        assert_eq!(ula.prefix_len(), 64);
+        assert!(matches!(ula.addr(), IpAddr::V6(addr) if addr.octets()[0] == 0xfd));
+        assert_eq!(ula, ula.trunc());
+    }
+}
@@ -0,0 +1,35 @@
+use crate::model::network::Network;
+use crate::model::Node;
+
+// network of the address range on the interface
+pub fn network(name: &str, cidr: &str, interface: &str) -> Network {
+    Network::new(
+        name.to_string(),
+        cidr.parse().unwrap(),
+        interface.to_string(),
+    )
+}
+
+// network `home` of 10.66.66.0/24 on the interface wg0
+pub fn home() -> Network {
+    network("home", "10.66.66.0/24", "wg0")
+}
+
+// relay or leaf with a single address and the keys `<name>-public` and `<name>-private`
+pub fn node(name: &str, relay: bool, address: &str) -> Node {
+    let mut node = Node::default();
+    node.with_relay(relay)
+        .with_name(Some(name.to_string()))
+        .with_address(Some(vec![address.parse().unwrap()]))
+        .with_public_key(Some(format!("{}-public", name)))
+        .with_private_key(Some(format!("{}-private", name)));
+    node
+}
+
+// leaf served by the relay, routing the network's 10.66.66.0/24 through it
+pub fn leaf(name: &str, relay: &str, address: &str) -> Node {
+    let mut node = node(name, false, address);
+    node.with_parent(Some(relay.to_string()))
+        .with_endpoint_allowed_ips(Some(vec!["10.66.66.0/24".parse().unwrap()]));
+    node
+}
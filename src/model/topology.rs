@@ -0,0 +1,236 @@
+use crate::model::hook::Preset;
+use crate::model::Node;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+// how the leaves of a network reach each other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Topology {
+    // every leaf only peers with its relay
+    #[default]
+    Hub,
+    // leaves peer directly whenever one of them has a public endpoint
+    Mesh,
+    // leaves peer directly with the leaves of the same relay only
+    Hybrid,
+}
+
+impl Topology {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Topology::Hub => "hub",
+            Topology::Mesh => "mesh",
+            Topology::Hybrid => "hybrid",
+        }
+    }
+}
+
+impl std::str::FromStr for Topology {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hub" => Ok(Topology::Hub),
+            "mesh" => Ok(Topology::Mesh),
+            "hybrid" => Ok(Topology::Hybrid),
+            _ => anyhow::bail!("unknown topology: {}", s),
+        }
+    }
+}
+
+// host routes of the node's own addresses, e.g. 10.66.66.2/32
+fn host_routes(node: &Node) -> impl Iterator<Item = IpNet> + '_ {
+    node.address
+        .iter()
+        .flatten()
+        .map(|address| IpNet::from(address.addr()))
+}
+
+// everything routed to the node: its own addresses and its advertised routes
+pub fn node_routes(node: &Node) -> impl Iterator<Item = IpNet> + '_ {
+    host_routes(node).chain(node.routes.iter().flatten().copied())
+}
+
+// hook presets of a peer, the forward preset comes first and only once when the peer
+// forwards the traffic of its routes, whether by --forward, --nat or --preset
+pub fn forwarding_presets(forward: bool, presets: &[Preset]) -> Vec<Preset> {
+    let mut unique = Vec::new();
+    if forward {
+        unique.push(Preset::Forward);
+    }
+    for preset in presets {
+        if !unique.contains(preset) {
+            unique.push(*preset);
+        }
+    }
+    unique
+}
+
+// relay that serves the leaf, the first relay of the network when none is assigned
+pub fn primary_relay<'a>(node: &Node, node_list: &'a [Node]) -> Option<&'a Node> {
+    let mut relays = node_list.iter().filter(|n| n.relay);
+    node.parent
+        .as_deref()
+        .and_then(|parent| relays.clone().find(|n| n.name().eq(parent)))
+        .or_else(|| relays.next())
+}
+
+// leaves whose primary relay is the relay
+pub fn leaves<'a>(relay: &'a Node, node_list: &'a [Node]) -> impl Iterator<Item = &'a Node> {
+    node_list.iter().filter(move |n| {
+        !n.relay && matches!(primary_relay(n, node_list), Some(r) if r.name().eq(relay.name()))
+    })
+}
+
+// addresses routed through the relay: its own, and those of its leaves and the networks behind them
+pub fn relay_routes(relay: &Node, node_list: &[Node]) -> Vec<IpNet> {
+    let mut routes = node_routes(relay).collect::<Vec<IpNet>>();
+    routes.extend(relay.allowed_ips.iter().flatten());
+    for leaf in leaves(relay, node_list) {
+        routes.extend(node_routes(leaf));
+        routes.extend(leaf.allowed_ips.iter().flatten());
+    }
+    routes
+}
+
+// Leaves the leaf peers with directly instead of through its relay. One of the two
+// needs a public endpoint to dial, the others fall back to the relay.
+pub fn direct_peers<'a>(
+    topology: Topology,
+    node: &'a Node,
+    node_list: &'a [Node],
+) -> impl Iterator<Item = &'a Node> {
+    let relay = primary_relay(node, node_list).map(Node::name);
+    node_list.iter().filter(move |n| {
+        let reachable = n.endpoint.is_some() || node.endpoint.is_some();
+        let in_scope = match topology {
+            Topology::Hub => false,
+            Topology::Mesh => true,
+            Topology::Hybrid => primary_relay(n, node_list).map(Node::name) == relay,
+        };
+        !n.relay && n.name().ne(node.name()) && reachable && in_scope
+    })
+}
+
+// nodes that have not expired at the time, the named node is always kept
+pub fn unexpired(node_list: &[Node], node_name: &str, now: i64) -> Vec<Node> {
+    node_list
+        .iter()
+        .filter(|n| n.name().eq(node_name) || !n.is_expired(now))
+        .cloned()
+        .collect()
+}
+
+// Peers of the relay interface: its own leaves, and the other relays in a full mesh.
+// Another relay routes the addresses of its leaves, so leaves on different relays reach each other.
+pub fn relay_peers(relay: &Node, node_list: &[Node]) -> Vec<Node> {
+    let mut peers = Vec::new();
+    for leaf in leaves(relay, node_list) {
+        // the relay does not dial its leaves
+        let mut leaf = leaf.clone();
+        leaf.with_endpoint(None);
+        peers.push(leaf);
+    }
+    for other in node_list
+        .iter()
+        .filter(|n| n.relay && n.name().ne(relay.name()))
+    {
+        let mut other_relay = other.clone();
+        other_relay.with_allowed_ips(Some(relay_routes(other, node_list)));
+        peers.push(other_relay);
+    }
+    peers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::{leaf, node};
+
+    fn names(node_list: &[Node]) -> Vec<&str> {
+        node_list.iter().map(|n| n.name()).collect()
+    }
+
+    #[test]
+    fn test_relay_mesh() {
+        let mut office = leaf("office", "eu", "10.66.66.4/24");
+        office.with_allowed_ips(Some(vec!["192.168.1.0/24".parse().unwrap()]));
+        let node_list = vec![
+            node("us", true, "10.66.66.1/24"),
+            node("eu", true, "10.66.66.2/24"),
+            // no primary relay, served by the first one
+            node("laptop", false, "10.66.66.3/24"),
+            office,
+            leaf("phone", "eu", "10.66.66.5/24"),
+        ];
+        let (us, eu) = (&node_list[0], &node_list[1]);
+
+        assert_eq!(
+            primary_relay(&node_list[2], &node_list).unwrap().name(),
+            "us"
+        );
+        assert_eq!(
+            primary_relay(&node_list[3], &node_list).unwrap().name(),
+            "eu"
+        );
+
+        let peers = relay_peers(us, &node_list);
+        assert_eq!(names(&peers), ["laptop", "eu"]);
+        assert_eq!(
+            peers[1].allowed_ips,
+            Some(vec![
+                "10.66.66.2/32".parse().unwrap(),
+                "10.66.66.4/32".parse().unwrap(),
+                "192.168.1.0/24".parse().unwrap(),
+                "10.66.66.5/32".parse().unwrap(),
+            ])
+        );
+
+        let peers = relay_peers(eu, &node_list);
+        assert_eq!(names(&peers), ["office", "phone", "us"]);
+        assert_eq!(
+            peers[2].allowed_ips,
+            Some(vec![
+                "10.66.66.1/32".parse().unwrap(),
+                "10.66.66.3/32".parse().unwrap(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_advertised_routes() {
+        let mut office = leaf("office", "eu", "10.66.66.3/24");
+        office.with_routes(Some(vec!["192.168.1.0/24".parse().unwrap()]));
+        let node_list = vec![
+            node("us", true, "10.66.66.1/24"),
+            node("eu", true, "10.66.66.2/24"),
+            office,
+        ];
+
+        // the other relay routes the subnet to the relay of the office
+        let peers = relay_peers(&node_list[0], &node_list);
+        assert_eq!(
+            peers[0].allowed_ips,
+            Some(vec![
+                "10.66.66.2/32".parse().unwrap(),
+                "10.66.66.3/32".parse().unwrap(),
+                "192.168.1.0/24".parse().unwrap(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_forwarding_presets() {
+        assert!(forwarding_presets(false, &[]).is_empty());
+        assert_eq!(forwarding_presets(true, &[]), [Preset::Forward]);
+        assert_eq!(
+            forwarding_presets(true, &[Preset::NatMasquerade, Preset::Forward]),
+            [Preset::Forward, Preset::NatMasquerade]
+        );
+        assert_eq!(
+            forwarding_presets(false, &[Preset::Forward, Preset::Forward]),
+            [Preset::Forward]
+        );
+    }
+}
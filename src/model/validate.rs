@@ -0,0 +1,237 @@
+use std::fmt::{Display, Formatter};
+
+use ipnet::IpNet;
+
+use crate::model::network::Network;
+use crate::model::{hook, topology, Node};
+
+// a conflict in the configuration of a network and the nodes it involves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub nodes: Vec<String>,
+    pub message: String,
+}
+
+impl Conflict {
+    fn new(nodes: &[&Node], message: String) -> Self {
+        Self {
+            nodes: nodes.iter().map(|n| n.name().to_string()).collect(),
+            message,
+        }
+    }
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// networks routed to the node, the host routes of its addresses are flagged
+fn claimed(node: &Node) -> Vec<(IpNet, bool)> {
+    let mut claimed = node
+        .address
+        .iter()
+        .flatten()
+        .map(|address| (IpNet::from(address.addr()), true))
+        .collect::<Vec<(IpNet, bool)>>();
+    claimed.extend(node.allowed_ips.iter().flatten().map(|n| (*n, false)));
+    claimed.extend(node.routes.iter().flatten().map(|n| (*n, false)));
+    claimed
+}
+
+fn overlaps(a: &IpNet, b: &IpNet) -> bool {
+    a.contains(&b.network()) || b.contains(&a.network())
+}
+
+// all conflicts of the node list of the network
+pub fn validate(network: &Network, node_list: &[Node]) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    for (i, node) in node_list.iter().enumerate() {
+        for other in &node_list[i + 1..] {
+            // the same tunnel address
+            for address in node.address.iter().flatten() {
+                if other
+                    .address
+                    .iter()
+                    .flatten()
+                    .any(|a| a.addr().eq(&address.addr()))
+                {
+                    conflicts.push(Conflict::new(
+                        &[node, other],
+                        format!(
+                            "address {} of {} is also used by {}",
+                            address.addr(),
+                            node.name(),
+                            other.name()
+                        ),
+                    ));
+                }
+            }
+            // both nodes claim the traffic of the same addresses, the same
+            // address twice is reported above
+            for (net, host) in claimed(node) {
+                for (other_net, other_host) in claimed(other) {
+                    if !(host && other_host) && overlaps(&net, &other_net) {
+                        conflicts.push(Conflict::new(
+                            &[node, other],
+                            format!(
+                                "allowed ips {} of {} overlap {} of {}",
+                                net,
+                                node.name(),
+                                other_net,
+                                other.name()
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+        // hook templates with a variable the node cannot fill in
+        if let Err(e) = hook::render_hooks(network, &mut node.clone()) {
+            conflicts.push(Conflict::new(&[node], e.to_string()));
+        }
+        if node.relay {
+            continue;
+        }
+        // the address of a peer inside the network of its relay
+        if let Some(relay) = topology::primary_relay(node, node_list) {
+            let relay_networks = relay
+                .address
+                .iter()
+                .flatten()
+                .map(IpNet::trunc)
+                .collect::<Vec<IpNet>>();
+            for address in node.address.iter().flatten() {
+                if !relay_networks.is_empty()
+                    && !relay_networks.iter().any(|n| n.contains(&address.addr()))
+                {
+                    conflicts.push(Conflict::new(
+                        &[node],
+                        format!(
+                            "address {} of {} is outside the network of relay {}",
+                            address.addr(),
+                            node.name(),
+                            relay.name()
+                        ),
+                    ));
+                }
+            }
+        }
+        // the peers reached through the relay have to be covered by the endpoint allowed ips
+        let direct = topology::direct_peers(network.topology, node, node_list)
+            .map(Node::name)
+            .collect::<Vec<&str>>();
+        if let Some(endpoint_allowed_ips) = &node.endpoint_allowed_ips {
+            for other in node_list
+                .iter()
+                .filter(|n| n.name().ne(node.name()) && !direct.contains(&n.name()))
+            {
+                for address in other.address.iter().flatten() {
+                    if !endpoint_allowed_ips
+                        .iter()
+                        .any(|n| n.contains(&address.addr()))
+                    {
+                        conflicts.push(Conflict::new(
+                            &[node],
+                            format!(
+                                "endpoint allowed ips of {} do not cover address {} of {}",
+                                node.name(),
+                                address.addr(),
+                                other.name()
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    conflicts
+}
+
+// fail on the conflicts the node is involved in, run before the node is written
+pub fn check(network: &Network, node_list: &[Node], node_name: &str) -> anyhow::Result<()> {
+    let conflicts = validate(network, node_list)
+        .into_iter()
+        .filter(|c| c.nodes.iter().any(|n| n.eq(node_name)))
+        .map(|c| c.message)
+        .collect::<Vec<String>>();
+    if !conflicts.is_empty() {
+        anyhow::bail!(conflicts.join("\n"))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::{home, leaf, node};
+
+    fn messages(node_list: &[Node]) -> Vec<String> {
+        let network = home();
+        validate(&network, node_list)
+            .into_iter()
+            .map(|c| c.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut node_list = vec![
+            node("us", true, "10.66.66.1/24"),
+            leaf("laptop", "us", "10.66.66.2/24"),
+            leaf("phone", "us", "10.66.66.3/24"),
+        ];
+        assert!(messages(&node_list).is_empty());
+
+        node_list[2].with_address(Some(vec!["10.66.66.2/24".parse().unwrap()]));
+        assert_eq!(
+            messages(&node_list),
+            ["address 10.66.66.2 of laptop is also used by phone"]
+        );
+
+        node_list[2]
+            .with_address(Some(vec!["10.66.66.3/24".parse().unwrap()]))
+            .with_allowed_ips(Some(vec!["10.66.66.0/30".parse().unwrap()]));
+        assert_eq!(
+            messages(&node_list),
+            [
+                "allowed ips 10.66.66.1/32 of us overlap 10.66.66.0/30 of phone",
+                "allowed ips 10.66.66.2/32 of laptop overlap 10.66.66.0/30 of phone",
+            ]
+        );
+
+        node_list[1].with_routes(Some(vec!["192.168.1.0/24".parse().unwrap()]));
+        node_list[2].with_routes(Some(vec!["192.168.0.0/16".parse().unwrap()]));
+        node_list[2].with_allowed_ips(None);
+        assert_eq!(
+            messages(&node_list),
+            ["allowed ips 192.168.1.0/24 of laptop overlap 192.168.0.0/16 of phone"]
+        );
+        node_list[2].with_routes(None);
+
+        node_list[2].with_address(Some(vec!["10.77.77.3/24".parse().unwrap()]));
+        assert_eq!(
+            messages(&node_list),
+            [
+                "endpoint allowed ips of laptop do not cover address 10.77.77.3 of phone",
+                "address 10.77.77.3 of phone is outside the network of relay us",
+            ]
+        );
+
+        // only the conflicts of the pushed node fail the push
+        let network = home();
+        assert!(check(&network, &node_list, "us").is_ok());
+        assert!(check(&network, &node_list, "laptop").is_err());
+        assert!(check(&network, &node_list, "phone").is_err());
+
+        // a hook with a variable the node cannot fill in
+        node_list[2]
+            .with_address(Some(vec!["10.66.66.3/24".parse().unwrap()]))
+            .with_post_up(Some("echo {listen_port}".to_string()));
+        assert_eq!(
+            messages(&node_list),
+            ["hook variable {listen_port} is undefined in the hooks of phone"]
+        );
+    }
+}
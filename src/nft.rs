@@ -0,0 +1,283 @@
+use std::io::Write;
+use std::process::Stdio;
+
+use ipnet::IpNet;
+
+use crate::model::network::Network;
+use crate::standard::NFT_TABLE_PREFIX;
+
+// forwarding and masquerading of a relay, in a table of its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ruleset {
+    // nftables table of the relay interface
+    pub table: String,
+    // relay interface
+    pub interface: String,
+    // network address ranges, masqueraded when leaving the relay
+    pub prefixes: Vec<IpNet>,
+    // interface the traffic leaves through, any other than the relay interface when unset
+    pub egress: Option<String>,
+    // drop the traffic between the peers of the relay, ahead of the access control rules
+    pub isolate: bool,
+    // compiled access control rules, the only traffic of the peers forwarded once set
+    pub acl: Option<Vec<String>>,
+}
+
+// dedicated table of the interface, nft identifiers only take letters, digits and underscores
+pub fn table_name(interface: &str) -> String {
+    let interface = interface
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    format!("{}_{}", NFT_TABLE_PREFIX, interface)
+}
+
+impl Ruleset {
+    pub fn new(network: &Network) -> Self {
+        Self {
+            table: table_name(&network.interface),
+            interface: network.interface.clone(),
+            prefixes: network.prefixes(),
+            egress: None,
+            isolate: false,
+            acl: None,
+        }
+    }
+
+    pub fn with_egress(&mut self, egress: Option<String>) -> &mut Self {
+        self.egress = egress;
+        self
+    }
+
+    pub fn with_isolate(&mut self, isolate: bool) -> &mut Self {
+        self.isolate = isolate;
+        self
+    }
+
+    pub fn with_acl(&mut self, acl: Option<Vec<String>>) -> &mut Self {
+        self.acl = acl;
+        self
+    }
+
+    // the table definition
+    pub fn render(&self) -> String {
+        let interface = format!("\"{}\"", self.interface);
+        // interfaces the traffic of the network leaves through
+        let outside = match &self.egress {
+            Some(egress) => format!("\"{}\"", egress),
+            None => format!("!= {}", interface),
+        };
+        let mut lines = vec![format!("table inet {} {{", self.table)];
+
+        lines.push("\tchain forward {".to_string());
+        lines.push("\t\ttype filter hook forward priority filter; policy accept;".to_string());
+        if let Some(acl) = &self.acl {
+            // replies pass, new connections of the peers only when a rule allows them
+            lines.push(format!(
+                "\t\tiifname {} ct state established,related accept",
+                interface
+            ));
+            lines.push(format!(
+                "\t\toifname {} ct state established,related accept",
+                interface
+            ));
+            if self.isolate {
+                lines.push(format!(
+                    "\t\tiifname {} oifname {} drop",
+                    interface, interface
+                ));
+            }
+            for rule in acl {
+                lines.push(format!("\t\tiifname {} {}", interface, rule));
+            }
+            lines.push(format!("\t\tiifname {} drop", interface));
+        } else {
+            let verdict = if self.isolate { "drop" } else { "accept" };
+            lines.push(format!(
+                "\t\tiifname {} oifname {} {}",
+                interface, interface, verdict
+            ));
+            lines.push(format!(
+                "\t\tiifname {} oifname {} accept",
+                interface, outside
+            ));
+            lines.push(format!(
+                "\t\tiifname {} oifname {} ct state established,related accept",
+                outside, interface
+            ));
+        }
+        lines.push("\t}".to_string());
+
+        lines.push("\tchain postrouting {".to_string());
+        lines.push("\t\ttype nat hook postrouting priority srcnat; policy accept;".to_string());
+        for prefix in &self.prefixes {
+            let family = match prefix {
+                IpNet::V4(_) => "ip",
+                IpNet::V6(_) => "ip6",
+            };
+            lines.push(format!(
+                "\t\t{} saddr {} oifname {} masquerade",
+                family, prefix, outside
+            ));
+        }
+        lines.push("\t}".to_string());
+
+        lines.push("}".to_string());
+        lines.join("\n") + "\n"
+    }
+
+    // replaces the table in a single transaction, adding it first so that the
+    // delete also succeeds when it does not exist yet
+    pub fn apply_script(&self) -> String {
+        format!(
+            "add table inet {table}\ndelete table inet {table}\n{}",
+            self.render(),
+            table = self.table
+        )
+    }
+
+    // whether the ruleset has IPv6 traffic to forward
+    fn has_v6(&self) -> bool {
+        self.prefixes.iter().any(|p| matches!(p, IpNet::V6(_)))
+    }
+}
+
+// removes the table, whether it exists or not
+pub fn remove_script(table: &str) -> String {
+    format!(
+        "add table inet {table}\ndelete table inet {table}\n",
+        table = table
+    )
+}
+
+// enable forwarding and load the ruleset
+pub fn apply(ruleset: &Ruleset) -> anyhow::Result<()> {
+    std::fs::write("/proc/sys/net/ipv4/ip_forward", "1")?;
+    if ruleset.has_v6() {
+        std::fs::write("/proc/sys/net/ipv6/conf/all/forwarding", "1")?;
+    }
+    nft(&ruleset.apply_script())
+}
+
+pub fn remove(table: &str) -> anyhow::Result<()> {
+    nft(&remove_script(table))
+}
+
+// run a script through `nft -f`, which applies it atomically
+fn nft(script: &str) -> anyhow::Result<()> {
+    let mut child = std::process::Command::new("nft")
+        .arg("-f")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("failed to run nft, is nftables installed? {}", e))?;
+    child
+        .stdin
+        .as_mut()
+        .expect("Failed to get stdin for nft")
+        .write_all(script.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "nft failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_util::network;
+
+    #[test]
+    fn test_render() {
+        let ruleset = Ruleset::new(&network("home", "10.66.66.0/24", "wg-home"));
+        assert_eq!(ruleset.table, "wgsdc_wg_home");
+        assert_eq!(
+            ruleset.render(),
+            "table inet wgsdc_wg_home {
+\tchain forward {
+\t\ttype filter hook forward priority filter; policy accept;
+\t\tiifname \"wg-home\" oifname \"wg-home\" accept
+\t\tiifname \"wg-home\" oifname != \"wg-home\" accept
+\t\tiifname != \"wg-home\" oifname \"wg-home\" ct state established,related accept
+\t}
+\tchain postrouting {
+\t\ttype nat hook postrouting priority srcnat; policy accept;
+\t\tip saddr 10.66.66.0/24 oifname != \"wg-home\" masquerade
+\t}
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_render_isolated() {
+        let mut network = network("home", "10.66.66.0/24", "wg-home");
+        network.cidr_v6 = Some("fd00:66:66::/64".parse().unwrap());
+        let mut ruleset = Ruleset::new(&network);
+        ruleset
+            .with_egress(Some("eth0".to_string()))
+            .with_isolate(true);
+        let rendered = ruleset.render();
+        let lines = rendered.lines().map(str::trim).collect::<Vec<&str>>();
+        assert!(lines.contains(&"iifname \"wg-home\" oifname \"wg-home\" drop"));
+        assert!(lines.contains(&"iifname \"wg-home\" oifname \"eth0\" accept"));
+        assert!(lines
+            .contains(&"iifname \"eth0\" oifname \"wg-home\" ct state established,related accept"));
+        assert!(lines.contains(&"ip saddr 10.66.66.0/24 oifname \"eth0\" masquerade"));
+        assert!(lines.contains(&"ip6 saddr fd00:66:66::/64 oifname \"eth0\" masquerade"));
+        assert!(ruleset.has_v6());
+    }
+
+    #[test]
+    fn test_render_acl() {
+        let mut ruleset = Ruleset::new(&network("home", "10.66.66.0/24", "wg-home"));
+        ruleset.with_acl(Some(vec![
+            "ip saddr { 10.66.66.2/32 } ip daddr { 10.66.66.4/32 } tcp dport 5432 accept"
+                .to_string(),
+        ]));
+        let rendered = ruleset.render();
+        let lines = rendered.lines().map(str::trim).collect::<Vec<&str>>();
+        assert_eq!(
+            lines[3..7],
+            [
+                "iifname \"wg-home\" ct state established,related accept",
+                "oifname \"wg-home\" ct state established,related accept",
+                "iifname \"wg-home\" ip saddr { 10.66.66.2/32 } ip daddr { 10.66.66.4/32 } tcp dport 5432 accept",
+                "iifname \"wg-home\" drop",
+            ]
+        );
+        assert_eq!(lines[7], "}");
+
+        // isolation drops the traffic between the peers before any rule allows it
+        ruleset.with_isolate(true);
+        let rendered = ruleset.render();
+        let lines = rendered.lines().map(str::trim).collect::<Vec<&str>>();
+        assert_eq!(
+            lines[5..8],
+            [
+                "iifname \"wg-home\" oifname \"wg-home\" drop",
+                "iifname \"wg-home\" ip saddr { 10.66.66.2/32 } ip daddr { 10.66.66.4/32 } tcp dport 5432 accept",
+                "iifname \"wg-home\" drop",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scripts() {
+        let ruleset = Ruleset::new(&network("home", "10.66.66.0/24", "wg-home"));
+        let script = ruleset.apply_script();
+        assert!(script.starts_with(
+            "add table inet wgsdc_wg_home\ndelete table inet wgsdc_wg_home\ntable inet wgsdc_wg_home {\n"
+        ));
+        assert_eq!(
+            remove_script(&ruleset.table),
+            "add table inet wgsdc_wg_home\ndelete table inet wgsdc_wg_home\n"
+        );
+    }
+}
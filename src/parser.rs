@@ -58,9 +58,100 @@ pub(crate) fn parser_address_in_range(s: &str) -> anyhow::Result<Vec<IpNet>> {
     Ok(res)
 }
 
+// interface address list parser, at most one address per address family
+pub(crate) fn parser_interface_address(s: &str) -> anyhow::Result<Vec<IpNet>> {
+    let res = parser_address_in_range(s)?;
+    let ipv4 = res.iter().filter(|v| matches!(v, IpNet::V4(_))).count();
+    if ipv4 > 1 || res.len() - ipv4 > 1 {
+        anyhow::bail!("`{}` has more than one address of the same family", s)
+    }
+    Ok(res)
+}
+
+// dns servers parser
+pub(crate) fn parser_dns(s: &str) -> anyhow::Result<Vec<std::net::IpAddr>> {
+    let mut res = Vec::new();
+    for value in s.split(',').map(|v| v.trim()) {
+        res.push(parser_address(value)?)
+    }
+    Ok(res)
+}
+
 pub(crate) fn parser_mtu(s: &str) -> anyhow::Result<u16> {
     let mtu = s
         .parse::<u16>()
         .map_err(|_| anyhow!(format!("`{}` isn't a mtu number", s)))?;
     Ok(mtu)
 }
+
+// tag parser, letters, digits, dashes and underscores
+pub(crate) fn parser_tag(s: &str) -> anyhow::Result<String> {
+    if s.is_empty()
+        || !s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!(
+            "`{}` isn't a tag of letters, digits, dashes and underscores",
+            s
+        )
+    }
+    Ok(s.to_string())
+}
+
+// duration parser, a number with a unit of s, m, h, d or w, e.g. 7d
+pub(crate) fn parser_duration(s: &str) -> anyhow::Result<std::time::Duration> {
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        Some('w') => 604800,
+        _ => anyhow::bail!("`{}` has no unit of s, m, h, d or w", s),
+    };
+    let value = s[..s.len() - 1]
+        .parse::<u64>()
+        .map_err(|_| anyhow!(format!("`{}` isn't a duration", s)))?;
+    let secs = value
+        .checked_mul(unit)
+        .ok_or_else(|| anyhow!(format!("`{}` is too long a duration", s)))?;
+    Ok(std::time::Duration::from_secs(secs))
+}
+
+// date time parser, RFC 3339 or a local date and time, to a unix timestamp
+pub(crate) fn parser_date_time(s: &str) -> anyhow::Result<i64> {
+    use chrono::TimeZone;
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(time.timestamp());
+    }
+    let time = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| {
+            chrono::NaiveDateTime::parse_from_str(&format!("{} 00:00:00", s), "%Y-%m-%d %H:%M:%S")
+        })
+        .map_err(|_| {
+            anyhow!(format!(
+                "`{}` isn't a date time like 2024-01-31 18:00:00",
+                s
+            ))
+        })?;
+    match chrono::Local.from_local_datetime(&time) {
+        chrono::LocalResult::Single(time) => Ok(time.timestamp()),
+        _ => anyhow::bail!("`{}` isn't a valid local time", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parser_duration() {
+        assert_eq!(parser_duration("90s").unwrap().as_secs(), 90);
+        assert_eq!(parser_duration("7d").unwrap().as_secs(), 604800);
+        assert!(parser_duration("7").is_err());
+        assert!(parser_duration("d").is_err());
+        assert!(parser_duration("-1d").is_err());
+        // the seconds would overflow
+        assert!(parser_duration(&format!("{}w", u64::MAX / 604800 + 1)).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use wireguard_uapi::{
+    Backend, Device, DeviceUpdate, InterfaceName, Key, PeerConfigBuilder, PeerInfo,
+};
+
+use crate::clock::Clock;
+use crate::conf::NodeOpt;
+use crate::model::endpoint::Endpoint;
+use crate::model::Node;
+use crate::standard::STALE_HANDSHAKE_SECS;
+
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    // resolve a peer endpoint to a socket address
+    async fn resolve(&self, endpoint: &Endpoint) -> anyhow::Result<SocketAddr>;
+}
+
+// system resolver
+pub struct DnsResolver;
+
+#[async_trait]
+impl Resolver for DnsResolver {
+    async fn resolve(&self, endpoint: &Endpoint) -> anyhow::Result<SocketAddr> {
+        tokio::net::lookup_host((endpoint.address(), endpoint.port()))
+            .await?
+            .next()
+            .with_context(|| format!("'{}' did not resolve", endpoint.to_string()))
+    }
+}
+
+// a peer is stale when it never completed a handshake, or not within the threshold
+fn is_stale(last_handshake: Option<SystemTime>, now: SystemTime) -> bool {
+    match last_handshake {
+        Some(time) if time > SystemTime::UNIX_EPOCH => now
+            .duration_since(time)
+            .map(|elapsed| elapsed.as_secs() > STALE_HANDSHAKE_SECS)
+            .unwrap_or(false),
+        _ => true,
+    }
+}
+
+// Re-resolve the hostname endpoints of stale peers, like reresolve-dns.sh.
+// Only peers whose resolved address differs from the device get an update.
+pub async fn reresolve(
+    resolver: &dyn Resolver,
+    peers: &[PeerInfo],
+    node_list: &[Node],
+    now: SystemTime,
+) -> DeviceUpdate {
+    let mut update = DeviceUpdate::new();
+    for node in node_list {
+        let endpoint = match node.endpoint {
+            Some(ref endpoint) if endpoint.is_hostname() => endpoint,
+            _ => continue,
+        };
+        let key = match node.public_key.as_deref().map(Key::from_base64) {
+            Some(Ok(key)) => key,
+            _ => continue,
+        };
+        let peer = match peers.iter().find(|p| p.config.public_key == key) {
+            Some(peer) => peer,
+            None => continue,
+        };
+        if !is_stale(peer.stats.last_handshake_time, now) {
+            continue;
+        }
+        match resolver.resolve(endpoint).await {
+            Ok(address) if peer.config.endpoint != Some(address) => {
+                log::info!(
+                    "peer {} endpoint {} now resolves to {}",
+                    node.name(),
+                    endpoint.to_string(),
+                    address
+                );
+                update = update.add_peer(PeerConfigBuilder::new(&key).set_endpoint(address));
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("failed to resolve peer {}: {}", node.name(), e),
+        }
+    }
+    update
+}
+
+// Periodically re-resolve the peers of the relay interface until the storage fails, a
+// failed round is logged and retried on the next tick
+pub async fn run(
+    store: &mut dyn NodeOpt,
+    resolver: &dyn Resolver,
+    clock: &dyn Clock,
+    interval: Duration,
+    backend: Backend,
+) -> anyhow::Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let interface = store.network().await?.interface.parse::<InterfaceName>()?;
+        let node_list = store.list().await?;
+        let device = match Device::get(&interface, backend) {
+            Ok(device) => device,
+            Err(e) => {
+                log::warn!("interface {} is not available: {}", interface, e);
+                continue;
+            }
+        };
+        let update = reresolve(resolver, &device.peers, &node_list, clock.system_time()).await;
+        if !update.is_empty() {
+            if let Err(e) = update.apply(&interface, backend) {
+                log::warn!("failed to update the endpoints of {}: {}", interface, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use wireguard_uapi::{KeyPair, PeerStats};
+
+    struct FakeResolver(HashMap<String, SocketAddr>);
+
+    #[async_trait]
+    impl Resolver for FakeResolver {
+        async fn resolve(&self, endpoint: &Endpoint) -> anyhow::Result<SocketAddr> {
+            self.0
+                .get(endpoint.address())
+                .copied()
+                .context("unknown host")
+        }
+    }
+
+    fn node(name: &str, key: &Key, endpoint: &str) -> Node {
+        let mut node = Node::default();
+        node.with_name(Some(name.to_string()))
+            .with_public_key(Some(key.to_base64()))
+            .with_endpoint(Some(endpoint.parse().unwrap()));
+        node
+    }
+
+    #[test]
+    fn test_stale_handshake() {
+        let now = SystemTime::now();
+        assert!(is_stale(None, now));
+        assert!(is_stale(Some(SystemTime::UNIX_EPOCH), now));
+        assert!(is_stale(Some(now - Duration::from_secs(300)), now));
+        assert!(!is_stale(Some(now - Duration::from_secs(10)), now));
+    }
+
+    #[tokio::test]
+    async fn test_reresolve_stale_hostname_peers() {
+        let now = SystemTime::now();
+        let home = KeyPair::generate().public;
+        let office = KeyPair::generate().public;
+        let laptop = KeyPair::generate().public;
+        let resolver = FakeResolver(HashMap::from([(
+            "home.example.com".to_string(),
+            "198.51.100.7:51820".parse().unwrap(),
+        )]));
+
+        let peer = |key: &Key, endpoint: &str, handshake: Option<SystemTime>| PeerInfo {
+            config: PeerConfigBuilder::new(key)
+                .set_endpoint(endpoint.parse().unwrap())
+                .into_peer_config(),
+            stats: PeerStats {
+                last_handshake_time: handshake,
+                ..Default::default()
+            },
+        };
+        let peers = vec![
+            peer(
+                &home,
+                "203.0.113.9:51820",
+                Some(now - Duration::from_secs(600)),
+            ),
+            peer(&office, "192.0.2.1:51820", None),
+            peer(&laptop, "203.0.113.10:51820", Some(now)),
+        ];
+        let node_list = vec![
+            node("home", &home, "home.example.com:51820"),
+            // ip literals are never re-resolved
+            node("office", &office, "192.0.2.1:51820"),
+            // fresh handshake, left alone
+            node("laptop", &laptop, "home.example.com:51820"),
+        ];
+
+        let update = reresolve(&resolver, &peers, &node_list, now).await;
+        let expected = DeviceUpdate::new().add_peer(
+            PeerConfigBuilder::new(&home).set_endpoint("198.51.100.7:51820".parse().unwrap()),
+        );
+        assert_eq!(update, expected);
+
+        // already up to date
+        let peers = vec![peer(&home, "198.51.100.7:51820", None)];
+        let update = reresolve(&resolver, &peers, &node_list, now).await;
+        assert!(update.is_empty());
+    }
+}
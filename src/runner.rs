@@ -0,0 +1,244 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::model::Node;
+
+// stage of bringing an interface up or down a node's hooks run at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    PreUp,
+    PostUp,
+    PreDown,
+    PostDown,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::PreUp => "PreUp",
+            Phase::PostUp => "PostUp",
+            Phase::PreDown => "PreDown",
+            Phase::PostDown => "PostDown",
+        }
+    }
+}
+
+// hooks of the phase, one per line like the repeated keys of a wg-quick configuration
+pub fn hooks(node: &Node, phase: Phase) -> Vec<&str> {
+    let hook = match phase {
+        Phase::PreUp => &node.pre_up,
+        Phase::PostUp => &node.post_up,
+        Phase::PreDown => &node.pre_down,
+        Phase::PostDown => &node.post_down,
+    };
+    hook.iter()
+        .flat_map(|hook| hook.lines())
+        .map(str::trim)
+        .filter(|hook| !hook.is_empty())
+        .collect()
+}
+
+// runs the rendered hooks of a node through bash like wg-quick, `%i` is the interface
+pub struct Runner {
+    interface: String,
+    timeout: Duration,
+}
+
+impl Runner {
+    pub fn new(interface: String, timeout: Duration) -> Self {
+        Self { interface, timeout }
+    }
+
+    // run the hooks of the phase in order, the first failure stops the phase
+    pub async fn run(&self, node: &Node, phase: Phase) -> anyhow::Result<()> {
+        for hook in hooks(node, phase) {
+            self.run_hook(phase, hook).await?;
+        }
+        Ok(())
+    }
+
+    // run every hook of the phase, failures are only logged
+    async fn run_all(&self, node: &Node, phase: Phase) {
+        for hook in hooks(node, phase) {
+            if let Err(e) = self.run_hook(phase, hook).await {
+                log::error!("{:#}", e);
+            }
+        }
+    }
+
+    async fn run_hook(&self, phase: Phase, hook: &str) -> anyhow::Result<()> {
+        let command = hook.replace("%i", &self.interface);
+        log::info!("[{}] {}", phase.as_str(), command);
+        let child = tokio::process::Command::new("bash")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // a hook that timed out is killed
+            .kill_on_drop(true)
+            .spawn()?;
+        let output = match tokio::time::timeout(self.timeout, child.wait_with_output()).await {
+            Ok(output) => output?,
+            Err(_) => anyhow::bail!(
+                "{} hook `{}` timed out after {}s",
+                phase.as_str(),
+                command,
+                self.timeout.as_secs_f32()
+            ),
+        };
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            log::info!("[{}] {}", phase.as_str(), line);
+        }
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            log::warn!("[{}] {}", phase.as_str(), line);
+        }
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} hook `{}` failed with {}",
+                phase.as_str(),
+                command,
+                output.status
+            )
+        }
+        Ok(())
+    }
+
+    // PreUp, create the interface, PostUp; a failure rolls back what was done so far the
+    // way down would: the interface is deleted and the remaining down hooks run
+    pub async fn up(
+        &self,
+        node: &Node,
+        create: impl FnOnce() -> anyhow::Result<()>,
+        delete: impl FnOnce() -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if let Err(e) = self.run(node, Phase::PreUp).await {
+            log::warn!("rolling back interface {}", self.interface);
+            self.run_all(node, Phase::PostDown).await;
+            return Err(e);
+        }
+        if let Err(e) = create() {
+            log::warn!("rolling back interface {}", self.interface);
+            self.run_all(node, Phase::PostDown).await;
+            return Err(e);
+        }
+        if let Err(e) = self.run(node, Phase::PostUp).await {
+            log::warn!("rolling back interface {}", self.interface);
+            self.run_all(node, Phase::PreDown).await;
+            if let Err(e) = delete() {
+                log::error!("{:#}", e);
+            }
+            self.run_all(node, Phase::PostDown).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // PreDown, delete the interface, PostDown; a failing PreDown keeps the interface up
+    pub async fn down(
+        &self,
+        node: &Node,
+        delete: impl FnOnce() -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.run(node, Phase::PreDown).await?;
+        delete()?;
+        self.run(node, Phase::PostDown).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // a node whose hooks append to the log file
+    fn node(log: &Path, pre_up: &str, post_up: &str) -> Node {
+        let hook = |name: &str| format!("echo {} %i >> {}", name, log.display());
+        let mut node = Node::default();
+        node.with_name(Some("wg0".to_string()))
+            .with_pre_up(Some(format!("{}\n{}", hook("pre-up"), pre_up)))
+            .with_post_up(Some(format!("{}\n{}", hook("post-up"), post_up)))
+            .with_pre_down(Some(hook("pre-down")))
+            .with_post_down(Some(hook("post-down")));
+        node
+    }
+
+    fn lines(log: &Path) -> Vec<String> {
+        std::fs::read_to_string(log)
+            .unwrap_or_default()
+            .lines()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut node = Node::default();
+        node.with_post_up(Some("echo a; echo b\n\n  echo c  ".to_string()));
+        assert_eq!(hooks(&node, Phase::PostUp), ["echo a; echo b", "echo c"]);
+        assert!(hooks(&node, Phase::PreUp).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_up_and_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let runner = Runner::new("wg0".to_string(), Duration::from_secs(5));
+        let node = node(&log, "true", "echo output; echo error >&2");
+        runner.up(&node, || Ok(()), || Ok(())).await.unwrap();
+        runner.down(&node, || Ok(())).await.unwrap();
+        assert_eq!(
+            lines(&log),
+            ["pre-up wg0", "post-up wg0", "pre-down wg0", "post-down wg0"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let runner = Runner::new("wg0".to_string(), Duration::from_secs(5));
+
+        // a failing PreUp never creates the interface
+        let failing = node(&log, "exit 3", "true");
+        let err = runner
+            .up(&failing, || panic!("created"), || Ok(()))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("PreUp hook `exit 3` failed"));
+        assert_eq!(lines(&log), ["pre-up wg0", "post-down wg0"]);
+
+        // a failing PostUp deletes the interface again
+        std::fs::remove_file(&log).unwrap();
+        let failing = node(&log, "true", "false");
+        let mut deleted = false;
+        assert!(runner
+            .up(
+                &failing,
+                || Ok(()),
+                || {
+                    deleted = true;
+                    Ok(())
+                }
+            )
+            .await
+            .is_err());
+        assert!(deleted);
+        assert_eq!(
+            lines(&log),
+            ["pre-up wg0", "post-up wg0", "pre-down wg0", "post-down wg0"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let runner = Runner::new("wg0".to_string(), Duration::from_millis(200));
+        let mut node = Node::default();
+        node.with_pre_down(Some("sleep 5".to_string()));
+        let err = runner.down(&node, || panic!("deleted")).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "PreDown hook `sleep 5` timed out after 0.2s"
+        );
+    }
+}
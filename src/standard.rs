@@ -1,3 +1,5 @@
+pub const DEFAULT_NETWORK: &str = "default";
+
 pub const DEFAULT_INTERFACE_ADDRESS: &str = "10.66.66.1/24";
 
 pub const DEFAULT_INTERFACE_LISTEN_PORT: &str = "51820";
@@ -6,4 +8,43 @@ pub const DEFAULT_MTU: &str = "1420";
 
 pub const DEFAULT_PEER_PERSISTENT_KEEPALIVE: &str = "21";
 
-pub const DEFAULT_PEER_ENDPOINT_ALLOWED_IPS: &str = "10.66.66.0/24";
+pub const DEFAULT_RERESOLVE_INTERVAL: &str = "30";
+
+pub const DEFAULT_SYNC_INTERVAL: &str = "5";
+
+// same threshold as wireguard-tools' reresolve-dns.sh
+pub const STALE_HANDSHAKE_SECS: u64 = 135;
+
+pub const DEFAULT_API_LISTEN: &str = "127.0.0.1:8080";
+
+// seconds a PreUp/PostUp/PreDown/PostDown hook may run before it is killed
+pub const DEFAULT_HOOK_TIMEOUT: &str = "30";
+
+// answer of an edit-peer prompt that clears the setting
+pub const CLEAR_ANSWER: &str = "-";
+
+// hours the previous key of a rotated node can be rolled back to
+pub const DEFAULT_KEY_GRACE_PERIOD: &str = "24";
+
+// PostUp/PostDown templates of the hook presets, rendered for each node; `{out}` of the
+// masquerade is replaced by the match of the interfaces the traffic leaves through
+pub const PRESET_NAT_POST_UP: &str =
+    "iptables -t nat -A POSTROUTING -s {subnet} {out} -j MASQUERADE";
+
+pub const PRESET_NAT_POST_DOWN: &str =
+    "iptables -t nat -D POSTROUTING -s {subnet} {out} -j MASQUERADE";
+
+pub const PRESET_FORWARD_POST_UP: &str = "sysctl -w net.ipv4.ip_forward=1; iptables -A FORWARD -i {interface} -j ACCEPT; iptables -A FORWARD -o {interface} -j ACCEPT";
+
+pub const PRESET_FORWARD_POST_DOWN: &str =
+    "iptables -D FORWARD -i {interface} -j ACCEPT; iptables -D FORWARD -o {interface} -j ACCEPT";
+
+// prefix of the nftables table of a relay interface
+pub const NFT_TABLE_PREFIX: &str = "wgsdc";
+
+// environment variables of the store key
+pub const SESSION_ENV: &str = "WGSDC_SESSION";
+pub const PASSPHRASE_ENV: &str = "WGSDC_PASSPHRASE";
+
+// environment variable of the passphrase of an encrypted backup
+pub const BACKUP_PASSPHRASE_ENV: &str = "WGSDC_BACKUP_PASSPHRASE";
@@ -1,3 +1,4 @@
+use ipnet::IpNet;
 use libc::c_char;
 
 use crate::{backends, key::Key, Backend, KeyPair, PeerConfigBuilder};
@@ -7,6 +8,7 @@ use colored::Colorize;
 
 use std::{
     borrow::Cow,
+    collections::HashSet,
     ffi::CStr,
     fmt, io,
     net::{IpAddr, SocketAddr},
@@ -15,7 +17,7 @@ use std::{
 };
 
 /// Represents an IP address a peer is allowed to have, in CIDR notation.
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Clone, Hash)]
 pub struct AllowedIp {
     /// The IP address.
     pub address: IpAddr,
@@ -27,6 +29,14 @@ impl AllowedIp {
     pub fn new(address: IpAddr, cidr: u8) -> Self {
         Self { address, cidr }
     }
+
+    /// The same network with the host bits cleared, as the kernel reports it back.
+    pub fn canonical(&self) -> Self {
+        match IpNet::new(self.address, self.cidr) {
+            Ok(network) => Self::new(network.network(), self.cidr),
+            Err(_) => self.clone(),
+        }
+    }
 }
 
 impl fmt::Debug for AllowedIp {
@@ -594,6 +604,129 @@ impl DeviceUpdate {
         self.add_peer(peer)
     }
 
+    /// Returns `true` if applying this update would not change anything on the interface.
+    pub fn is_empty(&self) -> bool {
+        self.public_key.is_none()
+            && self.private_key.is_none()
+            && self.fwmark.is_none()
+            && self.listen_port.is_none()
+            && self.peers.is_empty()
+            && !self.replace_peers
+    }
+
+    /// Computes the minimal update that brings `current` to the state described by `self`.
+    ///
+    /// `self` is treated as the complete desired state of the interface: peers present on
+    /// the device but missing from `self` are removed, missing peers are added, and peers
+    /// present on both sides only receive the attributes that actually differ. Allowed IPs
+    /// are replaced (with [`replace_allowed_ips`](PeerConfigBuilder::replace_allowed_ips))
+    /// only when the set differs, so established sessions of unchanged peers are kept.
+    ///
+    /// A desired peer without an endpoint keeps whatever endpoint the device has learned
+    /// by roaming. A missing keepalive interval or preshared key means "disabled".
+    /// Interface-wide settings are only patched when they are set in `self` and differ.
+    #[must_use]
+    pub fn diff(&self, current: &Device) -> DeviceUpdate {
+        let mut update = DeviceUpdate::new();
+
+        if let Some(ref private_key) = self.private_key {
+            if current.private_key.as_ref() != Some(private_key) {
+                update = update.set_keypair(KeyPair::from_private(private_key.clone()));
+            }
+        }
+        if let Some(listen_port) = self.listen_port {
+            if current.listen_port != Some(listen_port) {
+                update = update.set_listen_port(listen_port);
+            }
+        }
+        if let Some(fwmark) = self.fwmark {
+            if current.fwmark.unwrap_or(0) != fwmark {
+                update = update.set_fwmark(fwmark);
+            }
+        }
+
+        let desired = self
+            .peers
+            .iter()
+            .filter(|peer| !peer.remove_me)
+            .collect::<Vec<_>>();
+
+        for peer in &current.peers {
+            if !desired
+                .iter()
+                .any(|p| p.public_key == peer.config.public_key)
+            {
+                update = update.remove_peer_by_key(&peer.config.public_key);
+            }
+        }
+
+        for peer in desired {
+            let existing = current
+                .peers
+                .iter()
+                .find(|p| p.config.public_key == peer.public_key);
+            match existing {
+                None => {
+                    let mut added = peer.clone();
+                    added.replace_allowed_ips = true;
+                    update = update.add_peer(added);
+                }
+                Some(existing) => {
+                    if let Some(patch) = Self::diff_peer(peer, &existing.config) {
+                        update = update.add_peer(patch);
+                    }
+                }
+            }
+        }
+
+        update
+    }
+
+    // Only the changed attributes of a peer, or `None` if it is already up to date.
+    fn diff_peer(desired: &PeerConfigBuilder, current: &PeerConfig) -> Option<PeerConfigBuilder> {
+        let mut patch = PeerConfigBuilder::new(&desired.public_key);
+        let mut changed = false;
+
+        if let Some(endpoint) = desired.endpoint {
+            if current.endpoint != Some(endpoint) {
+                patch = patch.set_endpoint(endpoint);
+                changed = true;
+            }
+        }
+
+        let keepalive = desired.persistent_keepalive_interval.unwrap_or(0);
+        if current.persistent_keepalive_interval.unwrap_or(0) != keepalive {
+            patch = patch.set_persistent_keepalive_interval(keepalive);
+            changed = true;
+        }
+
+        let zero = Key::zero();
+        let preshared_key = desired.preshared_key.as_ref().unwrap_or(&zero);
+        if current.preshared_key.as_ref().unwrap_or(&zero) != preshared_key {
+            patch = patch.set_preshared_key(preshared_key.clone());
+            changed = true;
+        }
+
+        let desired_ips = desired
+            .allowed_ips
+            .iter()
+            .map(AllowedIp::canonical)
+            .collect::<HashSet<_>>();
+        let current_ips = current
+            .allowed_ips
+            .iter()
+            .map(AllowedIp::canonical)
+            .collect::<HashSet<_>>();
+        if desired_ips != current_ips {
+            patch = patch
+                .replace_allowed_ips()
+                .add_allowed_ips(&desired.allowed_ips);
+            changed = true;
+        }
+
+        changed.then_some(patch)
+    }
+
     /// Build and apply the configuration to a WireGuard interface by name.
     ///
     /// An interface with the provided name will be created if one does not exist already.
@@ -611,3 +744,126 @@ impl Default for DeviceUpdate {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(peers: Vec<PeerConfigBuilder>) -> Device {
+        Device {
+            name: "wg0".parse().unwrap(),
+            public_key: None,
+            private_key: None,
+            fwmark: None,
+            listen_port: Some(51820),
+            peers: peers
+                .into_iter()
+                .map(|peer| PeerInfo {
+                    config: peer.into_peer_config(),
+                    stats: PeerStats::default(),
+                })
+                .collect(),
+            linked_name: None,
+            backend: Backend::Userspace,
+            __cant_construct_me: (),
+        }
+    }
+
+    fn peer(key: &Key, ip: &str) -> PeerConfigBuilder {
+        PeerConfigBuilder::new(key).add_allowed_ip(ip.parse().unwrap(), 32)
+    }
+
+    #[test]
+    fn test_diff_unchanged_is_empty() {
+        let key = KeyPair::generate().public;
+        let current = device(vec![
+            peer(&key, "10.66.66.2").set_persistent_keepalive_interval(21)
+        ]);
+        let desired = DeviceUpdate::new()
+            .set_listen_port(51820)
+            .add_peer(peer(&key, "10.66.66.2").set_persistent_keepalive_interval(21));
+
+        assert!(desired.diff(&current).is_empty());
+    }
+
+    #[test]
+    fn test_diff_add_and_remove() {
+        let kept = KeyPair::generate().public;
+        let extra = KeyPair::generate().public;
+        let missing = KeyPair::generate().public;
+        let current = device(vec![peer(&kept, "10.66.66.2"), peer(&extra, "10.66.66.3")]);
+        let desired = DeviceUpdate::new()
+            .add_peer(peer(&kept, "10.66.66.2"))
+            .add_peer(peer(&missing, "10.66.66.4"));
+
+        let update = desired.diff(&current);
+        assert!(!update.replace_peers);
+        assert_eq!(update.peers.len(), 2);
+        assert!(update
+            .peers
+            .iter()
+            .any(|p| p.public_key == extra && p.remove_me));
+        assert!(update
+            .peers
+            .iter()
+            .any(|p| p.public_key == missing && !p.remove_me && p.replace_allowed_ips));
+    }
+
+    #[test]
+    fn test_diff_patches_only_changed_attributes() {
+        let key = KeyPair::generate().public;
+        let endpoint = "192.0.2.1:51820".parse().unwrap();
+        let current = device(vec![peer(&key, "10.66.66.2").set_endpoint(endpoint)]);
+        let desired = DeviceUpdate::new()
+            .add_peer(peer(&key, "10.66.66.2").set_persistent_keepalive_interval(25));
+
+        let update = desired.diff(&current);
+        assert_eq!(update.peers.len(), 1);
+        let patch = &update.peers[0];
+        assert_eq!(patch.persistent_keepalive_interval, Some(25));
+        // learned endpoint is left alone, allowed ips are untouched
+        assert_eq!(patch.endpoint, None);
+        assert!(!patch.replace_allowed_ips);
+        assert!(patch.allowed_ips.is_empty());
+        assert!(!patch.remove_me);
+    }
+
+    #[test]
+    fn test_diff_replaces_changed_allowed_ips() {
+        let key = KeyPair::generate().public;
+        let current = device(vec![PeerConfigBuilder::new(&key)
+            .add_allowed_ip("10.66.66.2".parse().unwrap(), 32)
+            .add_allowed_ip("192.168.1.0".parse().unwrap(), 24)]);
+
+        // host bits are ignored when comparing
+        let same = DeviceUpdate::new().add_peer(
+            PeerConfigBuilder::new(&key)
+                .add_allowed_ip("192.168.1.1".parse().unwrap(), 24)
+                .add_allowed_ip("10.66.66.2".parse().unwrap(), 32),
+        );
+        assert!(same.diff(&current).is_empty());
+
+        let changed = DeviceUpdate::new().add_peer(peer(&key, "10.66.66.2"));
+        let update = changed.diff(&current);
+        assert_eq!(update.peers.len(), 1);
+        assert!(update.peers[0].replace_allowed_ips);
+        assert_eq!(
+            update.peers[0].allowed_ips,
+            vec![AllowedIp::new("10.66.66.2".parse().unwrap(), 32)]
+        );
+    }
+
+    #[test]
+    fn test_diff_interface_settings() {
+        let keypair = KeyPair::generate();
+        let current = device(vec![]);
+        let update = DeviceUpdate::new()
+            .set_listen_port(51821)
+            .set_private_key(keypair.private.clone())
+            .diff(&current);
+
+        assert_eq!(update.listen_port, Some(51821));
+        assert_eq!(update.private_key, Some(keypair.private));
+        assert_eq!(update.public_key, Some(keypair.public));
+    }
+}
@@ -66,6 +66,11 @@ impl WgQuick {
         self
     }
 
+    pub fn set_mtu(mut self, mtu: u32) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
     pub fn randomize_listen_port(self) -> Self {
         self.set_listen_port(0)
     }